@@ -0,0 +1,524 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+
+use crate::error::GpuError;
+use crate::gpu::Gpu;
+
+// how often the gpus are checked while a step is being validated
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// tell the validation command which offsets it is testing
+const CORE_OFFSET_ENV: &str = "NVIDIA_TWEAKER_CORE_OFFSET";
+const MEM_OFFSET_ENV: &str = "NVIDIA_TWEAKER_MEM_OFFSET";
+
+// exit codes of sh for a command that could not be found or run, which
+// says nothing about the offsets
+const COMMAND_NOT_EXECUTABLE: i32 = 126;
+const COMMAND_NOT_FOUND: i32 = 127;
+
+// kernel log marker of the driver reporting an Xid error
+const XID_MARKER: &str = "NVRM: Xid";
+
+// throttling that points at a problem. Hitting the power limit is normal
+// under a stress test so that one is ignored
+const UNSTABLE_THROTTLE: ThrottleReasons = ThrottleReasons::HW_SLOWDOWN
+    .union(ThrottleReasons::HW_THERMAL_SLOWDOWN)
+    .union(ThrottleReasons::SW_THERMAL_SLOWDOWN)
+    .union(ThrottleReasons::HW_POWER_BRAKE_SLOWDOWN);
+
+// which offset is being scanned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Domain {
+    Core,
+    Memory,
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Domain::Core => write!(f, "core"),
+            Domain::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+// how to scan. Offsets are in MHz
+#[derive(Debug, Clone)]
+pub struct TuneSettings {
+    // added per step, 0 leaves that offset at 0
+    pub core_step: i32,
+    pub mem_step: i32,
+    // highest offset to try, None for the top of the supported range
+    pub core_max: Option<i32>,
+    pub mem_max: Option<i32>,
+    // taken off the highest stable offset for the result
+    pub core_margin: i32,
+    pub mem_margin: i32,
+    // shell command that has to succeed for a step to pass. Without one
+    // every step is just watched for the duration
+    pub command: Option<String>,
+    pub duration: Duration,
+    // how long the command may run before it is taken as hung
+    pub timeout: Duration,
+    // temperature that fails a step, on top of thermal throttling
+    pub max_temp_c: Option<u32>,
+}
+
+// why a step did not pass
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Failure {
+    // the offsets are too high, scanning goes on from the last stable ones
+    Unstable(String),
+    // the scan can not go on at all
+    Abort(String),
+}
+
+// the offsets found to be stable, with the safety margin already taken off
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TuneResult {
+    pub core_offset: i32,
+    pub mem_offset: i32,
+}
+
+// step the core and then the memory offset up on each of the given gpus
+// until a step fails, backing off to the last stable offset every time.
+// The result is left applied. Progress goes to log. Setting interrupted
+// stops the scan, which like any other error puts back the offsets the
+// gpus had before
+pub fn run(
+    gpu: &mut Gpu,
+    devices: &[u32],
+    settings: &TuneSettings,
+    interrupted: &AtomicBool,
+    log: &mut dyn FnMut(String),
+) -> Result<TuneResult, String> {
+    gpu.check_write_access().map_err(|e| e.to_string())?;
+
+    let mut original = Vec::new();
+    for &index in devices {
+        match (gpu.get_gpu_offset(index), gpu.get_mem_offset(index)) {
+            (Ok(core_offset), Ok(mem_offset)) => original.push((index, core_offset, mem_offset)),
+            (Err(e), _) | (_, Err(e)) => {
+                return Err(format!(
+                    "GPU {index}: could not read the current offsets to go back to: {e}"
+                ));
+            }
+        }
+    }
+
+    scan_all(gpu, devices, settings, interrupted, log).map_err(|e| {
+        let mut message = e;
+        for &(index, core_offset, mem_offset) in &original {
+            match gpu.apply_oc(&[index], core_offset, mem_offset) {
+                Ok(()) => log(format!(
+                    "GPU {index}: put back core {core_offset} MHz, mem {mem_offset} MHz"
+                )),
+                Err(e) => message.push_str(&format!("\ncould not put the offsets back: {e}")),
+            }
+        }
+        message
+    })
+}
+
+fn scan_all(
+    gpu: &mut Gpu,
+    devices: &[u32],
+    settings: &TuneSettings,
+    interrupted: &AtomicBool,
+    log: &mut dyn FnMut(String),
+) -> Result<TuneResult, String> {
+    let xid = XidWatch::new();
+    if xid.seen.is_none() {
+        log(String::from(
+            "warning: could not read the kernel log, Xid errors will not be noticed",
+        ));
+    }
+
+    let (core_range, mem_range) = gpu.offset_ranges(devices);
+    let mut scanner = Scanner {
+        gpu,
+        devices,
+        settings,
+        xid,
+        interrupted,
+        log,
+        tuned: TuneResult::default(),
+    };
+
+    // a command that fails without any offset would make every step look
+    // unstable, so there is nothing to learn from scanning with it
+    let scanning = settings.core_step > 0 || settings.mem_step > 0;
+    if scanning && settings.command.is_some() {
+        scanner.apply(0, 0)?;
+        (scanner.log)(String::from("no offset: testing"));
+        match scanner.validate(0, 0) {
+            Ok(()) => (scanner.log)(String::from("no offset: stable")),
+            Err(Failure::Unstable(reason) | Failure::Abort(reason)) => {
+                return Err(format!(
+                    "the validation fails even without an offset, {reason}"
+                ));
+            }
+        }
+    }
+
+    for domain in [Domain::Core, Domain::Memory] {
+        let (step, max, range, margin) = match domain {
+            Domain::Core => (
+                settings.core_step,
+                settings.core_max,
+                core_range,
+                settings.core_margin,
+            ),
+            Domain::Memory => (
+                settings.mem_step,
+                settings.mem_max,
+                mem_range,
+                settings.mem_margin,
+            ),
+        };
+        if step <= 0 {
+            continue;
+        }
+
+        let max = match (max, range) {
+            (Some(max), Some(range)) => max.min(range.max),
+            (Some(max), None) => max,
+            (None, Some(range)) => range.max,
+            (None, None) => {
+                return Err(format!(
+                    "the supported {domain} offset range is unknown, give a maximum to scan to"
+                ));
+            }
+        };
+
+        let stable = scanner.scan(domain, step, max)?;
+        let tuned = (stable - margin).max(0);
+        (scanner.log)(format!(
+            "{domain} offset: highest stable {stable} MHz, {tuned} MHz with the safety margin"
+        ));
+
+        match domain {
+            Domain::Core => scanner.tuned.core_offset = tuned,
+            Domain::Memory => scanner.tuned.mem_offset = tuned,
+        }
+    }
+
+    let result = scanner.tuned;
+    scanner.apply(result.core_offset, result.mem_offset)?;
+    Ok(result)
+}
+
+// everything a scan needs, kept together so it does not have to be passed
+// around piece by piece
+struct Scanner<'a> {
+    gpu: &'a mut Gpu,
+    devices: &'a [u32],
+    settings: &'a TuneSettings,
+    xid: XidWatch,
+    interrupted: &'a AtomicBool,
+    log: &'a mut dyn FnMut(String),
+    // offsets settled on so far
+    tuned: TuneResult,
+}
+
+impl Scanner<'_> {
+    // the offsets to apply when testing one domain at the given offset
+    fn offsets(&self, domain: Domain, offset: i32) -> (i32, i32) {
+        match domain {
+            Domain::Core => (offset, self.tuned.mem_offset),
+            Domain::Memory => (self.tuned.core_offset, offset),
+        }
+    }
+
+    // same path the gui and the set command use
+    fn apply(&mut self, core_offset: i32, mem_offset: i32) -> Result<(), String> {
+        self.gpu.apply_oc(self.devices, core_offset, mem_offset)
+    }
+
+    // step one offset up from 0 while keeping the other at its tuned value,
+    // returning the highest offset that passed
+    fn scan(&mut self, domain: Domain, step: i32, max: i32) -> Result<i32, String> {
+        let mut stable = 0;
+        let mut offset = step;
+
+        while offset <= max {
+            let (core_offset, mem_offset) = self.offsets(domain, offset);
+            self.apply(core_offset, mem_offset)?;
+            (self.log)(format!("{domain} offset {offset} MHz: testing"));
+
+            match self.validate(core_offset, mem_offset) {
+                Ok(()) => {
+                    (self.log)(format!("{domain} offset {offset} MHz: stable"));
+                    stable = offset;
+                    offset += step;
+                }
+                Err(Failure::Abort(reason)) => return Err(reason),
+                Err(Failure::Unstable(reason)) => {
+                    (self.log)(format!("{domain} offset {offset} MHz: unstable, {reason}"));
+
+                    // get back to a known good state before anything else
+                    let (core_offset, mem_offset) = self.offsets(domain, stable);
+                    self.apply(core_offset, mem_offset)?;
+                    break;
+                }
+            }
+        }
+
+        Ok(stable)
+    }
+
+    // run the validation for one step while watching the gpus, returning
+    // why the step failed if it did
+    fn validate(&mut self, core_offset: i32, mem_offset: i32) -> Result<(), Failure> {
+        let mut child = match &self.settings.command {
+            Some(command) => Some(
+                Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env(CORE_OFFSET_ENV, core_offset.to_string())
+                    .env(MEM_OFFSET_ENV, mem_offset.to_string())
+                    .spawn()
+                    .map_err(|e| {
+                        Failure::Abort(format!("could not start the validation command: {e}"))
+                    })?,
+            ),
+            None => None,
+        };
+
+        let limit = match child {
+            Some(_) => self.settings.timeout,
+            None => self.settings.duration,
+        };
+        let start = Instant::now();
+
+        let result = loop {
+            thread::sleep(POLL_INTERVAL);
+
+            if self.interrupted.load(Ordering::Relaxed) {
+                break Err(Failure::Abort(String::from("interrupted")));
+            }
+
+            if let Err(reason) = self.check_gpus() {
+                break Err(Failure::Unstable(reason));
+            }
+
+            if let Some(child) = &mut child {
+                match child.try_wait() {
+                    Ok(Some(status)) if status.success() => break Ok(()),
+                    Ok(Some(status))
+                        if matches!(
+                            status.code(),
+                            Some(COMMAND_NOT_EXECUTABLE | COMMAND_NOT_FOUND)
+                        ) =>
+                    {
+                        break Err(Failure::Abort(format!(
+                            "the validation command could not be run ({status})"
+                        )));
+                    }
+                    Ok(Some(status)) => {
+                        break Err(Failure::Unstable(format!(
+                            "the validation command failed ({status})"
+                        )));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        break Err(Failure::Abort(format!(
+                            "could not wait for the validation command: {e}"
+                        )));
+                    }
+                }
+            }
+
+            if start.elapsed() >= limit {
+                break match child {
+                    Some(_) => Err(Failure::Unstable(String::from(
+                        "the validation command timed out",
+                    ))),
+                    None => Ok(()),
+                };
+            }
+        };
+
+        if let Some(child) = &mut child {
+            stop(child);
+        }
+
+        result
+    }
+
+    // look for signs of instability in the latest readings
+    fn check_gpus(&mut self) -> Result<(), String> {
+        self.gpu.update_gpu_info();
+
+        if let Some(line) = self.xid.check() {
+            return Err(format!("the driver reported an error: {line}"));
+        }
+
+        for &index in self.devices {
+            let Some(device) = self.gpu.device(index) else {
+                continue;
+            };
+
+            if let (Some(max_temp_c), Ok(temp_c)) =
+                (self.settings.max_temp_c, &device.sample.temperature_c)
+                && *temp_c > max_temp_c
+            {
+                return Err(format!("GPU {index} reached {temp_c} °C"));
+            }
+
+            match self.gpu.throttle_reasons(index) {
+                Ok(reasons) if reasons.intersects(UNSTABLE_THROTTLE) => {
+                    return Err(format!(
+                        "GPU {index} is throttling: {:?}",
+                        reasons & UNSTABLE_THROTTLE
+                    ));
+                }
+                Err(GpuError::GpuLost) => return Err(format!("GPU {index} fell off the bus")),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn stop(child: &mut Child) {
+    if let Ok(None) = child.try_wait() {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+// notices new Xid errors in the kernel log. None when the log can not be
+// read, which usually means the process is not root
+struct XidWatch {
+    seen: Option<HashSet<String>>,
+}
+
+impl XidWatch {
+    fn new() -> Self {
+        Self {
+            seen: xid_lines().map(HashSet::from_iter),
+        }
+    }
+
+    // the first Xid line that was not there before, if any
+    fn check(&mut self) -> Option<String> {
+        let seen = self.seen.as_mut()?;
+
+        let mut first_new = None;
+        for line in xid_lines()? {
+            if seen.insert(line.clone()) && first_new.is_none() {
+                first_new = Some(line);
+            }
+        }
+
+        first_new
+    }
+}
+
+fn xid_lines() -> Option<Vec<String>> {
+    let output = Command::new("dmesg").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| line.contains(XID_MARKER))
+            .map(String::from)
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SimBackend;
+
+    fn settings(command: &str) -> TuneSettings {
+        TuneSettings {
+            core_step: 100,
+            mem_step: 0,
+            core_max: Some(300),
+            mem_max: None,
+            core_margin: 0,
+            mem_margin: 0,
+            command: Some(command.to_string()),
+            duration: Duration::ZERO,
+            timeout: Duration::from_secs(10),
+            max_temp_c: None,
+        }
+    }
+
+    fn tune(
+        gpu: &mut Gpu,
+        settings: &TuneSettings,
+        interrupted: bool,
+    ) -> Result<TuneResult, String> {
+        gpu.apply_oc(&[0], 50, 150).unwrap();
+        run(
+            gpu,
+            &[0],
+            settings,
+            &AtomicBool::new(interrupted),
+            &mut |_| {},
+        )
+    }
+
+    fn sim_gpu() -> Gpu {
+        Gpu::with_backend(Box::new(SimBackend::new(1))).unwrap()
+    }
+
+    #[test]
+    fn finds_the_highest_passing_offset() {
+        let mut gpu = sim_gpu();
+        let settings = settings("test \"$NVIDIA_TWEAKER_CORE_OFFSET\" -le 100");
+
+        let result = tune(&mut gpu, &settings, false).unwrap();
+        assert_eq!(
+            result,
+            TuneResult {
+                core_offset: 100,
+                mem_offset: 0
+            }
+        );
+        assert_eq!(gpu.get_gpu_offset(0), Ok(100));
+    }
+
+    #[test]
+    fn aborts_and_restores_when_the_command_can_not_run() {
+        let mut gpu = sim_gpu();
+        let error = tune(&mut gpu, &settings("exit 127"), false).unwrap_err();
+
+        assert!(error.contains("could not be run"), "{error}");
+        assert_eq!(gpu.get_gpu_offset(0), Ok(50));
+        assert_eq!(gpu.get_mem_offset(0), Ok(150));
+    }
+
+    #[test]
+    fn aborts_when_the_command_fails_without_an_offset() {
+        let mut gpu = sim_gpu();
+        let error = tune(&mut gpu, &settings("false"), false).unwrap_err();
+
+        assert!(error.contains("even without an offset"), "{error}");
+        assert_eq!(gpu.get_gpu_offset(0), Ok(50));
+    }
+
+    #[test]
+    fn restores_the_offsets_when_interrupted() {
+        let mut gpu = sim_gpu();
+        let error = tune(&mut gpu, &settings("sleep 5"), true).unwrap_err();
+
+        assert!(error.contains("interrupted"), "{error}");
+        assert_eq!(gpu.get_gpu_offset(0), Ok(50));
+        assert_eq!(gpu.get_mem_offset(0), Ok(150));
+    }
+}
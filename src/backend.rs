@@ -0,0 +1,212 @@
+use std::path::PathBuf;
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::Clock;
+
+use crate::error::GpuError;
+use crate::helper;
+
+mod helper_client;
+mod nvml;
+mod sim;
+
+pub use helper_client::HelperBackend;
+pub use nvml::NvmlBackend;
+pub use sim::SimBackend;
+
+// environment variable used to pick the backend at startup
+const BACKEND_ENV: &str = "NVIDIA_TWEAKER_BACKEND";
+
+// environment variable setting how many gpus the simulated backend exposes
+const SIM_DEVICES_ENV: &str = "NVIDIA_TWEAKER_SIM_DEVICES";
+const SIM_DEFAULT_DEVICES: u32 = 2;
+
+// environment variable pointing at the privileged helper's socket
+const HELPER_SOCKET_ENV: &str = "NVIDIA_TWEAKER_HELPER_SOCKET";
+
+// memory info as reported by a backend, all values in bytes
+pub struct MemoryInfo {
+    pub free: u64,
+    pub total: u64,
+    pub used: u64,
+}
+
+// utilization rates as reported by a backend, in percent
+pub struct Utilization {
+    pub gpu: u32,
+    pub memory: u32,
+}
+
+// range the power limit can be set to, all values in milliwatts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowerConstraints {
+    pub min_mw: u32,
+    pub max_mw: u32,
+    pub default_mw: u32,
+}
+
+// who is deciding the speed of a fan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanControlPolicy {
+    // the driver follows its own temperature curve
+    Auto,
+    // the speed was set by hand, or by one of our fan curves
+    Manual,
+}
+
+impl std::fmt::Display for FanControlPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FanControlPolicy::Auto => write!(f, "auto"),
+            FanControlPolicy::Manual => write!(f, "manual"),
+        }
+    }
+}
+
+// range a VF offset can be set to, in MHz
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetRange {
+    pub min: i32,
+    pub max: i32,
+}
+
+// range a clock is locked to, in MHz
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockLock {
+    pub min_mhz: u32,
+    pub max_mhz: u32,
+}
+
+// everything the app needs from a gpu. The NVML backend talks to the real
+// driver while the simulated one lets the app run on machines without one.
+// Backends get sampled from a worker thread so they have to be Send
+pub trait GpuBackend: Send {
+    // called once at the start of every update, before any of the queries
+    fn refresh(&mut self) {}
+
+    // number of gpus the backend can see. Every per-device call below takes
+    // an index in 0..device_count
+    fn device_count(&self) -> Result<u32, GpuError>;
+
+    fn name(&self, index: u32) -> Result<String, GpuError>;
+    fn driver_version(&self) -> Result<String, GpuError>;
+
+    // power draw in milliwatts
+    fn power_usage(&self, index: u32) -> Result<u32, GpuError>;
+    fn clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError>;
+    fn max_clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError>;
+    fn temperature(&self, index: u32) -> Result<u32, GpuError>;
+    fn memory_info(&self, index: u32) -> Result<MemoryInfo, GpuError>;
+    fn num_fans(&self, index: u32) -> Result<u32, GpuError>;
+    // fan speed in percent
+    fn fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError>;
+    fn fan_speed_rpm(&self, index: u32, fan: u32) -> Result<u32, GpuError>;
+    // speed in percent the fan is being driven towards
+    fn target_fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError>;
+    fn fan_control_policy(&self, index: u32, fan: u32) -> Result<FanControlPolicy, GpuError>;
+    fn utilization_rates(&self, index: u32) -> Result<Utilization, GpuError>;
+    // why the clocks are being held back right now
+    fn throttle_reasons(&self, index: u32) -> Result<ThrottleReasons, GpuError>;
+
+    fn gpc_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError>;
+    fn mem_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError>;
+    fn gpc_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError>;
+    fn mem_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError>;
+    fn set_gpc_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError>;
+    fn set_mem_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError>;
+
+    fn power_constraints(&self, index: u32) -> Result<PowerConstraints, GpuError>;
+    // the limit the driver is actually enforcing, in milliwatts
+    fn enforced_power_limit(&self, index: u32) -> Result<u32, GpuError>;
+    fn set_power_limit(&mut self, index: u32, limit_mw: u32) -> Result<(), GpuError>;
+
+    // take manual control of a fan and run it at the given percentage
+    fn set_fan_speed(&mut self, index: u32, fan: u32, speed: u32) -> Result<(), GpuError>;
+    // hand a fan back to the driver's automatic control
+    fn set_default_fan_speed(&mut self, index: u32, fan: u32) -> Result<(), GpuError>;
+
+    // clocks in MHz the memory can run at, highest first
+    fn supported_memory_clocks(&self, index: u32) -> Result<Vec<u32>, GpuError>;
+    // clocks in MHz the gpu can run at with the memory at the given clock
+    fn supported_graphics_clocks(&self, index: u32, mem_clock: u32) -> Result<Vec<u32>, GpuError>;
+
+    // keep the graphics or memory clock within a range
+    fn set_gpu_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError>;
+    fn set_mem_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError>;
+    // let the driver pick the graphics or memory clocks again after they
+    // were locked to a range
+    fn reset_gpu_locked_clocks(&mut self, index: u32) -> Result<(), GpuError>;
+    fn reset_mem_locked_clocks(&mut self, index: u32) -> Result<(), GpuError>;
+
+    // a second handle to the same gpus for taking readings on another
+    // thread, so they never wait on a write going through this one. Only
+    // the reading calls are used on it
+    fn reader(&self) -> Result<Box<dyn GpuBackend>, GpuError>;
+
+    // whether the write operations need the process to run as root
+    fn requires_root(&self) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    Nvml,
+    Simulated,
+}
+
+impl BackendKind {
+    // read the backend from the environment, defaulting to NVML
+    pub fn from_env() -> Self {
+        match std::env::var(BACKEND_ENV) {
+            Ok(value) => Self::parse(&value).unwrap_or(Self::Nvml),
+            Err(_) => Self::Nvml,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "nvml" => Some(Self::Nvml),
+            "sim" | "simulated" => Some(Self::Simulated),
+            _ => None,
+        }
+    }
+}
+
+// create the backend for the given kind, sending writes through the
+// privileged helper when there is one to use
+pub fn create(kind: BackendKind) -> Result<Box<dyn GpuBackend>, GpuError> {
+    let backend = create_local(kind)?;
+
+    Ok(match helper_socket(backend.as_ref()) {
+        Some(socket) => Box::new(HelperBackend::new(backend, socket)),
+        None => backend,
+    })
+}
+
+// create the backend for the given kind that talks to the gpus directly
+pub fn create_local(kind: BackendKind) -> Result<Box<dyn GpuBackend>, GpuError> {
+    match kind {
+        BackendKind::Nvml => Ok(Box::new(NvmlBackend::new()?)),
+        BackendKind::Simulated => Ok(Box::new(SimBackend::new(sim_device_count()))),
+    }
+}
+
+// the helper socket to send writes to, if any. A socket set in the
+// environment is always used, the default one only when the writes could
+// not be done by this process
+fn helper_socket(backend: &dyn GpuBackend) -> Option<PathBuf> {
+    if let Some(socket) = std::env::var_os(HELPER_SOCKET_ENV).filter(|socket| !socket.is_empty()) {
+        return Some(PathBuf::from(socket));
+    }
+
+    let socket = PathBuf::from(helper::DEFAULT_SOCKET);
+    (backend.requires_root() && !sudo2::running_as_root() && socket.exists()).then_some(socket)
+}
+
+// how many gpus to simulate, taken from the environment
+fn sim_device_count() -> u32 {
+    std::env::var(SIM_DEVICES_ENV)
+        .ok()
+        .and_then(|value| value.trim().parse::<u32>().ok())
+        .filter(|count| *count > 0)
+        .unwrap_or(SIM_DEFAULT_DEVICES)
+}
@@ -0,0 +1,229 @@
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::Clock;
+
+use super::{
+    ClockLock, FanControlPolicy, GpuBackend, MemoryInfo, OffsetRange, PowerConstraints, Utilization,
+};
+use crate::error::GpuError;
+use crate::helper::{Request, Response};
+
+// how long to wait on the helper before giving up
+const HELPER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// reads go straight to the wrapped backend while every write is sent to
+// the privileged helper, see helper.rs for the protocol
+pub struct HelperBackend {
+    inner: Box<dyn GpuBackend>,
+    socket: PathBuf,
+}
+
+impl HelperBackend {
+    pub fn new(inner: Box<dyn GpuBackend>, socket: PathBuf) -> Self {
+        Self { inner, socket }
+    }
+
+    // send a single request and wait for the answer. A fresh connection is
+    // used every time so a restarted helper is picked up without fuss
+    fn send(&self, request: Request) -> Result<(), GpuError> {
+        let unreachable = |e: std::io::Error| {
+            GpuError::Helper(format!(
+                "could not talk to the helper at {}: {e}",
+                self.socket.display()
+            ))
+        };
+
+        let mut stream = UnixStream::connect(&self.socket).map_err(unreachable)?;
+        // a helper that stops reading or answering must not hold up the
+        // caller, which may be keeping the backend locked
+        stream
+            .set_read_timeout(Some(HELPER_TIMEOUT))
+            .map_err(unreachable)?;
+        stream
+            .set_write_timeout(Some(HELPER_TIMEOUT))
+            .map_err(unreachable)?;
+
+        let mut line =
+            serde_json::to_string(&request).map_err(|e| GpuError::Helper(e.to_string()))?;
+        line.push('\n');
+        stream.write_all(line.as_bytes()).map_err(unreachable)?;
+
+        let mut reply = String::new();
+        BufReader::new(stream)
+            .read_line(&mut reply)
+            .map_err(unreachable)?;
+
+        serde_json::from_str::<Response>(&reply)
+            .map_err(|e| GpuError::Helper(format!("bad reply from the helper: {e}")))?
+            .into_result()
+    }
+}
+
+impl GpuBackend for HelperBackend {
+    fn refresh(&mut self) {
+        self.inner.refresh();
+    }
+
+    fn device_count(&self) -> Result<u32, GpuError> {
+        self.inner.device_count()
+    }
+
+    fn name(&self, index: u32) -> Result<String, GpuError> {
+        self.inner.name(index)
+    }
+
+    fn driver_version(&self) -> Result<String, GpuError> {
+        self.inner.driver_version()
+    }
+
+    fn power_usage(&self, index: u32) -> Result<u32, GpuError> {
+        self.inner.power_usage(index)
+    }
+
+    fn clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError> {
+        self.inner.clock_info(index, clock)
+    }
+
+    fn max_clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError> {
+        self.inner.max_clock_info(index, clock)
+    }
+
+    fn temperature(&self, index: u32) -> Result<u32, GpuError> {
+        self.inner.temperature(index)
+    }
+
+    fn memory_info(&self, index: u32) -> Result<MemoryInfo, GpuError> {
+        self.inner.memory_info(index)
+    }
+
+    fn num_fans(&self, index: u32) -> Result<u32, GpuError> {
+        self.inner.num_fans(index)
+    }
+
+    fn fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        self.inner.fan_speed(index, fan)
+    }
+
+    fn fan_speed_rpm(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        self.inner.fan_speed_rpm(index, fan)
+    }
+
+    fn target_fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        self.inner.target_fan_speed(index, fan)
+    }
+
+    fn fan_control_policy(&self, index: u32, fan: u32) -> Result<FanControlPolicy, GpuError> {
+        self.inner.fan_control_policy(index, fan)
+    }
+
+    fn utilization_rates(&self, index: u32) -> Result<Utilization, GpuError> {
+        self.inner.utilization_rates(index)
+    }
+
+    fn throttle_reasons(&self, index: u32) -> Result<ThrottleReasons, GpuError> {
+        self.inner.throttle_reasons(index)
+    }
+
+    fn gpc_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError> {
+        self.inner.gpc_clk_vf_offset(index)
+    }
+
+    fn mem_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError> {
+        self.inner.mem_clk_vf_offset(index)
+    }
+
+    fn gpc_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError> {
+        self.inner.gpc_clk_vf_offset_range(index)
+    }
+
+    fn mem_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError> {
+        self.inner.mem_clk_vf_offset_range(index)
+    }
+
+    fn set_gpc_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError> {
+        self.send(Request::SetCoreOffset {
+            device: index,
+            offset_mhz: offset,
+        })
+    }
+
+    fn set_mem_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError> {
+        self.send(Request::SetMemOffset {
+            device: index,
+            offset_mhz: offset,
+        })
+    }
+
+    fn power_constraints(&self, index: u32) -> Result<PowerConstraints, GpuError> {
+        self.inner.power_constraints(index)
+    }
+
+    fn enforced_power_limit(&self, index: u32) -> Result<u32, GpuError> {
+        self.inner.enforced_power_limit(index)
+    }
+
+    fn set_power_limit(&mut self, index: u32, limit_mw: u32) -> Result<(), GpuError> {
+        self.send(Request::SetPowerLimit {
+            device: index,
+            limit_mw,
+        })
+    }
+
+    fn set_fan_speed(&mut self, index: u32, fan: u32, speed: u32) -> Result<(), GpuError> {
+        self.send(Request::SetFanSpeed {
+            device: index,
+            fan,
+            speed_percent: speed,
+        })
+    }
+
+    fn set_default_fan_speed(&mut self, index: u32, fan: u32) -> Result<(), GpuError> {
+        self.send(Request::SetDefaultFanSpeed { device: index, fan })
+    }
+
+    fn supported_memory_clocks(&self, index: u32) -> Result<Vec<u32>, GpuError> {
+        self.inner.supported_memory_clocks(index)
+    }
+
+    fn supported_graphics_clocks(&self, index: u32, mem_clock: u32) -> Result<Vec<u32>, GpuError> {
+        self.inner.supported_graphics_clocks(index, mem_clock)
+    }
+
+    fn set_gpu_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError> {
+        self.send(Request::SetGpuLockedClocks {
+            device: index,
+            min_mhz: lock.min_mhz,
+            max_mhz: lock.max_mhz,
+        })
+    }
+
+    fn set_mem_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError> {
+        self.send(Request::SetMemLockedClocks {
+            device: index,
+            min_mhz: lock.min_mhz,
+            max_mhz: lock.max_mhz,
+        })
+    }
+
+    fn reset_gpu_locked_clocks(&mut self, index: u32) -> Result<(), GpuError> {
+        self.send(Request::ResetGpuLockedClocks { device: index })
+    }
+
+    fn reset_mem_locked_clocks(&mut self, index: u32) -> Result<(), GpuError> {
+        self.send(Request::ResetMemLockedClocks { device: index })
+    }
+
+    // reads never go through the helper anyway
+    fn reader(&self) -> Result<Box<dyn GpuBackend>, GpuError> {
+        self.inner.reader()
+    }
+
+    // the helper does the privileged part
+    fn requires_root(&self) -> bool {
+        false
+    }
+}
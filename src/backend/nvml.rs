@@ -0,0 +1,360 @@
+use std::ffi::OsString;
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::{Clock, TemperatureSensor};
+use nvml_wrapper::{
+    Device, Nvml,
+    error::{NvmlError, nvml_try},
+};
+use nvml_wrapper_sys::bindings::{
+    NVML_FAN_POLICY_MANUAL, NVML_FAN_POLICY_TEMPERATURE_CONTINOUS_SW, NvmlLib, nvmlDevice_t,
+    nvmlReturn_t,
+};
+
+use super::{
+    ClockLock, FanControlPolicy, GpuBackend, MemoryInfo, OffsetRange, PowerConstraints, Utilization,
+};
+use crate::error::GpuError;
+
+// environment variable pointing at the NVML library to load instead of
+// the usual names
+const LIBRARY_ENV: &str = "NVIDIA_TWEAKER_NVML_LIB";
+
+// names the library goes by, the versioned one first since the unversioned
+// link only comes with the driver's development files on most distros
+const LIBRARY_NAMES: [&str; 2] = ["libnvidia-ml.so.1", "libnvidia-ml.so"];
+
+// nvmlDeviceGetFanSpeedRPM came with drivers newer than the bindings in
+// use, so it is looked up by hand along with its nvmlFanSpeedInfo_v1_t
+const FAN_SPEED_RPM_FUNCTION: &str = "nvmlDeviceGetFanSpeedRPM";
+
+#[repr(C)]
+struct FanSpeedInfo {
+    version: u32,
+    fan: u32,
+    speed_rpm: u32,
+}
+
+// NVML_STRUCT_VERSION(FanSpeedInfo, 1), the size with the version on top
+const FAN_SPEED_INFO_VERSION: u32 = size_of::<FanSpeedInfo>() as u32 | 1 << 24;
+
+type GetFanSpeedRpm = unsafe extern "C" fn(nvmlDevice_t, *mut FanSpeedInfo) -> nvmlReturn_t;
+
+// backend talking to the real driver through NVML
+pub struct NvmlBackend {
+    nvml: Nvml,
+    // the same library again, for functions nvml-wrapper does not cover.
+    // Loaded once since every reading goes through it
+    lib: NvmlLib,
+    // None when the driver is too old to have it
+    get_fan_speed_rpm: Option<GetFanSpeedRpm>,
+}
+
+// calling a function the library does not export panics, so check for it
+// first and fail with its name
+macro_rules! require {
+    ($lib:expr, $function:ident) => {
+        if $lib.$function.is_err() {
+            return Err(GpuError::MissingFunction(String::from(stringify!(
+                $function
+            ))));
+        }
+    };
+}
+
+// load the library from the configured path, or else the first of the
+// usual names that loads
+fn load_library() -> Result<(OsString, NvmlLib), GpuError> {
+    let candidates = match std::env::var_os(LIBRARY_ENV).filter(|path| !path.is_empty()) {
+        Some(path) => vec![path],
+        None => LIBRARY_NAMES.iter().map(OsString::from).collect(),
+    };
+
+    let mut errors = Vec::new();
+    for path in candidates {
+        match unsafe { NvmlLib::new(&path) } {
+            Ok(lib) => return Ok((path, lib)),
+            Err(e) => errors.push(e.to_string()),
+        }
+    }
+
+    Err(GpuError::LibraryNotFound(errors.join("; ")))
+}
+
+fn get_value<T, F>(f: F) -> Result<T, NvmlError>
+where
+    T: Default,
+    F: FnOnce(*mut T) -> nvmlReturn_t,
+{
+    let mut value = T::default();
+    let status = f(&mut value);
+    nvml_try(status).map(|_| value)
+}
+
+impl NvmlBackend {
+    pub fn new() -> Result<Self, GpuError> {
+        let (path, lib) = load_library()?;
+
+        // the pointer stays valid for as long as lib keeps the library loaded
+        let get_fan_speed_rpm = unsafe {
+            lib.__library
+                .get::<GetFanSpeedRpm>(format!("{FAN_SPEED_RPM_FUNCTION}\0").as_bytes())
+                .ok()
+                .map(|function| *function)
+        };
+
+        Ok(Self {
+            nvml: Nvml::builder().lib_path(&path).init()?,
+            lib,
+            get_fan_speed_rpm,
+        })
+    }
+
+    fn device(&self, index: u32) -> Result<Device<'_>, NvmlError> {
+        self.nvml.device_by_index(index)
+    }
+
+    // run a call against the raw library for functions nvml-wrapper does not cover
+    fn with_raw<T, F>(&self, index: u32, f: F) -> Result<T, GpuError>
+    where
+        F: FnOnce(&NvmlLib, nvmlDevice_t) -> Result<T, NvmlError>,
+    {
+        let nvml_device = self.device(index)?;
+
+        unsafe {
+            let raw_device_handle: nvmlDevice_t = nvml_device.handle();
+            Ok(f(&self.lib, raw_device_handle)?)
+        }
+    }
+}
+
+impl GpuBackend for NvmlBackend {
+    fn device_count(&self) -> Result<u32, GpuError> {
+        Ok(self.nvml.device_count()?)
+    }
+
+    fn name(&self, index: u32) -> Result<String, GpuError> {
+        Ok(self.device(index)?.name()?)
+    }
+
+    fn driver_version(&self) -> Result<String, GpuError> {
+        Ok(self.nvml.sys_driver_version()?)
+    }
+
+    fn power_usage(&self, index: u32) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.power_usage()?)
+    }
+
+    fn clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.clock_info(clock)?)
+    }
+
+    fn max_clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.max_clock_info(clock)?)
+    }
+
+    fn temperature(&self, index: u32) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.temperature(TemperatureSensor::Gpu)?)
+    }
+
+    fn memory_info(&self, index: u32) -> Result<MemoryInfo, GpuError> {
+        let mem_info = self.device(index)?.memory_info()?;
+
+        Ok(MemoryInfo {
+            free: mem_info.free,
+            total: mem_info.total,
+            used: mem_info.used,
+        })
+    }
+
+    fn num_fans(&self, index: u32) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.num_fans()?)
+    }
+
+    fn fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.fan_speed(fan)?)
+    }
+
+    fn fan_speed_rpm(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        let get_fan_speed_rpm = self
+            .get_fan_speed_rpm
+            .ok_or_else(|| GpuError::MissingFunction(String::from(FAN_SPEED_RPM_FUNCTION)))?;
+
+        self.with_raw(index, |_, handle| unsafe {
+            let mut info = FanSpeedInfo {
+                version: FAN_SPEED_INFO_VERSION,
+                fan,
+                speed_rpm: 0,
+            };
+            nvml_try(get_fan_speed_rpm(handle, &mut info)).map(|_| info.speed_rpm)
+        })
+    }
+
+    fn target_fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        require!(self.lib, nvmlDeviceGetTargetFanSpeed);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            get_value(|speed| nvml_lib.nvmlDeviceGetTargetFanSpeed(handle, fan, speed))
+        })
+    }
+
+    fn fan_control_policy(&self, index: u32, fan: u32) -> Result<FanControlPolicy, GpuError> {
+        require!(self.lib, nvmlDeviceGetFanControlPolicy_v2);
+        let policy = self.with_raw(index, |nvml_lib, handle| unsafe {
+            get_value(|policy| nvml_lib.nvmlDeviceGetFanControlPolicy_v2(handle, fan, policy))
+        })?;
+
+        match policy {
+            NVML_FAN_POLICY_TEMPERATURE_CONTINOUS_SW => Ok(FanControlPolicy::Auto),
+            NVML_FAN_POLICY_MANUAL => Ok(FanControlPolicy::Manual),
+            other => Err(GpuError::Unknown(format!(
+                "unknown fan control policy {other}"
+            ))),
+        }
+    }
+
+    fn utilization_rates(&self, index: u32) -> Result<Utilization, GpuError> {
+        let utilization_rates = self.device(index)?.utilization_rates()?;
+
+        Ok(Utilization {
+            gpu: utilization_rates.gpu,
+            memory: utilization_rates.memory,
+        })
+    }
+
+    fn throttle_reasons(&self, index: u32) -> Result<ThrottleReasons, GpuError> {
+        Ok(self.device(index)?.current_throttle_reasons()?)
+    }
+
+    fn gpc_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError> {
+        require!(self.lib, nvmlDeviceGetGpcClkVfOffset);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            get_value(|offset| nvml_lib.nvmlDeviceGetGpcClkVfOffset(handle, offset))
+        })
+    }
+
+    fn mem_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError> {
+        require!(self.lib, nvmlDeviceGetMemClkVfOffset);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            get_value(|offset| nvml_lib.nvmlDeviceGetMemClkVfOffset(handle, offset))
+        })
+    }
+
+    fn gpc_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError> {
+        require!(self.lib, nvmlDeviceGetGpcClkMinMaxVfOffset);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            let mut range = OffsetRange { min: 0, max: 0 };
+            nvml_try(nvml_lib.nvmlDeviceGetGpcClkMinMaxVfOffset(
+                handle,
+                &mut range.min,
+                &mut range.max,
+            ))
+            .map(|_| range)
+        })
+    }
+
+    fn mem_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError> {
+        require!(self.lib, nvmlDeviceGetMemClkMinMaxVfOffset);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            let mut range = OffsetRange { min: 0, max: 0 };
+            nvml_try(nvml_lib.nvmlDeviceGetMemClkMinMaxVfOffset(
+                handle,
+                &mut range.min,
+                &mut range.max,
+            ))
+            .map(|_| range)
+        })
+    }
+
+    fn set_gpc_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceSetGpcClkVfOffset);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceSetGpcClkVfOffset(handle, offset))
+        })
+    }
+
+    fn set_mem_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceSetMemClkVfOffset);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceSetMemClkVfOffset(handle, offset))
+        })
+    }
+
+    fn power_constraints(&self, index: u32) -> Result<PowerConstraints, GpuError> {
+        let nvml_device = self.device(index)?;
+        let constraints = nvml_device.power_management_limit_constraints()?;
+
+        Ok(PowerConstraints {
+            min_mw: constraints.min_limit,
+            max_mw: constraints.max_limit,
+            default_mw: nvml_device.power_management_limit_default()?,
+        })
+    }
+
+    fn enforced_power_limit(&self, index: u32) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.enforced_power_limit()?)
+    }
+
+    fn set_power_limit(&mut self, index: u32, limit_mw: u32) -> Result<(), GpuError> {
+        Ok(self.device(index)?.set_power_management_limit(limit_mw)?)
+    }
+
+    fn set_fan_speed(&mut self, index: u32, fan: u32, speed: u32) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceSetFanSpeed_v2);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceSetFanSpeed_v2(handle, fan, speed))
+        })
+    }
+
+    fn set_default_fan_speed(&mut self, index: u32, fan: u32) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceSetDefaultFanSpeed_v2);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceSetDefaultFanSpeed_v2(handle, fan))
+        })
+    }
+
+    fn supported_memory_clocks(&self, index: u32) -> Result<Vec<u32>, GpuError> {
+        Ok(self.device(index)?.supported_memory_clocks()?)
+    }
+
+    fn supported_graphics_clocks(&self, index: u32, mem_clock: u32) -> Result<Vec<u32>, GpuError> {
+        Ok(self.device(index)?.supported_graphics_clocks(mem_clock)?)
+    }
+
+    fn set_gpu_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceSetGpuLockedClocks);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceSetGpuLockedClocks(handle, lock.min_mhz, lock.max_mhz))
+        })
+    }
+
+    fn set_mem_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceSetMemoryLockedClocks);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceSetMemoryLockedClocks(handle, lock.min_mhz, lock.max_mhz))
+        })
+    }
+
+    fn reset_gpu_locked_clocks(&mut self, index: u32) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceResetGpuLockedClocks);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceResetGpuLockedClocks(handle))
+        })
+    }
+
+    fn reset_mem_locked_clocks(&mut self, index: u32) -> Result<(), GpuError> {
+        require!(self.lib, nvmlDeviceResetMemoryLockedClocks);
+        self.with_raw(index, |nvml_lib, handle| unsafe {
+            nvml_try(nvml_lib.nvmlDeviceResetMemoryLockedClocks(handle))
+        })
+    }
+
+    // NVML counts its initializations, so a second instance is a separate
+    // handle on the same driver
+    fn reader(&self) -> Result<Box<dyn GpuBackend>, GpuError> {
+        Ok(Box::new(NvmlBackend::new()?))
+    }
+
+    fn requires_root(&self) -> bool {
+        true
+    }
+}
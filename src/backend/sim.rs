@@ -0,0 +1,406 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::Clock;
+
+use super::{
+    ClockLock, FanControlPolicy, GpuBackend, MemoryInfo, OffsetRange, PowerConstraints, Utilization,
+};
+use crate::error::GpuError;
+
+// number of refreshes it takes the simulated load to go from idle to full and back
+const LOAD_PERIOD: u64 = 200;
+
+// how far apart the load waves of neighbouring simulated gpus are
+const DEVICE_PHASE: u64 = 37;
+
+const MEM_TOTAL_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+
+// every simulated gpu has two fans
+const FAN_COUNT: u32 = 2;
+const FAN_MAX_RPM: u32 = 3000;
+
+const POWER_CONSTRAINTS: PowerConstraints = PowerConstraints {
+    min_mw: 100_000,
+    max_mw: 300_000,
+    default_mw: 220_000,
+};
+
+// same error the driver reports for out of range settings
+fn invalid_argument() -> GpuError {
+    GpuError::InvalidArgument
+}
+
+fn invalid_index(index: u32) -> GpuError {
+    GpuError::Unknown(format!("no simulated GPU with index {index}"))
+}
+
+// clocks the simulated gpus can be locked to, in MHz
+const SUPPORTED_MEMORY_CLOCKS: [u32; 4] = [7000, 5001, 810, 405];
+const GRAPHICS_CLOCK_MIN: u32 = 300;
+const GRAPHICS_CLOCK_STEP: u32 = 15;
+
+const GPC_OFFSET_RANGE: OffsetRange = OffsetRange {
+    min: -500,
+    max: 1000,
+};
+
+const MEM_OFFSET_RANGE: OffsetRange = OffsetRange {
+    min: -1000,
+    max: 2000,
+};
+
+fn check_range(offset: i32, range: OffsetRange) -> Result<(), GpuError> {
+    if (range.min..=range.max).contains(&offset) {
+        Ok(())
+    } else {
+        Err(invalid_argument())
+    }
+}
+
+// writable state of a single simulated gpu
+#[derive(Clone, Copy)]
+struct SimDevice {
+    gpc_offset: i32,
+    mem_offset: i32,
+    power_limit_mw: u32,
+    // manual speed per fan, None while the fan is on automatic control
+    fan_overrides: [Option<u32>; FAN_COUNT as usize],
+    gpu_lock: Option<ClockLock>,
+    mem_lock: Option<ClockLock>,
+}
+
+impl Default for SimDevice {
+    fn default() -> Self {
+        Self {
+            gpc_offset: 0,
+            mem_offset: 0,
+            power_limit_mw: POWER_CONSTRAINTS.default_mw,
+            fan_overrides: [None; FAN_COUNT as usize],
+            gpu_lock: None,
+            mem_lock: None,
+        }
+    }
+}
+
+// deterministic fake gpus so the app can run on machines without a driver.
+// every value is derived from the refresh counter and the device index, so
+// two runs always see the same sequence of readings. Readers made with
+// reader() share the state, like two handles to the same driver
+pub struct SimBackend {
+    tick: Arc<AtomicU64>,
+    devices: Arc<Mutex<Vec<SimDevice>>>,
+}
+
+impl SimBackend {
+    pub fn new(device_count: u32) -> Self {
+        Self {
+            tick: Arc::new(AtomicU64::new(0)),
+            devices: Arc::new(Mutex::new(vec![
+                SimDevice::default();
+                device_count as usize
+            ])),
+        }
+    }
+
+    fn devices(&self) -> MutexGuard<'_, Vec<SimDevice>> {
+        self.devices.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn device(&self, index: u32) -> Result<SimDevice, GpuError> {
+        self.devices()
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| invalid_index(index))
+    }
+
+    fn update_device<T>(
+        &mut self,
+        index: u32,
+        update: impl FnOnce(&mut SimDevice) -> T,
+    ) -> Result<T, GpuError> {
+        self.devices()
+            .get_mut(index as usize)
+            .map(update)
+            .ok_or_else(|| invalid_index(index))
+    }
+
+    fn set_fan_override(
+        &mut self,
+        index: u32,
+        fan: u32,
+        fan_override: Option<u32>,
+    ) -> Result<(), GpuError> {
+        self.update_device(index, |device| {
+            let current = device
+                .fan_overrides
+                .get_mut(fan as usize)
+                .ok_or_else(invalid_argument)?;
+            *current = fan_override;
+            Ok(())
+        })?
+    }
+
+    // simulated load in percent, a triangle wave over LOAD_PERIOD refreshes
+    fn load(&self, index: u32) -> u32 {
+        let phase = (self.tick.load(Ordering::Relaxed) + index as u64 * DEVICE_PHASE) % LOAD_PERIOD;
+        let half = LOAD_PERIOD / 2;
+        let rising = if phase < half {
+            phase
+        } else {
+            LOAD_PERIOD - phase
+        };
+
+        (rising * 100 / half) as u32
+    }
+
+    fn fan_override(&self, index: u32, fan: u32) -> Result<Option<u32>, GpuError> {
+        self.device(index)?
+            .fan_overrides
+            .get(fan as usize)
+            .copied()
+            .ok_or_else(invalid_argument)
+    }
+
+    fn base_max_clock(clock: &Clock) -> u32 {
+        match clock {
+            Clock::Graphics | Clock::SM => 2100,
+            Clock::Memory => 7000,
+            Clock::Video => 1950,
+        }
+    }
+
+    fn lock_for(device: &SimDevice, clock: &Clock) -> Option<ClockLock> {
+        match clock {
+            Clock::Graphics | Clock::SM => device.gpu_lock,
+            Clock::Memory => device.mem_lock,
+            Clock::Video => None,
+        }
+    }
+
+    fn offset_for(device: &SimDevice, clock: &Clock) -> i32 {
+        match clock {
+            Clock::Graphics | Clock::SM => device.gpc_offset,
+            Clock::Memory => device.mem_offset,
+            Clock::Video => 0,
+        }
+    }
+}
+
+impl GpuBackend for SimBackend {
+    fn refresh(&mut self) {
+        self.tick.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn device_count(&self) -> Result<u32, GpuError> {
+        Ok(self.devices().len() as u32)
+    }
+
+    fn name(&self, index: u32) -> Result<String, GpuError> {
+        self.device(index)?;
+        Ok(format!("Simulated GPU {index}"))
+    }
+
+    fn driver_version(&self) -> Result<String, GpuError> {
+        Ok(String::from("0.0 (simulated)"))
+    }
+
+    fn power_usage(&self, index: u32) -> Result<u32, GpuError> {
+        // the draw gets capped by the power limit like on a real card
+        let limit = self.device(index)?.power_limit_mw;
+        Ok((25_000 + self.load(index) * 2_000).min(limit))
+    }
+
+    fn clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError> {
+        let max = self.max_clock_info(index, clock.clone())?;
+        let idle = max / 5;
+        let current = idle + (max - idle) * self.load(index) / 100;
+
+        // a locked clock stays within its range whatever the load
+        Ok(match Self::lock_for(&self.device(index)?, &clock) {
+            Some(lock) => current.clamp(lock.min_mhz, lock.max_mhz),
+            None => current,
+        })
+    }
+
+    fn max_clock_info(&self, index: u32, clock: Clock) -> Result<u32, GpuError> {
+        let device = self.device(index)?;
+        let max = Self::base_max_clock(&clock) as i32 + Self::offset_for(&device, &clock);
+
+        Ok(max.max(0) as u32)
+    }
+
+    fn temperature(&self, index: u32) -> Result<u32, GpuError> {
+        self.device(index)?;
+        Ok(35 + self.load(index) * 45 / 100)
+    }
+
+    fn memory_info(&self, index: u32) -> Result<MemoryInfo, GpuError> {
+        self.device(index)?;
+        let used = MEM_TOTAL_BYTES / 16 + MEM_TOTAL_BYTES / 2 * self.load(index) as u64 / 100;
+
+        Ok(MemoryInfo {
+            free: MEM_TOTAL_BYTES - used,
+            total: MEM_TOTAL_BYTES,
+            used,
+        })
+    }
+
+    fn num_fans(&self, index: u32) -> Result<u32, GpuError> {
+        self.device(index)?;
+        Ok(FAN_COUNT)
+    }
+
+    fn fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        let fan_override = self.fan_override(index, fan)?;
+        Ok(fan_override.unwrap_or(30 + self.load(index) * 50 / 100))
+    }
+
+    fn fan_speed_rpm(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        Ok(self.fan_speed(index, fan)? * FAN_MAX_RPM / 100)
+    }
+
+    // simulated fans reach their target straight away
+    fn target_fan_speed(&self, index: u32, fan: u32) -> Result<u32, GpuError> {
+        self.fan_speed(index, fan)
+    }
+
+    fn fan_control_policy(&self, index: u32, fan: u32) -> Result<FanControlPolicy, GpuError> {
+        Ok(match self.fan_override(index, fan)? {
+            Some(_) => FanControlPolicy::Manual,
+            None => FanControlPolicy::Auto,
+        })
+    }
+
+    fn utilization_rates(&self, index: u32) -> Result<Utilization, GpuError> {
+        self.device(index)?;
+        Ok(Utilization {
+            gpu: self.load(index),
+            memory: self.load(index) * 3 / 4,
+        })
+    }
+
+    // idle with no load and power capped once the draw reaches the limit
+    fn throttle_reasons(&self, index: u32) -> Result<ThrottleReasons, GpuError> {
+        let mut reasons = ThrottleReasons::empty();
+        if self.load(index) == 0 {
+            reasons |= ThrottleReasons::GPU_IDLE;
+        }
+        if self.power_usage(index)? >= self.device(index)?.power_limit_mw {
+            reasons |= ThrottleReasons::SW_POWER_CAP;
+        }
+
+        Ok(reasons)
+    }
+
+    fn gpc_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError> {
+        Ok(self.device(index)?.gpc_offset)
+    }
+
+    fn mem_clk_vf_offset(&self, index: u32) -> Result<i32, GpuError> {
+        Ok(self.device(index)?.mem_offset)
+    }
+
+    fn gpc_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError> {
+        self.device(index)?;
+        Ok(GPC_OFFSET_RANGE)
+    }
+
+    fn mem_clk_vf_offset_range(&self, index: u32) -> Result<OffsetRange, GpuError> {
+        self.device(index)?;
+        Ok(MEM_OFFSET_RANGE)
+    }
+
+    fn set_gpc_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError> {
+        check_range(offset, GPC_OFFSET_RANGE)?;
+        self.update_device(index, |device| device.gpc_offset = offset)
+    }
+
+    fn set_mem_clk_vf_offset(&mut self, index: u32, offset: i32) -> Result<(), GpuError> {
+        check_range(offset, MEM_OFFSET_RANGE)?;
+        self.update_device(index, |device| device.mem_offset = offset)
+    }
+
+    fn power_constraints(&self, index: u32) -> Result<PowerConstraints, GpuError> {
+        self.device(index)?;
+        Ok(POWER_CONSTRAINTS)
+    }
+
+    fn enforced_power_limit(&self, index: u32) -> Result<u32, GpuError> {
+        Ok(self.device(index)?.power_limit_mw)
+    }
+
+    fn set_power_limit(&mut self, index: u32, limit_mw: u32) -> Result<(), GpuError> {
+        if !(POWER_CONSTRAINTS.min_mw..=POWER_CONSTRAINTS.max_mw).contains(&limit_mw) {
+            return Err(invalid_argument());
+        }
+
+        self.update_device(index, |device| device.power_limit_mw = limit_mw)
+    }
+
+    fn set_fan_speed(&mut self, index: u32, fan: u32, speed: u32) -> Result<(), GpuError> {
+        if speed > 100 {
+            return Err(invalid_argument());
+        }
+
+        self.set_fan_override(index, fan, Some(speed))
+    }
+
+    fn set_default_fan_speed(&mut self, index: u32, fan: u32) -> Result<(), GpuError> {
+        self.set_fan_override(index, fan, None)
+    }
+
+    fn supported_memory_clocks(&self, index: u32) -> Result<Vec<u32>, GpuError> {
+        self.device(index)?;
+        Ok(SUPPORTED_MEMORY_CLOCKS.to_vec())
+    }
+
+    fn supported_graphics_clocks(&self, index: u32, mem_clock: u32) -> Result<Vec<u32>, GpuError> {
+        self.device(index)?;
+        if !SUPPORTED_MEMORY_CLOCKS.contains(&mem_clock) {
+            return Err(invalid_argument());
+        }
+
+        let max = Self::base_max_clock(&Clock::Graphics);
+        Ok((GRAPHICS_CLOCK_MIN..=max)
+            .rev()
+            .step_by(GRAPHICS_CLOCK_STEP as usize)
+            .collect())
+    }
+
+    fn set_gpu_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError> {
+        if lock.min_mhz > lock.max_mhz {
+            return Err(invalid_argument());
+        }
+
+        self.update_device(index, |device| device.gpu_lock = Some(lock))
+    }
+
+    fn set_mem_locked_clocks(&mut self, index: u32, lock: ClockLock) -> Result<(), GpuError> {
+        if lock.min_mhz > lock.max_mhz {
+            return Err(invalid_argument());
+        }
+
+        self.update_device(index, |device| device.mem_lock = Some(lock))
+    }
+
+    fn reset_gpu_locked_clocks(&mut self, index: u32) -> Result<(), GpuError> {
+        self.update_device(index, |device| device.gpu_lock = None)
+    }
+
+    fn reset_mem_locked_clocks(&mut self, index: u32) -> Result<(), GpuError> {
+        self.update_device(index, |device| device.mem_lock = None)
+    }
+
+    fn reader(&self) -> Result<Box<dyn GpuBackend>, GpuError> {
+        Ok(Box::new(Self {
+            tick: Arc::clone(&self.tick),
+            devices: Arc::clone(&self.devices),
+        }))
+    }
+
+    fn requires_root(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,286 @@
+use iced::widget::canvas::{self, Frame, Geometry, Path, Stroke};
+use iced::widget::{Row, column, text};
+use iced::{Color, Element, Fill, Pixels, Point, Rectangle, Renderer, Size, Theme, mouse};
+
+use crate::history::{History, HistoryPoint, HistoryWindow, age};
+use crate::telemetry::CLOCK_LABELS;
+
+const CHART_HEIGHT: f32 = 110.0;
+const LABEL_SIZE: f32 = 12.0;
+
+// line colors, picked to stay readable on both the light and dark themes
+const SERIES_COLORS: [Color; 4] = [
+    Color::from_rgb(0.26, 0.52, 0.96),
+    Color::from_rgb(0.20, 0.66, 0.33),
+    Color::from_rgb(0.98, 0.55, 0.00),
+    Color::from_rgb(0.67, 0.28, 0.74),
+];
+
+// one line on a chart
+pub struct Series {
+    pub label: &'static str,
+    pub value: fn(&HistoryPoint) -> Option<f32>,
+}
+
+// a chart and the lines drawn on it
+pub struct ChartSpec {
+    pub title: &'static str,
+    pub unit: &'static str,
+    // decimals shown in the legend
+    pub precision: usize,
+    // top of the y axis, None to fit the data
+    pub max: Option<f32>,
+    pub series: &'static [Series],
+}
+
+pub const CHARTS: [ChartSpec; 5] = [
+    ChartSpec {
+        title: "Temp",
+        unit: "°C",
+        precision: 0,
+        max: None,
+        series: &[Series {
+            label: "Core",
+            value: |point| point.temperature_c,
+        }],
+    },
+    ChartSpec {
+        title: "Power",
+        unit: "W",
+        precision: 1,
+        max: None,
+        series: &[Series {
+            label: "Draw",
+            value: |point| point.power_watts,
+        }],
+    },
+    ChartSpec {
+        title: "Clocks",
+        unit: "MHz",
+        precision: 0,
+        max: None,
+        series: &[
+            Series {
+                label: CLOCK_LABELS[0],
+                value: |point| point.clocks_mhz[0],
+            },
+            Series {
+                label: CLOCK_LABELS[1],
+                value: |point| point.clocks_mhz[1],
+            },
+            Series {
+                label: CLOCK_LABELS[2],
+                value: |point| point.clocks_mhz[2],
+            },
+            Series {
+                label: CLOCK_LABELS[3],
+                value: |point| point.clocks_mhz[3],
+            },
+        ],
+    },
+    ChartSpec {
+        title: "Use",
+        unit: "%",
+        precision: 0,
+        max: Some(100.0),
+        series: &[
+            Series {
+                label: "GPU",
+                value: |point| point.gpu_utilization_percent,
+            },
+            Series {
+                label: "Memory",
+                value: |point| point.mem_utilization_percent,
+            },
+        ],
+    },
+    ChartSpec {
+        title: "Fan",
+        unit: "%",
+        precision: 0,
+        max: Some(100.0),
+        series: &[Series {
+            label: "Fan 0",
+            value: |point| point.fan_speed_percent,
+        }],
+    },
+];
+
+// a chart with a legend showing the latest values above it
+pub fn view<'a, Message: 'a>(
+    spec: &'a ChartSpec,
+    history: &'a History,
+    window: HistoryWindow,
+) -> Element<'a, Message> {
+    let latest = history.latest();
+
+    let mut legend = Row::new()
+        .push(text(spec.title).size(crate::FONT_SIZE_SM).width(100))
+        .spacing(12);
+
+    for (series, color) in spec.series.iter().zip(SERIES_COLORS) {
+        let value = latest
+            .and_then(series.value)
+            .map_or(String::from("N/A"), |value| {
+                format!(
+                    "{value:.precision$} {}",
+                    spec.unit,
+                    precision = spec.precision
+                )
+            });
+
+        legend = legend.push(
+            text(format!("{} {value}", series.label))
+                .size(crate::FONT_SIZE_SM)
+                .color(color),
+        );
+    }
+
+    column![
+        legend,
+        canvas::Canvas::new(Plot {
+            spec,
+            history,
+            window,
+        })
+        .width(Fill)
+        .height(CHART_HEIGHT)
+    ]
+    .spacing(4)
+    .into()
+}
+
+// draws the lines of one chart, newest point on the right edge
+struct Plot<'a> {
+    spec: &'a ChartSpec,
+    history: &'a History,
+    window: HistoryWindow,
+}
+
+impl Plot<'_> {
+    // top of the y axis
+    fn max(&self) -> f32 {
+        let max = self.spec.max.unwrap_or_else(|| {
+            let highest = self
+                .history
+                .window(self.window)
+                .flat_map(|point| {
+                    self.spec
+                        .series
+                        .iter()
+                        .filter_map(|series| (series.value)(point))
+                })
+                .fold(0.0, f32::max);
+
+            // leave some headroom over the highest value
+            highest * 1.1
+        });
+
+        max.max(1.0)
+    }
+
+    // the line for one series. Samples are averaged into one point per pixel
+    // column so an hour of history does not turn into thousands of segments,
+    // and readings that failed leave a gap
+    fn line(&self, series: &Series, size: Size, max: f32) -> Path {
+        let Some(newest) = self.history.latest().map(|point| point.timestamp) else {
+            return Path::new(|_| {});
+        };
+        let window = self.window.duration().as_secs_f32();
+
+        let to_point = |column: i32, sum: f32, count: f32| {
+            Point::new(column as f32, size.height * (1.0 - sum / count / max))
+        };
+
+        let mut points = Vec::new();
+        // pixel column, sum and count of the values in it
+        let mut current: Option<(i32, f32, f32)> = None;
+
+        for point in self.history.window(self.window) {
+            let x = size.width * (1.0 - age(newest, point.timestamp).as_secs_f32() / window);
+            let column = x.floor() as i32;
+
+            match ((series.value)(point), &mut current) {
+                (Some(value), Some((current_column, sum, count))) if *current_column == column => {
+                    *sum += value;
+                    *count += 1.0;
+                }
+                (value, _) => {
+                    if let Some((column, sum, count)) = current.take() {
+                        points.push(Some(to_point(column, sum, count)));
+                    }
+
+                    match value {
+                        Some(value) => current = Some((column, value, 1.0)),
+                        None => points.push(None),
+                    }
+                }
+            }
+        }
+
+        if let Some((column, sum, count)) = current {
+            points.push(Some(to_point(column, sum, count)));
+        }
+
+        Path::new(|builder| {
+            let mut pen_down = false;
+            for point in points {
+                match point {
+                    Some(point) if pen_down => builder.line_to(point),
+                    Some(point) => {
+                        builder.move_to(point);
+                        pen_down = true;
+                    }
+                    None => pen_down = false,
+                }
+            }
+        })
+    }
+}
+
+impl<Message> canvas::Program<Message> for Plot<'_> {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let palette = theme.extended_palette();
+        let size = frame.size();
+        let max = self.max();
+
+        frame.fill_rectangle(Point::ORIGIN, size, palette.background.weak.color);
+
+        // grid lines at every quarter of the y axis
+        let grid = Stroke::default()
+            .with_color(palette.background.strong.color)
+            .with_width(1.0);
+        for quarter in 1..4 {
+            let y = size.height * quarter as f32 / 4.0;
+            frame.stroke(
+                &Path::line(Point::new(0.0, y), Point::new(size.width, y)),
+                grid,
+            );
+        }
+
+        frame.fill_text(canvas::Text {
+            content: format!("{max:.0} {}", self.spec.unit),
+            position: Point::new(4.0, 2.0),
+            color: palette.background.weak.text,
+            size: Pixels(LABEL_SIZE),
+            ..canvas::Text::default()
+        });
+
+        for (series, color) in self.spec.series.iter().zip(SERIES_COLORS) {
+            let line = self.line(series, size, max);
+            frame.stroke(&line, Stroke::default().with_color(color).with_width(2.0));
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
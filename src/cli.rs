@@ -0,0 +1,1368 @@
+use std::io::{self, BufRead, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{Args, Parser, Subcommand};
+use nvml_wrapper::enum_wrappers::device::Clock;
+use serde_json::{Value, json};
+
+use crate::autotune::{self, TuneSettings};
+use crate::backend::{self, BackendKind, ClockLock};
+use crate::error::GpuError;
+use crate::exporter;
+use crate::format::{
+    clock_lock_text, fan_text, mem_usage_text, metric_text, metric_text_with, power_text,
+    supported_clocks_text, throttle_text,
+};
+use crate::gpu::{DeviceState, Gpu, LockedClock};
+use crate::helper::{self, Helper, HelperPolicy};
+use crate::profile::{self, FanPolicy, Profile, ProfileStore};
+use crate::recorder::{self, RecordFormat, Recorder, Rotation};
+use crate::rollback::Rollback;
+use crate::service::{self, RestoreUnit};
+use crate::telemetry::CLOCK_LABELS;
+
+// exit codes, also listed in the --help output
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_NO_GPU: i32 = 3;
+pub const EXIT_PERMISSION: i32 = 4;
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  success
+  1  reading from or writing to the GPU failed
+  2  invalid arguments
+  3  no GPU backend could be initialized
+  4  not allowed to change GPU settings (run as root)";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "nvidia-tweaker",
+    version,
+    about = "Monitor and tune NVIDIA GPUs. Starts the GUI when no command is given.",
+    after_help = EXIT_CODES_HELP
+)]
+pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        value_parser = parse_backend,
+        help = "GPU backend to use: nvml or sim. Defaults to $NVIDIA_TWEAKER_BACKEND, then nvml"
+    )]
+    pub backend: Option<BackendKind>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Print the current readings and settings")]
+    Status {
+        #[command(flatten)]
+        devices: DeviceArgs,
+        #[arg(long, help = "Print JSON instead of text")]
+        json: bool,
+    },
+    #[command(about = "Change clock offsets, locked clocks, the power limit or the fan speed")]
+    Set(SetArgs),
+    #[command(
+        about = "Put offsets, the power limit, fans and locked clocks back to their defaults"
+    )]
+    Reset {
+        #[command(flatten)]
+        devices: DeviceArgs,
+    },
+    #[command(
+        about = "Find stable offsets by stepping them up until a step fails",
+        long_about = "Find stable offsets by stepping the core and then the memory offset up \
+until a step fails, then save the last stable offsets minus a safety margin as a profile. \
+A step fails when the validation command fails or hangs, the driver logs an Xid error, \
+or the GPU starts thermal or hardware throttling. The command gets the offsets under \
+test in $NVIDIA_TWEAKER_CORE_OFFSET and $NVIDIA_TWEAKER_MEM_OFFSET."
+    )]
+    Autotune {
+        #[command(flatten)]
+        devices: DeviceArgs,
+        #[arg(
+            long,
+            value_name = "CMD",
+            help = "Shell command that has to succeed for a step to pass, such as a stress test"
+        )]
+        command: Option<String>,
+        #[arg(
+            long,
+            value_name = "MHZ",
+            default_value_t = 15,
+            help = "Core offset step, 0 to leave the core offset alone"
+        )]
+        core_step: i32,
+        #[arg(
+            long,
+            value_name = "MHZ",
+            default_value_t = 50,
+            help = "Memory offset step, 0 to leave the memory offset alone"
+        )]
+        mem_step: i32,
+        #[arg(
+            long,
+            value_name = "MHZ",
+            help = "Highest core offset to try, defaults to the top of the supported range"
+        )]
+        core_max: Option<i32>,
+        #[arg(
+            long,
+            value_name = "MHZ",
+            help = "Highest memory offset to try, defaults to the top of the supported range"
+        )]
+        mem_max: Option<i32>,
+        #[arg(
+            long,
+            value_name = "MHZ",
+            default_value_t = 30,
+            help = "Taken off the highest stable core offset"
+        )]
+        core_margin: i32,
+        #[arg(
+            long,
+            value_name = "MHZ",
+            default_value_t = 100,
+            help = "Taken off the highest stable memory offset"
+        )]
+        mem_margin: i32,
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 60,
+            help = "How long each step is watched for when there is no command"
+        )]
+        duration: u64,
+        #[arg(
+            long,
+            value_name = "SECS",
+            default_value_t = 600,
+            help = "How long the command may run before the step counts as hung"
+        )]
+        timeout: u64,
+        #[arg(
+            long,
+            value_name = "CELSIUS",
+            help = "Fail a step above this temperature"
+        )]
+        max_temp: Option<u32>,
+        #[arg(
+            long,
+            value_name = "NAME",
+            default_value = "autotune",
+            help = "Profile to save the result as, replacing one with the same name"
+        )]
+        profile: String,
+    },
+    #[command(about = "Manage saved profiles")]
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+    #[command(about = "Apply a saved profile without any prompts, as done at boot")]
+    Restore {
+        profile: String,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Profile file to read instead of the one in the config directory"
+        )]
+        profiles: Option<PathBuf>,
+        #[command(flatten)]
+        devices: DeviceArgs,
+    },
+    #[command(about = "Install or remove the systemd unit that restores a profile at boot")]
+    Service {
+        #[command(subcommand)]
+        command: ServiceCommand,
+    },
+    #[command(about = "Run the privileged helper that makes changes for unprivileged clients")]
+    Helper {
+        #[arg(long, value_name = "PATH", default_value = helper::DEFAULT_SOCKET)]
+        socket: PathBuf,
+        #[arg(
+            long,
+            value_name = "PATH",
+            default_value = helper::DEFAULT_POLICY_PATH,
+            help = "Allow-list limiting what clients may change"
+        )]
+        policy: PathBuf,
+        #[arg(long, help = "Group allowed to connect to the socket")]
+        group: Option<String>,
+    },
+    #[command(about = "Write readings to a CSV or JSON Lines file until interrupted")]
+    Record {
+        output: PathBuf,
+        #[command(flatten)]
+        devices: DeviceArgs,
+        #[arg(
+            long,
+            value_parser = parse_record_format,
+            help = "csv or jsonl, guessed from the file name when not given"
+        )]
+        format: Option<RecordFormat>,
+        #[arg(
+            long,
+            value_name = "MS",
+            default_value_t = 1000,
+            help = "Time between samples"
+        )]
+        interval: u64,
+        #[arg(long, help = "Stop after this many samples")]
+        count: Option<u64>,
+        #[arg(
+            long,
+            value_name = "MIB",
+            help = "Start a new file once the current one reaches this size"
+        )]
+        max_size: Option<u64>,
+        #[arg(
+            long,
+            requires = "max_size",
+            default_value_t = recorder::DEFAULT_MAX_FILES,
+            help = "Number of old files to keep when rotating"
+        )]
+        max_files: usize,
+    },
+    #[command(about = "Serve readings over HTTP for Prometheus to scrape")]
+    Exporter {
+        #[arg(
+            long,
+            value_name = "ADDR",
+            default_value = exporter::DEFAULT_LISTEN,
+            help = "Address and port to listen on, use 0.0.0.0:9842 to allow scrapes from other machines"
+        )]
+        listen: String,
+    },
+    #[command(about = "Keep printing readings until interrupted")]
+    Watch {
+        #[command(flatten)]
+        devices: DeviceArgs,
+        #[arg(
+            long,
+            value_name = "MS",
+            default_value_t = 1000,
+            help = "Time between samples"
+        )]
+        interval: u64,
+        #[arg(long, help = "Stop after this many samples")]
+        count: Option<u64>,
+        #[arg(long, help = "Print one JSON object per device and sample")]
+        json: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ProfileCommand {
+    #[command(about = "List the saved profiles")]
+    List {
+        #[arg(long, help = "Print JSON instead of text")]
+        json: bool,
+    },
+    #[command(about = "Apply a saved profile")]
+    Apply {
+        name: String,
+        #[command(flatten)]
+        devices: DeviceArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ServiceCommand {
+    #[command(about = "Write a unit that restores the profile at boot")]
+    Install {
+        profile: String,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Profile file the unit reads, defaults to the one in the config directory"
+        )]
+        profiles: Option<PathBuf>,
+        #[command(flatten)]
+        devices: DeviceArgs,
+        #[arg(
+            long,
+            value_name = "DIR",
+            default_value = "/",
+            help = "Install under this directory instead of /"
+        )]
+        root: PathBuf,
+    },
+    #[command(about = "Remove the unit written by install")]
+    Uninstall {
+        #[arg(
+            long,
+            value_name = "DIR",
+            default_value = "/",
+            help = "Remove from under this directory instead of /"
+        )]
+        root: PathBuf,
+    },
+}
+
+// what the set command changes
+#[derive(Debug, Args)]
+pub struct SetArgs {
+    #[command(flatten)]
+    devices: DeviceArgs,
+    #[arg(long, value_name = "MHZ", allow_negative_numbers = true)]
+    core_offset: Option<i32>,
+    #[arg(long, value_name = "MHZ", allow_negative_numbers = true)]
+    mem_offset: Option<i32>,
+    #[arg(
+        long,
+        value_name = "MIN-MAX",
+        value_parser = parse_clock_lock,
+        help = "Keep the graphics clock within a range, or at a single clock"
+    )]
+    lock_gpu_clocks: Option<ClockLock>,
+    #[arg(
+        long,
+        value_name = "MIN-MAX",
+        value_parser = parse_clock_lock,
+        help = "Keep the memory clock within a range, or at a single clock"
+    )]
+    lock_mem_clocks: Option<ClockLock>,
+    #[arg(
+        long,
+        conflicts_with_all = ["lock_gpu_clocks", "lock_mem_clocks"],
+        help = "Let the driver pick the graphics and memory clocks again"
+    )]
+    unlock_clocks: bool,
+    #[arg(long, value_name = "WATTS")]
+    power_limit: Option<f64>,
+    #[arg(long, value_name = "auto|PERCENT", value_parser = profile::parse_fan_policy)]
+    fan: Option<FanPolicy>,
+    #[arg(
+        long,
+        value_name = "SECS",
+        help = "Put the old offsets back unless the change is confirmed within this time"
+    )]
+    confirm_timeout: Option<u64>,
+}
+
+// which gpus a command works on
+#[derive(Debug, Args)]
+pub struct DeviceArgs {
+    #[arg(
+        short,
+        long = "device",
+        value_name = "INDEX",
+        help = "GPU to use, can be given more than once"
+    )]
+    devices: Vec<u32>,
+    #[arg(long, conflicts_with = "devices", help = "Use every GPU")]
+    all: bool,
+}
+
+// an error to print along with the exit code to return
+#[derive(Debug)]
+struct CliError {
+    code: i32,
+    message: String,
+}
+
+impl CliError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn usage(message: impl Into<String>) -> Self {
+        Self::new(EXIT_USAGE, message)
+    }
+
+    fn failure(message: impl Into<String>) -> Self {
+        Self::new(EXIT_FAILURE, message)
+    }
+}
+
+impl From<GpuError> for CliError {
+    fn from(error: GpuError) -> Self {
+        let code = match error {
+            GpuError::NoPermission => EXIT_PERMISSION,
+            GpuError::DriverNotLoaded | GpuError::LibraryNotFound(_) => EXIT_NO_GPU,
+            _ => EXIT_FAILURE,
+        };
+
+        Self::new(code, error.to_string())
+    }
+}
+
+impl DeviceArgs {
+    // the device indices to use. Without any flags commands that only read
+    // use every gpu while commands that write only touch the first one
+    fn resolve(&self, gpu: &Gpu, default_all: bool) -> Result<Vec<u32>, CliError> {
+        let all: Vec<u32> = gpu.devices.iter().map(|device| device.index).collect();
+
+        if self.all || (self.devices.is_empty() && default_all) {
+            return Ok(all);
+        }
+
+        if self.devices.is_empty() {
+            return all
+                .first()
+                .map(|index| vec![*index])
+                .ok_or_else(|| CliError::new(EXIT_NO_GPU, "no GPUs found"));
+        }
+
+        for index in &self.devices {
+            if gpu.device(*index).is_none() {
+                return Err(CliError::usage(format!(
+                    "there is no GPU with index {index}"
+                )));
+            }
+        }
+
+        Ok(self.devices.clone())
+    }
+
+    // the same selection as command line arguments, to pass on to the
+    // restore command
+    fn to_args(&self) -> Vec<String> {
+        if self.all {
+            return vec![String::from("--all")];
+        }
+
+        self.devices
+            .iter()
+            .flat_map(|index| [String::from("--device"), index.to_string()])
+            .collect()
+    }
+}
+
+fn parse_record_format(value: &str) -> Result<RecordFormat, String> {
+    RecordFormat::parse(value)
+        .ok_or_else(|| format!("unknown format \"{value}\", use csv or jsonl"))
+}
+
+// a clock range given as MIN-MAX, or a single clock to stay at
+fn parse_clock_lock(value: &str) -> Result<ClockLock, String> {
+    let parse = |mhz: &str| {
+        mhz.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("\"{}\" is not a clock in MHz", mhz.trim()))
+    };
+
+    let (min_mhz, max_mhz) = match value.split_once('-') {
+        Some((min, max)) => (parse(min)?, parse(max)?),
+        None => (parse(value)?, parse(value)?),
+    };
+
+    Ok(ClockLock { min_mhz, max_mhz })
+}
+
+fn parse_backend(value: &str) -> Result<BackendKind, String> {
+    BackendKind::parse(value).ok_or_else(|| format!("unknown backend \"{value}\", use nvml or sim"))
+}
+
+// run a command line command, returning the exit code
+pub fn run(command: Command, backend: BackendKind) -> i32 {
+    let result = match command {
+        Command::Status { devices, json } => {
+            open_gpu(backend).and_then(|mut gpu| status(&mut gpu, &devices, json))
+        }
+        Command::Set(args) => open_gpu(backend).and_then(|mut gpu| {
+            // only a confirmation prompt has anything to put back
+            let interrupted = match args.confirm_timeout {
+                Some(_) => catch_interrupts()?,
+                None => Arc::default(),
+            };
+            set(
+                &mut gpu,
+                &args,
+                io::BufReader::new(io::stdin()),
+                &interrupted,
+            )
+        }),
+        Command::Reset { devices } => {
+            open_gpu(backend).and_then(|mut gpu| reset(&mut gpu, &devices))
+        }
+        Command::Autotune {
+            devices,
+            command,
+            core_step,
+            mem_step,
+            core_max,
+            mem_max,
+            core_margin,
+            mem_margin,
+            duration,
+            timeout,
+            max_temp,
+            profile,
+        } => open_gpu(backend).and_then(|mut gpu| {
+            let settings = TuneSettings {
+                core_step,
+                mem_step,
+                core_max,
+                mem_max,
+                core_margin,
+                mem_margin,
+                command,
+                duration: Duration::from_secs(duration),
+                timeout: Duration::from_secs(timeout),
+                max_temp_c: max_temp,
+            };
+            autotune(&mut gpu, &devices, &settings, &profile)
+        }),
+        Command::Profile { command } => {
+            open_gpu(backend).and_then(|mut gpu| profile_command(&mut gpu, command))
+        }
+        Command::Restore {
+            profile,
+            profiles,
+            devices,
+        } => restore(backend, &profile, profiles, &devices),
+        Command::Service { command } => service_command(command),
+        Command::Helper {
+            socket,
+            policy,
+            group,
+        } => run_helper(backend, &socket, &policy, group.as_deref()),
+        Command::Record {
+            output,
+            devices,
+            format,
+            interval,
+            count,
+            max_size,
+            max_files,
+        } => open_gpu(backend).and_then(|mut gpu| {
+            let format = format.unwrap_or_else(|| RecordFormat::from_path(&output));
+            let rotation = max_size.map(|max_mib| Rotation::from_mib(max_mib, max_files));
+            record(
+                &mut gpu, &output, &devices, format, rotation, interval, count,
+            )
+        }),
+        Command::Exporter { listen } => {
+            open_gpu(backend).and_then(|mut gpu| run_exporter(&mut gpu, &listen))
+        }
+        Command::Watch {
+            devices,
+            interval,
+            count,
+            json,
+        } => open_gpu(backend).and_then(|mut gpu| watch(&mut gpu, &devices, interval, count, json)),
+    };
+
+    match result {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("error: {}", e.message);
+            e.code
+        }
+    }
+}
+
+fn open_gpu(backend: BackendKind) -> Result<Gpu, CliError> {
+    Gpu::new(backend).map_err(|e| {
+        CliError::new(
+            EXIT_NO_GPU,
+            format!("failed to initialize the GPU backend: {e}"),
+        )
+    })
+}
+
+fn status(gpu: &mut Gpu, devices: &DeviceArgs, json: bool) -> Result<(), CliError> {
+    let indices = devices.resolve(gpu, true)?;
+    gpu.update_gpu_info();
+
+    let driver_version = gpu.get_driver_version();
+
+    if json {
+        let devices: Vec<Value> = indices
+            .iter()
+            .filter_map(|index| gpu.device(*index))
+            .map(device_json)
+            .collect();
+
+        let status = json!({
+            "driver_version": driver_version.ok(),
+            "devices": devices,
+        });
+        println!("{status:#}");
+        return Ok(());
+    }
+
+    println!("Driver {}", metric_text(&driver_version));
+    for device in indices.iter().filter_map(|index| gpu.device(*index)) {
+        print_device(device);
+    }
+
+    Ok(())
+}
+
+// the readings and offsets of a device as JSON, all from the same sample
+fn device_json(device: &DeviceState) -> Value {
+    let mut value = device.sample.to_json();
+
+    value["name"] = device.name.as_ref().ok().cloned().into();
+    value["core_offset_mhz"] = device.core_offset.as_ref().ok().copied().into();
+    value["mem_offset_mhz"] = device.mem_offset.as_ref().ok().copied().into();
+    value["supported_graphics_clocks_mhz"] = device.graphics_clocks.as_ref().ok().cloned().into();
+    value["supported_memory_clocks_mhz"] = device.memory_clocks.as_ref().ok().cloned().into();
+
+    value
+}
+
+fn print_device(device: &DeviceState) {
+    let sample = &device.sample;
+    let mhz = |mhz: &u32| format!("{mhz} MHz");
+
+    println!("GPU {}: {}", device.index, metric_text(&device.name));
+    println!(
+        "  {:<12} {} / {}",
+        "Power",
+        power_text(&sample.power_mw),
+        power_text(&sample.power_limit_mw)
+    );
+    println!(
+        "  {:<12} {}",
+        "Temp",
+        metric_text_with(&sample.temperature_c, |temp| format!("{temp} °C"))
+    );
+    match &sample.fans {
+        Ok(fans) if !fans.is_empty() => {
+            for fan in fans {
+                println!("  {:<12} {}", format!("Fan {}", fan.fan), fan_text(fan));
+            }
+        }
+        _ => println!("  {:<12} N/A", "Fan"),
+    }
+    println!("  {:<12} {}", "Memory", mem_usage_text(sample));
+    println!(
+        "  {:<12} {}",
+        "GPU Use",
+        metric_text_with(&sample.gpu_utilization_percent, |use_| format!("{use_} %"))
+    );
+    println!(
+        "  {:<12} {}",
+        "Mem Use",
+        metric_text_with(&sample.mem_utilization_percent, |use_| format!("{use_} %"))
+    );
+    for (index, label) in CLOCK_LABELS.iter().enumerate() {
+        println!(
+            "  {:<12} {} / {}",
+            label,
+            metric_text_with(&sample.clocks_mhz[index], mhz),
+            metric_text_with(&sample.max_clocks_mhz[index], mhz)
+        );
+    }
+    println!(
+        "  {:<12} {}",
+        "Throttle",
+        throttle_text(&sample.throttle_reasons)
+    );
+    println!(
+        "  {:<12} {}",
+        "Core Offset",
+        metric_text_with(&device.core_offset, |offset| format!("{offset} MHz"))
+    );
+    println!(
+        "  {:<12} {}",
+        "Mem Offset",
+        metric_text_with(&device.mem_offset, |offset| format!("{offset} MHz"))
+    );
+    println!(
+        "  {:<12} {}",
+        "Gfx Clocks",
+        supported_clocks_text(&device.graphics_clocks)
+    );
+    println!(
+        "  {:<12} {}",
+        "Mem Clocks",
+        supported_clocks_text(&device.memory_clocks)
+    );
+}
+
+// the answer to a confirmation prompt is read from input, and an interrupt
+// while waiting for it reverts like any answer other than yes
+fn set(
+    gpu: &mut Gpu,
+    args: &SetArgs,
+    input: impl BufRead + Send + 'static,
+    interrupted: &AtomicBool,
+) -> Result<(), CliError> {
+    let &SetArgs {
+        ref devices,
+        core_offset,
+        mem_offset,
+        lock_gpu_clocks,
+        lock_mem_clocks,
+        unlock_clocks,
+        power_limit,
+        fan,
+        confirm_timeout,
+    } = args;
+    let confirm_timeout = confirm_timeout.map(Duration::from_secs);
+
+    if core_offset.is_none()
+        && mem_offset.is_none()
+        && lock_gpu_clocks.is_none()
+        && lock_mem_clocks.is_none()
+        && !unlock_clocks
+        && power_limit.is_none()
+        && fan.is_none()
+    {
+        return Err(CliError::usage(
+            "nothing to set, give at least one of --core-offset, --mem-offset, --lock-gpu-clocks, \
+--lock-mem-clocks, --unlock-clocks, --power-limit or --fan",
+        ));
+    }
+
+    if confirm_timeout.is_some() && core_offset.is_none() && mem_offset.is_none() {
+        return Err(CliError::usage(
+            "--confirm-timeout only applies to --core-offset and --mem-offset",
+        ));
+    }
+
+    let indices = devices.resolve(gpu, false)?;
+
+    // check everything before touching the gpu so a typo does not leave a
+    // half applied set of changes
+    let (core_range, mem_range) = gpu.offset_ranges(&indices);
+    for (offset, range, name) in [
+        (core_offset, core_range, "core"),
+        (mem_offset, mem_range, "memory"),
+    ] {
+        if let (Some(offset), Some(range)) = (offset, range)
+            && !(range.min..=range.max).contains(&offset)
+        {
+            return Err(CliError::usage(format!(
+                "{offset} MHz is outside the supported {name} offset range of {} to {} MHz",
+                range.min, range.max
+            )));
+        }
+    }
+
+    let clock_locks = [
+        (LockedClock::Graphics, lock_gpu_clocks),
+        (LockedClock::Memory, lock_mem_clocks),
+    ];
+    for (clock, lock) in clock_locks {
+        if let Some(lock) = lock {
+            for index in &indices {
+                gpu.validate_clock_lock(*index, clock, lock)
+                    .map_err(|e| CliError::usage(format!("GPU {index}: {e}")))?;
+            }
+        }
+    }
+
+    if let Some(limit_watts) = power_limit {
+        for index in &indices {
+            gpu.validate_power_limit(*index, limit_watts)
+                .map_err(|e| CliError::usage(format!("GPU {index}: {e}")))?;
+        }
+    }
+
+    gpu.check_write_access()?;
+
+    // read the offsets to go back to before changing anything
+    let rollback = match confirm_timeout {
+        Some(timeout) => Some(Rollback::capture(gpu, &indices, timeout).ok_or_else(|| {
+            CliError::failure("could not read the current offsets to go back to")
+        })?),
+        None => None,
+    };
+
+    // a failure part way through never gets to the prompt, so the offsets
+    // go back straight away
+    if let Err(mut e) = apply_set(gpu, &indices, args) {
+        if let Some(rollback) = &rollback {
+            match rollback.revert(gpu) {
+                Ok(()) => println!("Reverted to the previous offsets"),
+                Err(revert_error) => e
+                    .message
+                    .push_str(&format!("\ncould not revert the offsets: {revert_error}")),
+            }
+        }
+        return Err(e);
+    }
+
+    if let (Some(rollback), Some(timeout)) = (rollback, confirm_timeout) {
+        print!(
+            "Keep the new offsets? Type y within {} s, anything else reverts them: ",
+            timeout.as_secs()
+        );
+        let _ = io::stdout().flush();
+
+        if confirm(input, timeout, interrupted) {
+            println!("Keeping the new offsets");
+        } else {
+            // revert before printing, the terminal may already be gone
+            rollback.revert(gpu).map_err(CliError::failure)?;
+            println!("\nReverted to the previous offsets");
+        }
+    }
+
+    Ok(())
+}
+
+// make the changes of a set command that were already checked
+fn apply_set(gpu: &mut Gpu, indices: &[u32], args: &SetArgs) -> Result<(), CliError> {
+    let &SetArgs {
+        core_offset,
+        mem_offset,
+        lock_gpu_clocks,
+        lock_mem_clocks,
+        unlock_clocks,
+        power_limit,
+        fan,
+        ..
+    } = args;
+    let clock_locks = [
+        (LockedClock::Graphics, lock_gpu_clocks),
+        (LockedClock::Memory, lock_mem_clocks),
+    ];
+
+    if let Some(core_offset) = core_offset {
+        gpu.apply_core_offset(indices, core_offset)
+            .map_err(CliError::failure)?;
+        println!("Core offset set to {core_offset} MHz");
+    }
+
+    if let Some(mem_offset) = mem_offset {
+        gpu.apply_mem_offset(indices, mem_offset)
+            .map_err(CliError::failure)?;
+        println!("Memory offset set to {mem_offset} MHz");
+    }
+
+    if unlock_clocks {
+        for clock in [LockedClock::Graphics, LockedClock::Memory] {
+            gpu.unlock_clocks(indices, clock)
+                .map_err(CliError::failure)?;
+        }
+        println!("Clocks unlocked");
+    }
+
+    for (clock, lock) in clock_locks {
+        if let Some(lock) = lock {
+            gpu.lock_clocks(indices, clock, lock)
+                .map_err(CliError::failure)?;
+            println!("Locked the {clock} clock to {}", clock_lock_text(lock));
+        }
+    }
+
+    if let Some(limit_watts) = power_limit {
+        gpu.apply_power_limit(indices, limit_watts)
+            .map_err(CliError::failure)?;
+        println!("Power limit set to {limit_watts} W");
+    }
+
+    if let Some(fan) = fan {
+        gpu.apply_fan_policy(indices, fan)
+            .map_err(CliError::failure)?;
+        println!("Fans set to {fan}");
+    }
+
+    Ok(())
+}
+
+// reset every tunable, printing how each part went
+fn reset(gpu: &mut Gpu, devices: &DeviceArgs) -> Result<(), CliError> {
+    let indices = devices.resolve(gpu, false)?;
+    let report = gpu.reset_defaults(&indices)?;
+
+    for step in &report.steps {
+        println!("{step}");
+    }
+
+    if report.succeeded() {
+        Ok(())
+    } else {
+        Err(CliError::failure("some settings could not be reset"))
+    }
+}
+
+// how often a confirmation prompt checks for an interrupt
+const INTERRUPT_POLL: Duration = Duration::from_millis(100);
+
+// wait for the user to answer yes, giving up after the timeout, on an
+// interrupt or when the input is closed
+fn confirm(
+    mut input: impl BufRead + Send + 'static,
+    timeout: Duration,
+    interrupted: &AtomicBool,
+) -> bool {
+    let (sender, receiver) = mpsc::channel();
+
+    // stdin can not be read with a timeout, so read it on a thread that
+    // is simply abandoned if nobody answers. A closed terminal reads as
+    // an empty answer
+    thread::spawn(move || {
+        let mut line = String::new();
+        if input.read_line(&mut line).is_err() {
+            line.clear();
+        }
+        let _ = sender.send(line);
+    });
+
+    let deadline = Instant::now() + timeout;
+    while !interrupted.load(Ordering::Relaxed) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return false;
+        }
+
+        match receiver.recv_timeout(remaining.min(INTERRUPT_POLL)) {
+            Ok(line) => {
+                return matches!(line.trim().to_ascii_lowercase().as_str(), "y" | "yes");
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+
+    false
+}
+
+// turn ctrl+c, a hangup or a terminate into a flag, so a command can put
+// the gpu back before exiting
+fn catch_interrupts() -> Result<Arc<AtomicBool>, CliError> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || handler.store(true, Ordering::Relaxed))
+        .map_err(|e| CliError::failure(format!("could not catch Ctrl+C: {e}")))?;
+
+    Ok(interrupted)
+}
+
+// scan for stable offsets and save them as a profile
+fn autotune(
+    gpu: &mut Gpu,
+    devices: &DeviceArgs,
+    settings: &TuneSettings,
+    name: &str,
+) -> Result<(), CliError> {
+    if name.trim().is_empty() {
+        return Err(CliError::usage("profile names can not be empty"));
+    }
+    if settings.core_step < 0 || settings.mem_step < 0 {
+        return Err(CliError::usage("offset steps can not be negative"));
+    }
+
+    let indices = devices.resolve(gpu, false)?;
+    gpu.check_write_access()?;
+
+    // let the scan put the offsets back instead of dying halfway through
+    let interrupted = catch_interrupts()?;
+
+    let result = autotune::run(gpu, &indices, settings, &interrupted, &mut |line| {
+        println!("{line}")
+    })
+    .map_err(CliError::failure)?;
+
+    let profile = Profile {
+        name: name.trim().to_string(),
+        core_offset: result.core_offset,
+        mem_offset: result.mem_offset,
+        power_limit_watts: None,
+        fan_policy: FanPolicy::Auto,
+    };
+
+    let path = profile::default_profiles_path();
+    let mut profiles = ProfileStore::load(&path)
+        .map_err(|e| CliError::failure(format!("{}: {e}", path.display())))?;
+    let saved = if profiles.get(&profile.name).is_some() {
+        profiles.update(profile)
+    } else {
+        profiles.create(profile)
+    };
+    saved
+        .and_then(|_| profiles.save(&path))
+        .map_err(|e| CliError::failure(format!("{}: {e}", path.display())))?;
+
+    println!(
+        "Saved profile \"{}\": core {} MHz, mem {} MHz",
+        name.trim(),
+        result.core_offset,
+        result.mem_offset
+    );
+    Ok(())
+}
+
+fn profile_command(gpu: &mut Gpu, command: ProfileCommand) -> Result<(), CliError> {
+    let profiles = ProfileStore::load(&profile::default_profiles_path())
+        .map_err(|e| CliError::failure(e.to_string()))?;
+
+    match command {
+        ProfileCommand::List { json } => {
+            if json {
+                let profiles: Vec<Value> = profiles.profiles.iter().map(profile_json).collect();
+                println!("{:#}", Value::from(profiles));
+                return Ok(());
+            }
+
+            for profile in &profiles.profiles {
+                println!(
+                    "{}: core {} MHz, mem {} MHz, power {}, fan {}",
+                    profile.name,
+                    profile.core_offset,
+                    profile.mem_offset,
+                    power_limit_text(profile),
+                    profile.fan_policy
+                );
+            }
+
+            Ok(())
+        }
+        ProfileCommand::Apply { name, devices } => {
+            let profile = profiles
+                .get(&name)
+                .ok_or_else(|| CliError::usage(format!("there is no profile named \"{name}\"")))?;
+            let indices = devices.resolve(gpu, false)?;
+
+            gpu.check_write_access()?;
+            gpu.apply_profile(&indices, profile)
+                .map_err(CliError::failure)?;
+
+            println!("Applied profile \"{name}\"");
+            Ok(())
+        }
+    }
+}
+
+// apply a saved profile, logging each step. This runs unattended from the
+// systemd unit so everything goes to stdout/stderr where journald picks it up
+fn restore(
+    backend: BackendKind,
+    name: &str,
+    profiles_path: Option<PathBuf>,
+    devices: &DeviceArgs,
+) -> Result<(), CliError> {
+    let profiles_path = profiles_path.unwrap_or_else(profile::default_profiles_path);
+    let profile = load_profile(&profiles_path, name)?;
+
+    let mut gpu = open_gpu(backend)?;
+    let indices = devices.resolve(&gpu, false)?;
+    gpu.check_write_access()?;
+
+    let targets: Vec<String> = indices.iter().map(u32::to_string).collect();
+    println!(
+        "restoring profile \"{name}\" from {} on GPU {}: core {} MHz, mem {} MHz, power {}, fan {}",
+        profiles_path.display(),
+        targets.join(", "),
+        profile.core_offset,
+        profile.mem_offset,
+        power_limit_text(&profile),
+        profile.fan_policy
+    );
+
+    gpu.apply_profile(&indices, &profile)
+        .map_err(|e| CliError::failure(format!("failed to restore profile \"{name}\":\n{e}")))?;
+
+    println!("restored profile \"{name}\"");
+    Ok(())
+}
+
+fn service_command(command: ServiceCommand) -> Result<(), CliError> {
+    match command {
+        ServiceCommand::Install {
+            profile,
+            profiles,
+            devices,
+            root,
+        } => {
+            let profiles_path = profiles.unwrap_or_else(profile::default_profiles_path);
+            // the unit runs as root from /, so every path in it has to be absolute
+            let profiles_path = std::path::absolute(&profiles_path)
+                .map_err(|e| CliError::failure(e.to_string()))?;
+
+            // catch typos now rather than at the next boot
+            load_profile(&profiles_path, &profile)?;
+
+            let exe = std::env::current_exe().map_err(|e| {
+                CliError::failure(format!("could not find the path of this program: {e}"))
+            })?;
+
+            let unit = RestoreUnit {
+                exe,
+                profiles_path,
+                profile,
+                device_args: devices.to_args(),
+            };
+            let path = service::install(&root, &unit).map_err(|e| {
+                CliError::failure(format!(
+                    "could not write {}: {e}",
+                    service::unit_path(&root).display()
+                ))
+            })?;
+
+            println!("Wrote {}", path.display());
+            if root == Path::new("/") {
+                println!(
+                    "Enable it with: systemctl daemon-reload && systemctl enable {}",
+                    service::SERVICE_NAME
+                );
+            }
+
+            Ok(())
+        }
+        ServiceCommand::Uninstall { root } => {
+            let path = service::unit_path(&root);
+            let removed = service::uninstall(&root).map_err(|e| {
+                CliError::failure(format!("could not remove {}: {e}", path.display()))
+            })?;
+
+            if removed {
+                println!("Removed {}", path.display());
+                if root == Path::new("/") {
+                    println!(
+                        "Run systemctl disable {} to drop any leftover links",
+                        service::SERVICE_NAME
+                    );
+                }
+            } else {
+                println!("{} is not installed", path.display());
+            }
+
+            Ok(())
+        }
+    }
+}
+
+// serve write requests from unprivileged clients until killed
+fn run_helper(
+    kind: BackendKind,
+    socket: &Path,
+    policy_path: &Path,
+    group: Option<&str>,
+) -> Result<(), CliError> {
+    let policy = HelperPolicy::load(policy_path).map_err(CliError::usage)?;
+
+    // talk to the gpus directly, the helper must never forward to itself
+    let backend = backend::create_local(kind).map_err(|e| {
+        CliError::new(
+            EXIT_NO_GPU,
+            format!("failed to initialize the GPU backend: {e}"),
+        )
+    })?;
+    if backend.requires_root() && !sudo2::running_as_root() {
+        return Err(CliError::new(
+            EXIT_PERMISSION,
+            "the helper has to run as root",
+        ));
+    }
+
+    let group = group
+        .map(helper::group_id)
+        .transpose()
+        .map_err(|e| CliError::usage(e.to_string()))?;
+    let listener = helper::bind(socket, group)
+        .map_err(|e| CliError::failure(format!("could not listen on {}: {e}", socket.display())))?;
+
+    eprintln!("helper listening on {}", socket.display());
+    helper::serve(listener, &mut Helper::new(backend, policy))
+        .map_err(|e| CliError::failure(e.to_string()))
+}
+
+// serve metrics on /metrics until killed
+fn run_exporter(gpu: &mut Gpu, listen: &str) -> Result<(), CliError> {
+    let listener = TcpListener::bind(listen)
+        .map_err(|e| CliError::failure(format!("could not listen on {listen}: {e}")))?;
+
+    eprintln!("serving metrics on http://{listen}/metrics");
+    exporter::serve(listener, gpu).map_err(|e| CliError::failure(e.to_string()))
+}
+
+// read a single profile from the given file
+fn load_profile(path: &Path, name: &str) -> Result<Profile, CliError> {
+    ProfileStore::load(path)
+        .map_err(|e| CliError::failure(format!("{}: {e}", path.display())))?
+        .get(name)
+        .cloned()
+        .ok_or_else(|| {
+            CliError::usage(format!(
+                "there is no profile named \"{name}\" in {}",
+                path.display()
+            ))
+        })
+}
+
+fn power_limit_text(profile: &Profile) -> String {
+    profile
+        .power_limit_watts
+        .map(|limit_watts| format!("{limit_watts} W"))
+        .unwrap_or_else(|| String::from("unchanged"))
+}
+
+fn profile_json(profile: &Profile) -> Value {
+    json!({
+        "name": profile.name,
+        "core_offset_mhz": profile.core_offset,
+        "mem_offset_mhz": profile.mem_offset,
+        "power_limit_watts": profile.power_limit_watts,
+        "fan_policy": profile.fan_policy.to_string(),
+    })
+}
+
+// write samples to a file until count is reached or we get killed. Every
+// batch is flushed so nothing is lost on Ctrl+C
+fn record(
+    gpu: &mut Gpu,
+    output: &Path,
+    devices: &DeviceArgs,
+    format: RecordFormat,
+    rotation: Option<Rotation>,
+    interval: u64,
+    count: Option<u64>,
+) -> Result<(), CliError> {
+    let indices = devices.resolve(gpu, true)?;
+    let mut recorder = Recorder::start(output, format, rotation)
+        .map_err(|e| CliError::failure(format!("could not create {}: {e}", output.display())))?;
+
+    eprintln!("recording to {} as {format}", output.display());
+
+    let mut taken = 0;
+    while count.is_none_or(|count| taken < count) {
+        if taken > 0 {
+            thread::sleep(Duration::from_millis(interval));
+        }
+
+        gpu.update_gpu_info();
+        taken += 1;
+
+        let samples = indices
+            .iter()
+            .filter_map(|index| gpu.device(*index))
+            .map(|device| &device.sample);
+        recorder.record(samples).map_err(|e| {
+            CliError::failure(format!(
+                "could not write to {}: {e}",
+                recorder.path().display()
+            ))
+        })?;
+    }
+
+    eprintln!("recorded {} samples", recorder.samples());
+    Ok(())
+}
+
+fn watch(
+    gpu: &mut Gpu,
+    devices: &DeviceArgs,
+    interval: u64,
+    count: Option<u64>,
+    json: bool,
+) -> Result<(), CliError> {
+    let indices = devices.resolve(gpu, true)?;
+    let mut taken = 0;
+
+    while count.is_none_or(|count| taken < count) {
+        if taken > 0 {
+            thread::sleep(Duration::from_millis(interval));
+        }
+
+        gpu.update_gpu_info();
+        taken += 1;
+
+        for device in indices.iter().filter_map(|index| gpu.device(*index)) {
+            let line = if json {
+                device_json(device).to_string()
+            } else {
+                watch_line(device)
+            };
+
+            // stop quietly once whatever we are piped into goes away
+            let mut stdout = io::stdout().lock();
+            if writeln!(stdout, "{line}")
+                .and_then(|_| stdout.flush())
+                .is_err()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// a single line summary of a device for watch mode
+fn watch_line(device: &DeviceState) -> String {
+    let sample = &device.sample;
+
+    format!(
+        "GPU {}  {:>9}  {:>6}  fan {:>5}  use {:>5}  core {:>9}  mem {:>9}  throttle {}",
+        device.index,
+        power_text(&sample.power_mw),
+        metric_text_with(&sample.temperature_c, |temp| format!("{temp} °C")),
+        metric_text_with(&sample.fan_speed_percent(), |speed| format!("{speed} %")),
+        metric_text_with(&sample.gpu_utilization_percent, |use_| format!("{use_} %")),
+        metric_text_with(sample.clock_mhz(Clock::Graphics), |mhz| format!(
+            "{mhz} MHz"
+        )),
+        metric_text_with(sample.clock_mhz(Clock::Memory), |mhz| format!("{mhz} MHz")),
+        throttle_text(&sample.throttle_reasons),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SimBackend;
+
+    fn set_args(args: &[&str]) -> SetArgs {
+        let cli = Cli::try_parse_from([&["nvidia-tweaker", "set"], args].concat()).unwrap();
+        match cli.command {
+            Some(Command::Set(args)) => args,
+            command => panic!("parsed {command:?}"),
+        }
+    }
+
+    fn sim_gpu() -> Gpu {
+        let mut gpu = Gpu::with_backend(Box::new(SimBackend::new(1))).unwrap();
+        gpu.apply_oc(&[0], 10, 20).unwrap();
+        gpu
+    }
+
+    #[test]
+    fn a_closed_stdin_reverts_the_offsets() {
+        let mut gpu = sim_gpu();
+        let args = set_args(&["--core-offset", "100", "--confirm-timeout", "30"]);
+
+        set(&mut gpu, &args, io::empty(), &AtomicBool::new(false)).unwrap();
+        assert_eq!(gpu.get_gpu_offset(0), Ok(10));
+        assert_eq!(gpu.get_mem_offset(0), Ok(20));
+    }
+
+    #[test]
+    fn an_interrupt_reverts_the_offsets() {
+        let mut gpu = sim_gpu();
+        let args = set_args(&["--mem-offset", "200", "--confirm-timeout", "30"]);
+
+        // a reader that never answers, like a terminal nobody types into
+        let (_writer, reader) = std::os::unix::net::UnixStream::pair().unwrap();
+        let input = io::BufReader::new(reader);
+
+        set(&mut gpu, &args, input, &AtomicBool::new(true)).unwrap();
+        assert_eq!(gpu.get_mem_offset(0), Ok(20));
+    }
+
+    #[test]
+    fn a_yes_keeps_the_offsets() {
+        let mut gpu = sim_gpu();
+        let args = set_args(&["--core-offset", "100", "--confirm-timeout", "30"]);
+
+        set(
+            &mut gpu,
+            &args,
+            io::Cursor::new("y\n"),
+            &AtomicBool::new(false),
+        )
+        .unwrap();
+        assert_eq!(gpu.get_gpu_offset(0), Ok(100));
+    }
+}
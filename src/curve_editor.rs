@@ -0,0 +1,275 @@
+use iced::widget::canvas::{self, Frame, Geometry, Path, Stroke, event};
+use iced::{Color, Element, Fill, Pixels, Point, Rectangle, Renderer, Size, Theme, Vector, mouse};
+
+use crate::fan_curve::{CurvePoint, FanCurve, MAX_SPEED_PERCENT, MAX_TEMP_C};
+
+const EDITOR_HEIGHT: f32 = 220.0;
+const LABEL_SIZE: f32 = 12.0;
+
+// room left around the plot for the axis labels
+const MARGIN_LEFT: f32 = 36.0;
+const MARGIN_BOTTOM: f32 = 20.0;
+const MARGIN: f32 = 8.0;
+
+const POINT_RADIUS: f32 = 5.0;
+// how close in pixels a click has to be to grab a point
+const GRAB_RADIUS: f32 = 10.0;
+
+const CURVE_COLOR: Color = Color::from_rgb(0.26, 0.52, 0.96);
+const TEMP_COLOR: Color = Color::from_rgb(0.98, 0.55, 0.00);
+
+// a change made to the curve in the editor
+#[derive(Debug, Clone, Copy)]
+pub enum CurveEdit {
+    Move(usize, CurvePoint),
+    Insert(CurvePoint),
+    Remove(usize),
+    // a drag ended, the curve can be saved now
+    Finished,
+}
+
+// an editable temperature to fan speed graph. Drag a point to move it, click
+// an empty spot to add one and right click a point to remove it. The current
+// temperature is marked so it is clear where on the curve the fans are
+pub fn view<'a, Message: 'a>(
+    curve: &'a FanCurve,
+    temp_c: Option<f32>,
+    on_edit: fn(CurveEdit) -> Message,
+) -> Element<'a, Message> {
+    canvas::Canvas::new(Editor {
+        curve,
+        temp_c,
+        on_edit,
+    })
+    .width(Fill)
+    .height(EDITOR_HEIGHT)
+    .into()
+}
+
+struct Editor<'a, Message> {
+    curve: &'a FanCurve,
+    temp_c: Option<f32>,
+    on_edit: fn(CurveEdit) -> Message,
+}
+
+#[derive(Default)]
+struct EditorState {
+    // index of the point being dragged
+    dragging: Option<usize>,
+}
+
+// the part of the canvas the curve is drawn in
+fn plot_area(size: Size) -> Rectangle {
+    Rectangle {
+        x: MARGIN_LEFT,
+        y: MARGIN,
+        width: (size.width - MARGIN_LEFT - MARGIN).max(1.0),
+        height: (size.height - MARGIN - MARGIN_BOTTOM).max(1.0),
+    }
+}
+
+fn to_screen(area: Rectangle, temp_c: f32, speed_percent: f32) -> Point {
+    Point::new(
+        area.x + area.width * temp_c / MAX_TEMP_C as f32,
+        area.y + area.height * (1.0 - speed_percent / MAX_SPEED_PERCENT as f32),
+    )
+}
+
+// the curve point under a position on the canvas, clamped into the axes
+fn from_screen(area: Rectangle, position: Point) -> CurvePoint {
+    let temp = (position.x - area.x) / area.width * MAX_TEMP_C as f32;
+    let speed = (1.0 - (position.y - area.y) / area.height) * MAX_SPEED_PERCENT as f32;
+
+    CurvePoint {
+        temp_c: temp.round().clamp(0.0, MAX_TEMP_C as f32) as u32,
+        speed_percent: speed.round().clamp(0.0, MAX_SPEED_PERCENT as f32) as u32,
+    }
+}
+
+impl<Message> Editor<'_, Message> {
+    // index of the point close enough to the position to grab
+    fn point_at(&self, area: Rectangle, position: Point) -> Option<usize> {
+        self.curve
+            .points
+            .iter()
+            .enumerate()
+            .map(|(index, point)| {
+                let screen = to_screen(area, point.temp_c as f32, point.speed_percent as f32);
+                (index, screen.distance(position))
+            })
+            .filter(|(_, distance)| *distance <= GRAB_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+    }
+}
+
+impl<Message> canvas::Program<Message> for Editor<'_, Message> {
+    type State = EditorState;
+
+    fn update(
+        &self,
+        state: &mut Self::State,
+        event: canvas::Event,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> (event::Status, Option<Message>) {
+        let area = plot_area(bounds.size());
+        let edit = |edit| (event::Status::Captured, Some((self.on_edit)(edit)));
+
+        let canvas::Event::Mouse(event) = event else {
+            return (event::Status::Ignored, None);
+        };
+
+        match event {
+            // keep following the cursor outside the canvas while dragging
+            mouse::Event::CursorMoved { .. } => match (state.dragging, cursor.position()) {
+                (Some(index), Some(position)) => {
+                    let position = position - Vector::new(bounds.x, bounds.y);
+                    edit(CurveEdit::Move(index, from_screen(area, position)))
+                }
+                _ => (event::Status::Ignored, None),
+            },
+            mouse::Event::ButtonReleased(mouse::Button::Left) if state.dragging.is_some() => {
+                state.dragging = None;
+                edit(CurveEdit::Finished)
+            }
+            mouse::Event::ButtonPressed(button) => {
+                let Some(position) = cursor.position_in(bounds) else {
+                    return (event::Status::Ignored, None);
+                };
+
+                match (button, self.point_at(area, position)) {
+                    (mouse::Button::Left, Some(index)) => {
+                        state.dragging = Some(index);
+                        (event::Status::Captured, None)
+                    }
+                    (mouse::Button::Left, None) => {
+                        edit(CurveEdit::Insert(from_screen(area, position)))
+                    }
+                    (mouse::Button::Right, Some(index)) => edit(CurveEdit::Remove(index)),
+                    _ => (event::Status::Ignored, None),
+                }
+            }
+            _ => (event::Status::Ignored, None),
+        }
+    }
+
+    fn draw(
+        &self,
+        _state: &Self::State,
+        renderer: &Renderer,
+        theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let palette = theme.extended_palette();
+        let area = plot_area(frame.size());
+
+        frame.fill_rectangle(
+            Point::new(area.x, area.y),
+            area.size(),
+            palette.background.weak.color,
+        );
+
+        // grid lines and labels every 20 °C and 20 %
+        let grid = Stroke::default()
+            .with_color(palette.background.strong.color)
+            .with_width(1.0);
+        let label = |content: String, position: Point| canvas::Text {
+            content,
+            position,
+            color: palette.background.base.text,
+            size: Pixels(LABEL_SIZE),
+            ..canvas::Text::default()
+        };
+
+        for step in 0..=5 {
+            let value = step as f32 * 20.0;
+
+            let x = to_screen(area, value, 0.0).x;
+            frame.stroke(
+                &Path::line(Point::new(x, area.y), Point::new(x, area.y + area.height)),
+                grid,
+            );
+            frame.fill_text(label(
+                format!("{value:.0}°C"),
+                Point::new(x - 12.0, area.y + area.height + 4.0),
+            ));
+
+            let y = to_screen(area, 0.0, value).y;
+            frame.stroke(
+                &Path::line(Point::new(area.x, y), Point::new(area.x + area.width, y)),
+                grid,
+            );
+            frame.fill_text(label(format!("{value:.0}%"), Point::new(2.0, y - 7.0)));
+        }
+
+        // the curve runs flat from both ends to the edges of the graph
+        let points = &self.curve.points;
+        if let (Some(first), Some(last)) = (points.first(), points.last()) {
+            let line = Path::new(|builder| {
+                builder.move_to(to_screen(area, 0.0, first.speed_percent as f32));
+                for point in points {
+                    builder.line_to(to_screen(
+                        area,
+                        point.temp_c as f32,
+                        point.speed_percent as f32,
+                    ));
+                }
+                builder.line_to(to_screen(
+                    area,
+                    MAX_TEMP_C as f32,
+                    last.speed_percent as f32,
+                ));
+            });
+            frame.stroke(
+                &line,
+                Stroke::default().with_color(CURVE_COLOR).with_width(2.0),
+            );
+        }
+
+        for point in points {
+            let center = to_screen(area, point.temp_c as f32, point.speed_percent as f32);
+            frame.fill(&Path::circle(center, POINT_RADIUS), CURVE_COLOR);
+        }
+
+        if let Some(temp_c) = self.temp_c {
+            let temp_c = temp_c.clamp(0.0, MAX_TEMP_C as f32);
+            let top = to_screen(area, temp_c, MAX_SPEED_PERCENT as f32);
+            let bottom = to_screen(area, temp_c, 0.0);
+            frame.stroke(
+                &Path::line(top, bottom),
+                Stroke::default().with_color(TEMP_COLOR).with_width(1.0),
+            );
+            frame.fill(
+                &Path::circle(
+                    to_screen(area, temp_c, self.curve.speed_at(temp_c)),
+                    POINT_RADIUS - 1.0,
+                ),
+                TEMP_COLOR,
+            );
+        }
+
+        vec![frame.into_geometry()]
+    }
+
+    fn mouse_interaction(
+        &self,
+        state: &Self::State,
+        bounds: Rectangle,
+        cursor: mouse::Cursor,
+    ) -> mouse::Interaction {
+        if state.dragging.is_some() {
+            return mouse::Interaction::Grabbing;
+        }
+
+        match cursor.position_in(bounds) {
+            Some(position) if self.point_at(plot_area(bounds.size()), position).is_some() => {
+                mouse::Interaction::Grab
+            }
+            Some(_) => mouse::Interaction::Crosshair,
+            None => mouse::Interaction::default(),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt;
+
+use nvml_wrapper::error::NvmlError;
+
+// errors coming back from a gpu backend, collapsed into the cases the app
+// actually treats differently or has different advice for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GpuError {
+    // the query or setting is not available on this device
+    NotSupported,
+    // the NVML library in use does not export the named function, usually
+    // because the driver is older than the feature
+    MissingFunction(String),
+    // the operation needs more privileges than the process has
+    NoPermission,
+    // the NVML library could not be found or loaded, with why
+    LibraryNotFound(String),
+    // the kernel driver is not loaded
+    DriverNotLoaded,
+    // the NVML library and the kernel module are from different drivers
+    VersionMismatch,
+    // the device fell off the bus and needs a reset
+    GpuLost,
+    // the device has to be reset before it can be used again
+    ResetRequired,
+    // the driver rejected a value, usually for being out of range
+    InvalidArgument,
+    // the external power cables are not plugged in properly
+    InsufficientPower,
+    // the setting can not change while something is running on the gpu
+    InUse,
+    // the operating system or a cgroup keeps the process from the device
+    Blocked,
+    // the privileged helper refused the request or could not be reached
+    Helper(String),
+    // anything else, with the original description
+    Unknown(String),
+}
+
+impl GpuError {
+    // the device or the driver can not do this at all, as opposed to the
+    // call failing
+    pub fn is_not_supported(&self) -> bool {
+        matches!(self, GpuError::NotSupported | GpuError::MissingFunction(_))
+    }
+
+    // what the user can do about it, when there is anything
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            GpuError::NotSupported => {
+                Some("this GPU architecture or board does not allow it through NVML")
+            }
+            GpuError::MissingFunction(_) => {
+                Some("the driver is too old for this, update the NVIDIA driver")
+            }
+            GpuError::NoPermission => Some(
+                "changing settings requires root, run with sudo or start the helper with \
+                 `nvidia-tweaker helper`",
+            ),
+            GpuError::LibraryNotFound(_) => Some(
+                "install the NVIDIA driver, or set NVIDIA_TWEAKER_NVML_LIB to the path of \
+                 libnvidia-ml.so.1",
+            ),
+            GpuError::DriverNotLoaded => {
+                Some("check that the nvidia kernel module is loaded with `lsmod | grep nvidia`")
+            }
+            GpuError::VersionMismatch => {
+                Some("reboot after a driver update, or reinstall the driver if that does not help")
+            }
+            GpuError::GpuLost => Some("check `dmesg` for Xid errors and reboot to recover"),
+            GpuError::ResetRequired => Some("reset it with `nvidia-smi -r` or reboot"),
+            GpuError::InvalidArgument => Some("check the value against the supported range"),
+            GpuError::InsufficientPower => Some("check the PCIe power connectors"),
+            GpuError::InUse => Some("close the applications using the GPU and try again"),
+            GpuError::Blocked => Some("check the container or cgroup device permissions"),
+            GpuError::Helper(_) | GpuError::Unknown(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for GpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuError::NotSupported => write!(f, "not supported on this device"),
+            GpuError::MissingFunction(name) => {
+                write!(f, "the installed NVML library has no {name}")
+            }
+            GpuError::LibraryNotFound(reason) if reason.is_empty() => {
+                write!(f, "could not load the NVML library")
+            }
+            GpuError::LibraryNotFound(reason) => {
+                write!(f, "could not load the NVML library: {reason}")
+            }
+            GpuError::NoPermission => write!(f, "insufficient permissions"),
+            GpuError::DriverNotLoaded => write!(f, "NVIDIA driver is not loaded"),
+            GpuError::VersionMismatch => write!(
+                f,
+                "the NVML library does not match the loaded NVIDIA driver"
+            ),
+            GpuError::GpuLost => write!(f, "GPU is lost"),
+            GpuError::ResetRequired => write!(f, "GPU needs a reset before it can be used again"),
+            GpuError::InvalidArgument => write!(f, "the driver rejected the value"),
+            GpuError::InsufficientPower => write!(
+                f,
+                "the GPU's external power cables are not properly attached"
+            ),
+            GpuError::InUse => write!(f, "the GPU is in use"),
+            GpuError::Blocked => write!(f, "access to the GPU is blocked by the operating system"),
+            GpuError::Helper(description) => write!(f, "helper: {description}"),
+            GpuError::Unknown(description) => write!(f, "{description}"),
+        }?;
+
+        match self.hint() {
+            Some(hint) => write!(f, " ({hint})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for GpuError {}
+
+impl From<NvmlError> for GpuError {
+    fn from(error: NvmlError) -> Self {
+        match error {
+            NvmlError::NotSupported | NvmlError::FunctionNotFound => GpuError::NotSupported,
+            NvmlError::NoPermission => GpuError::NoPermission,
+            NvmlError::DriverNotLoaded | NvmlError::Uninitialized => GpuError::DriverNotLoaded,
+            NvmlError::LibraryNotFound => GpuError::LibraryNotFound(String::new()),
+            NvmlError::LibloadingError(e) => GpuError::LibraryNotFound(e.to_string()),
+            NvmlError::LibRmVersionMismatch => GpuError::VersionMismatch,
+            NvmlError::GpuLost => GpuError::GpuLost,
+            NvmlError::ResetRequired => GpuError::ResetRequired,
+            NvmlError::InvalidArg => GpuError::InvalidArgument,
+            NvmlError::InsufficientPower => GpuError::InsufficientPower,
+            NvmlError::InUse => GpuError::InUse,
+            NvmlError::OperatingSystem => GpuError::Blocked,
+            // a return code newer than the bindings know about
+            NvmlError::UnexpectedVariant(code) => GpuError::Unknown(format!(
+                "NVML returned an unknown error code {code}, the driver may be newer than this app"
+            )),
+            NvmlError::Unknown => GpuError::Unknown(String::from(
+                "the driver hit an internal error, check `dmesg` for details",
+            )),
+            other => GpuError::Unknown(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_nvml_errors() {
+        let cases = [
+            (NvmlError::NotSupported, GpuError::NotSupported),
+            (NvmlError::FunctionNotFound, GpuError::NotSupported),
+            (NvmlError::NoPermission, GpuError::NoPermission),
+            (NvmlError::DriverNotLoaded, GpuError::DriverNotLoaded),
+            (
+                NvmlError::LibraryNotFound,
+                GpuError::LibraryNotFound(String::new()),
+            ),
+            (NvmlError::LibRmVersionMismatch, GpuError::VersionMismatch),
+            (NvmlError::GpuLost, GpuError::GpuLost),
+            (NvmlError::ResetRequired, GpuError::ResetRequired),
+            (NvmlError::InvalidArg, GpuError::InvalidArgument),
+            (NvmlError::InsufficientPower, GpuError::InsufficientPower),
+            (NvmlError::InUse, GpuError::InUse),
+            (NvmlError::OperatingSystem, GpuError::Blocked),
+        ];
+
+        for (nvml_error, expected) in cases {
+            assert_eq!(GpuError::from(nvml_error), expected);
+        }
+
+        assert!(matches!(
+            GpuError::from(NvmlError::UnexpectedVariant(999)),
+            GpuError::Unknown(description) if description.contains("999")
+        ));
+    }
+
+    #[test]
+    fn missing_functions_count_as_not_supported() {
+        let error = GpuError::MissingFunction(String::from("nvmlDeviceGetFanSpeedRPM"));
+
+        assert!(error.is_not_supported());
+        assert!(!GpuError::InvalidArgument.is_not_supported());
+        assert_eq!(
+            error.to_string(),
+            "the installed NVML library has no nvmlDeviceGetFanSpeedRPM \
+             (the driver is too old for this, update the NVIDIA driver)"
+        );
+    }
+
+    #[test]
+    fn hints_follow_the_description() {
+        assert_eq!(
+            GpuError::InUse.to_string(),
+            "the GPU is in use (close the applications using the GPU and try again)"
+        );
+        assert_eq!(
+            GpuError::LibraryNotFound(String::new()).to_string(),
+            "could not load the NVML library (install the NVIDIA driver, or set \
+             NVIDIA_TWEAKER_NVML_LIB to the path of libnvidia-ml.so.1)"
+        );
+        // errors that only pass a description along have nothing to add
+        assert_eq!(GpuError::Unknown(String::from("odd")).to_string(), "odd");
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::format::timestamp_text;
+
+// oldest entries are dropped past this
+const MAX_ENTRIES: usize = 500;
+
+// errors shown in the notification area until dismissed, newest kept
+const MAX_NOTICES: usize = 3;
+
+// the same message again within this long of the last time counts as a
+// repeat of that entry instead of a new one
+const REPEAT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Info,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+// one thing that happened, with how often it kept happening
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub severity: Severity,
+    pub message: String,
+    pub first_seen: SystemTime,
+    pub last_seen: SystemTime,
+    pub count: u32,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}",
+            timestamp_text(self.first_seen),
+            self.severity,
+            self.message
+        )?;
+        if self.count > 1 {
+            write!(
+                f,
+                " (x{}, last at {})",
+                self.count,
+                timestamp_text(self.last_seen)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+// what the gui did and what went wrong, oldest first. New errors also go
+// to the notification area until they are dismissed
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    entries: VecDeque<LogEntry>,
+    notices: Vec<String>,
+}
+
+impl EventLog {
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(Severity::Info, message.into());
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        // a repeat was already shown, or dismissed on purpose
+        if self.push(Severity::Error, message.clone()) {
+            if self.notices.len() >= MAX_NOTICES {
+                self.notices.remove(0);
+            }
+            self.notices.push(message);
+        }
+    }
+
+    // add an entry, or count it against a recent one with the same message.
+    // True when it made a new entry
+    fn push(&mut self, severity: Severity, message: String) -> bool {
+        let now = SystemTime::now();
+
+        let repeat = self.entries.iter_mut().rev().find(|entry| {
+            entry.severity == severity
+                && entry.message == message
+                && now
+                    .duration_since(entry.last_seen)
+                    .is_ok_and(|since| since <= REPEAT_WINDOW)
+        });
+        if let Some(entry) = repeat {
+            entry.last_seen = now;
+            entry.count += 1;
+            return false;
+        }
+
+        if self.entries.len() >= MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(LogEntry {
+            severity,
+            message,
+            first_seen: now,
+            last_seen: now,
+            count: 1,
+        });
+
+        true
+    }
+
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &LogEntry> {
+        self.entries.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // errors that have not been dismissed yet
+    pub fn notices(&self) -> &[String] {
+        &self.notices
+    }
+
+    pub fn dismiss_notices(&mut self) {
+        self.notices.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.notices.clear();
+    }
+
+    // write every entry to a text file, one per line
+    pub fn export(&self, path: &Path) -> Result<(), String> {
+        let contents: String = self
+            .entries
+            .iter()
+            .map(|entry| format!("{entry}\n"))
+            .collect();
+
+        fs::write(path, contents).map_err(|e| format!("{}: {e}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn repeats_within_the_window_are_counted() {
+        let mut log = EventLog::default();
+        log.info("applied");
+        log.info("applied");
+        log.error("applied");
+
+        let entries: Vec<&LogEntry> = log.entries().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].count, 2);
+        assert_eq!(entries[1].severity, Severity::Error);
+        assert!(entries[0].to_string().contains("(x2, last at "));
+    }
+
+    #[test]
+    fn repeats_after_the_window_are_new_entries() {
+        let mut log = EventLog::default();
+        log.info("applied");
+        log.entries[0].last_seen -= REPEAT_WINDOW + Duration::from_secs(1);
+        log.info("applied");
+
+        assert_eq!(log.entries().count(), 2);
+        assert!(log.entries().all(|entry| entry.count == 1));
+    }
+
+    #[test]
+    fn drops_the_oldest_entries_past_the_cap() {
+        let mut log = EventLog::default();
+        for n in 0..MAX_ENTRIES + 5 {
+            log.info(format!("event {n}"));
+        }
+
+        assert_eq!(log.entries().count(), MAX_ENTRIES);
+        assert_eq!(log.entries().next().unwrap().message, "event 5");
+    }
+
+    #[test]
+    fn keeps_the_newest_notices_and_skips_repeats() {
+        let mut log = EventLog::default();
+        for n in 0..MAX_NOTICES + 1 {
+            log.error(format!("failed {n}"));
+        }
+        log.error(format!("failed {MAX_NOTICES}"));
+
+        let expected: Vec<String> = (1..=MAX_NOTICES).map(|n| format!("failed {n}")).collect();
+        assert_eq!(log.notices(), expected);
+
+        // dismissed on purpose, a repeat does not bring it back
+        log.dismiss_notices();
+        log.error(format!("failed {MAX_NOTICES}"));
+        assert!(log.notices().is_empty());
+
+        log.info("not a notice");
+        assert!(log.notices().is_empty());
+    }
+
+    #[test]
+    fn exports_one_line_per_entry() {
+        let dir = TempDir::new();
+        let path = dir.join("events.log");
+
+        let mut log = EventLog::default();
+        log.info("applied");
+        log.error("failed");
+        log.error("failed");
+        log.export(&path).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("[info] applied"), "{}", lines[0]);
+        assert!(
+            lines[1].contains("[error] failed (x2, last at "),
+            "{}",
+            lines[1]
+        );
+
+        assert!(log.export(&dir.join("missing/events.log")).is_err());
+    }
+}
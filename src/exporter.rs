@@ -0,0 +1,390 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::backend::FanControlPolicy;
+use crate::gpu::{DeviceState, Gpu};
+use crate::telemetry::{CLOCK_LABELS, FanReading, Metric, THROTTLE_REASONS, mw_to_watts};
+
+// only this machine can scrape unless another address is given on purpose
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:9842";
+
+// prefix of every metric name
+const NAMESPACE: &str = "nvidia_tweaker";
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// drop scrapers that stop talking so they can not block the next one
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const HERTZ_PER_MHZ: f64 = 1_000_000.0;
+
+// limits on what a scraper can send before getting dropped, a real request
+// is a few hundred bytes
+const MAX_LINE_BYTES: u64 = 8192;
+const MAX_HEADERS: usize = 100;
+
+// answer scrapes until the listener fails. Readings are taken fresh for
+// every scrape so they are never older than the scrape itself
+pub fn serve(listener: TcpListener, gpu: &mut Gpu) -> io::Result<()> {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = serve_client(stream, gpu) {
+                    eprintln!("scrape failed: {e}");
+                }
+            }
+            Err(e) => eprintln!("failed to accept a connection: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn serve_client(stream: TcpStream, gpu: &mut Gpu) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request_line = read_line(&mut reader)?;
+
+    // skip the headers, nothing in them matters here
+    let mut headers = 0;
+    loop {
+        let header = read_line(&mut reader)?;
+        if header.trim().is_empty() {
+            break;
+        }
+
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("more than {MAX_HEADERS} headers"),
+            ));
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    // ignore any query string
+    let path = parts
+        .next()
+        .unwrap_or_default()
+        .split('?')
+        .next()
+        .unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => {
+            gpu.update_gpu_info();
+            ("200 OK", CONTENT_TYPE, render(gpu))
+        }
+        ("GET", "/") => (
+            "200 OK",
+            "text/html; charset=utf-8",
+            String::from(
+                "<html><head><title>Nvidia Tweaker Exporter</title></head>\
+                 <body><a href=\"/metrics\">Metrics</a></body></html>\n",
+            ),
+        ),
+        ("GET", _) => ("404 Not Found", "text/plain", String::from("not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("method not allowed\n"),
+        ),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+// read a single line of the request, empty at the end of the stream
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    // one byte over the limit is enough to tell the line is too long
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_BYTES + 1)
+        .read_until(b'\n', &mut line)?;
+    if line.len() as u64 > MAX_LINE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("request line longer than {MAX_LINE_BYTES} bytes"),
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+// every metric in the prometheus text format. Readings that could not be
+// taken are left out rather than reported as zero
+pub fn render(gpu: &Gpu) -> String {
+    let mut out = String::new();
+    let devices = &gpu.devices;
+
+    let driver_version = gpu.get_driver_version().unwrap_or_default();
+    gauge(
+        &mut out,
+        "gpu_info",
+        "GPU name and driver version, always 1",
+        devices.iter().map(|device| {
+            let labels = format!(
+                "{},name=\"{}\",driver_version=\"{}\"",
+                gpu_label(device),
+                escape(device.name.as_deref().unwrap_or_default()),
+                escape(&driver_version)
+            );
+            (labels, Some(1.0))
+        }),
+    );
+
+    let per_device = |value: fn(&DeviceState) -> Option<f64>| {
+        devices
+            .iter()
+            .map(move |device| (gpu_label(device), value(device)))
+    };
+
+    gauge(
+        &mut out,
+        "power_watts",
+        "Current power draw in watts",
+        per_device(|device| watts(&device.sample.power_mw)),
+    );
+    gauge(
+        &mut out,
+        "power_limit_watts",
+        "Power limit the driver is enforcing in watts",
+        per_device(|device| watts(&device.sample.power_limit_mw)),
+    );
+    gauge(
+        &mut out,
+        "temperature_celsius",
+        "Core temperature in degrees celsius",
+        per_device(|device| value(&device.sample.temperature_c)),
+    );
+
+    for (name, help, max) in [
+        ("clock_hertz", "Current clock speed in hertz", false),
+        ("max_clock_hertz", "Maximum clock speed in hertz", true),
+    ] {
+        gauge(
+            &mut out,
+            name,
+            help,
+            devices.iter().flat_map(|device| {
+                let clocks = if max {
+                    &device.sample.max_clocks_mhz
+                } else {
+                    &device.sample.clocks_mhz
+                };
+
+                CLOCK_LABELS.iter().zip(clocks).map(move |(label, clock)| {
+                    (
+                        format!("{},clock=\"{}\"", gpu_label(device), label.to_lowercase()),
+                        value(clock).map(|mhz| mhz * HERTZ_PER_MHZ),
+                    )
+                })
+            }),
+        );
+    }
+
+    gauge(
+        &mut out,
+        "memory_used_bytes",
+        "Used video memory in bytes",
+        per_device(|device| bytes(&device.sample.mem_used_bytes)),
+    );
+    gauge(
+        &mut out,
+        "memory_free_bytes",
+        "Free video memory in bytes",
+        per_device(|device| bytes(&device.sample.mem_free_bytes)),
+    );
+    gauge(
+        &mut out,
+        "memory_total_bytes",
+        "Total video memory in bytes",
+        per_device(|device| bytes(&device.sample.mem_total_bytes)),
+    );
+
+    let per_fan = |value: fn(&FanReading) -> Option<f64>| {
+        devices.iter().flat_map(move |device| {
+            let fans = device.sample.fans.as_deref().unwrap_or_default();
+            fans.iter().map(move |fan| {
+                (
+                    format!("{},fan=\"{}\"", gpu_label(device), fan.fan),
+                    value(fan),
+                )
+            })
+        })
+    };
+
+    gauge(
+        &mut out,
+        "fan_speed_ratio",
+        "Fan speed as a fraction of its maximum",
+        per_fan(|fan| ratio(&fan.speed_percent)),
+    );
+    gauge(
+        &mut out,
+        "fan_target_speed_ratio",
+        "Speed the fan is being driven towards as a fraction of its maximum",
+        per_fan(|fan| ratio(&fan.target_percent)),
+    );
+    gauge(
+        &mut out,
+        "fan_speed_rpm",
+        "Fan speed in revolutions per minute",
+        per_fan(|fan| value(&fan.speed_rpm)),
+    );
+    gauge(
+        &mut out,
+        "fan_manual_control",
+        "1 when the fan speed is set manually, 0 when the driver controls it",
+        per_fan(|fan| {
+            fan.policy
+                .as_ref()
+                .ok()
+                .map(|policy| f64::from(u8::from(*policy == FanControlPolicy::Manual)))
+        }),
+    );
+    gauge(
+        &mut out,
+        "gpu_utilization_ratio",
+        "Fraction of time the GPU was busy over the last sample period",
+        per_device(|device| ratio(&device.sample.gpu_utilization_percent)),
+    );
+    gauge(
+        &mut out,
+        "memory_utilization_ratio",
+        "Fraction of time video memory was being read or written over the last sample period",
+        per_device(|device| ratio(&device.sample.mem_utilization_percent)),
+    );
+
+    gauge(
+        &mut out,
+        "clock_throttle_active",
+        "1 while the reason is holding the clocks back, 0 otherwise",
+        devices.iter().flat_map(|device| {
+            THROTTLE_REASONS.iter().map(move |reason| {
+                let active = device
+                    .sample
+                    .throttle_reasons
+                    .as_ref()
+                    .ok()
+                    .map(|reasons| f64::from(u8::from(reasons.contains(reason.flag))));
+                (
+                    format!("{},reason=\"{}\"", gpu_label(device), reason.key),
+                    active,
+                )
+            })
+        }),
+    );
+
+    gauge(
+        &mut out,
+        "core_clock_offset_hertz",
+        "Core clock VF offset in hertz",
+        per_device(|device| offset_hertz(&device.core_offset)),
+    );
+    gauge(
+        &mut out,
+        "memory_clock_offset_hertz",
+        "Memory clock VF offset in hertz",
+        per_device(|device| offset_hertz(&device.mem_offset)),
+    );
+
+    out
+}
+
+// write one gauge family. Samples without a value are skipped and a family
+// without any samples is left out completely
+fn gauge(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    samples: impl Iterator<Item = (String, Option<f64>)>,
+) {
+    let samples: Vec<(String, f64)> = samples
+        .filter_map(|(labels, value)| value.map(|value| (labels, value)))
+        .collect();
+    if samples.is_empty() {
+        return;
+    }
+
+    let _ = writeln!(out, "# HELP {NAMESPACE}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {NAMESPACE}_{name} gauge");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{NAMESPACE}_{name}{{{labels}}} {value}");
+    }
+}
+
+fn gpu_label(device: &DeviceState) -> String {
+    format!("gpu=\"{}\"", device.index)
+}
+
+// escape a label value as the text format requires
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn value<T: Copy + Into<f64>>(metric: &Metric<T>) -> Option<f64> {
+    metric.as_ref().ok().map(|value| (*value).into())
+}
+
+fn bytes(metric: &Metric<u64>) -> Option<f64> {
+    metric.as_ref().ok().map(|bytes| *bytes as f64)
+}
+
+fn watts(metric: &Metric<u32>) -> Option<f64> {
+    metric.as_ref().ok().map(|power_mw| mw_to_watts(*power_mw))
+}
+
+fn ratio(metric: &Metric<u32>) -> Option<f64> {
+    value(metric).map(|percent| percent / 100.0)
+}
+
+fn offset_hertz(metric: &Metric<i32>) -> Option<f64> {
+    value(metric).map(|mhz| mhz * HERTZ_PER_MHZ)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SimBackend;
+
+    #[test]
+    fn renders_the_sampled_offsets() {
+        let mut gpu = Gpu::with_backend(Box::new(SimBackend::new(1))).unwrap();
+        gpu.apply_oc(&[0], 100, 0).unwrap();
+        gpu.update_gpu_info();
+        // written after the sample, so not in this scrape
+        gpu.apply_oc(&[0], 200, 0).unwrap();
+
+        let metrics = render(&gpu);
+        assert!(metrics.contains("nvidia_tweaker_core_clock_offset_hertz{gpu=\"0\"} 100000000\n"));
+        assert!(metrics.contains("nvidia_tweaker_gpu_info{gpu=\"0\",name=\"Simulated GPU 0\""));
+    }
+
+    #[test]
+    fn caps_request_lines() {
+        let mut reader = "GET /metrics HTTP/1.1\r\nHost: x\r\n".as_bytes();
+        assert_eq!(read_line(&mut reader).unwrap(), "GET /metrics HTTP/1.1\r\n");
+        assert_eq!(read_line(&mut reader).unwrap(), "Host: x\r\n");
+        assert_eq!(read_line(&mut reader).unwrap(), "");
+
+        let long = "a".repeat(MAX_LINE_BYTES as usize + 1);
+        let error = read_line(&mut long.as_bytes()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
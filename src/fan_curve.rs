@@ -0,0 +1,610 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::gpu::Gpu;
+use crate::profile;
+
+const FAN_CURVES_FILE_NAME: &str = "fan_curves.toml";
+
+// the range of both axes of a curve
+pub const MAX_TEMP_C: u32 = 100;
+pub const MAX_SPEED_PERCENT: u32 = 100;
+
+const DEFAULT_HYSTERESIS_C: u32 = 3;
+const DEFAULT_RAMP_PERCENT_PER_SEC: u32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurvePoint {
+    pub temp_c: u32,
+    pub speed_percent: u32,
+}
+
+// a temperature to fan speed mapping, linear between the points and flat
+// past either end
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FanCurve {
+    pub name: String,
+    // kept sorted by temperature
+    pub points: Vec<CurvePoint>,
+    // how far the temperature has to fall before the fans slow down again
+    #[serde(default = "default_hysteresis")]
+    pub hysteresis_c: u32,
+    // fastest the speed may change, 0 for no limit
+    #[serde(default = "default_ramp")]
+    pub ramp_percent_per_sec: u32,
+}
+
+fn default_hysteresis() -> u32 {
+    DEFAULT_HYSTERESIS_C
+}
+
+fn default_ramp() -> u32 {
+    DEFAULT_RAMP_PERCENT_PER_SEC
+}
+
+impl FanCurve {
+    // a reasonable starting curve
+    pub fn new(name: String) -> Self {
+        let point = |temp_c, speed_percent| CurvePoint {
+            temp_c,
+            speed_percent,
+        };
+
+        Self {
+            name,
+            points: vec![point(40, 30), point(60, 45), point(75, 70), point(85, 100)],
+            hysteresis_c: DEFAULT_HYSTERESIS_C,
+            ramp_percent_per_sec: DEFAULT_RAMP_PERCENT_PER_SEC,
+        }
+    }
+
+    // fan speed in percent for the given temperature
+    pub fn speed_at(&self, temp_c: f32) -> f32 {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return MAX_SPEED_PERCENT as f32;
+        };
+
+        if temp_c <= first.temp_c as f32 {
+            return first.speed_percent as f32;
+        }
+
+        for pair in self.points.windows(2) {
+            let (low, high) = (pair[0], pair[1]);
+            if temp_c <= high.temp_c as f32 {
+                let span = (high.temp_c - low.temp_c).max(1) as f32;
+                let fraction = (temp_c - low.temp_c as f32) / span;
+                return low.speed_percent as f32
+                    + fraction * (high.speed_percent as f32 - low.speed_percent as f32);
+            }
+        }
+
+        last.speed_percent as f32
+    }
+
+    // add a point, replacing any point already at that temperature
+    pub fn insert(&mut self, point: CurvePoint) {
+        let point = clamp_point(point);
+        match self
+            .points
+            .binary_search_by_key(&point.temp_c, |point| point.temp_c)
+        {
+            Ok(index) => self.points[index] = point,
+            Err(index) => self.points.insert(index, point),
+        }
+    }
+
+    // move a point, keeping it between its neighbours so the order holds
+    pub fn move_point(&mut self, index: usize, point: CurvePoint) {
+        if index >= self.points.len() {
+            return;
+        }
+
+        let mut point = clamp_point(point);
+        let min_temp = index
+            .checked_sub(1)
+            .map_or(0, |previous| self.points[previous].temp_c + 1);
+        let max_temp = self
+            .points
+            .get(index + 1)
+            .map_or(MAX_TEMP_C, |next| next.temp_c.saturating_sub(1));
+        point.temp_c = point.temp_c.clamp(min_temp, max_temp.max(min_temp));
+
+        self.points[index] = point;
+    }
+
+    // remove a point, a curve always keeps at least one
+    pub fn remove(&mut self, index: usize) {
+        if self.points.len() > 1 && index < self.points.len() {
+            self.points.remove(index);
+        }
+    }
+}
+
+fn clamp_point(point: CurvePoint) -> CurvePoint {
+    CurvePoint {
+        temp_c: point.temp_c.min(MAX_TEMP_C),
+        speed_percent: point.speed_percent.min(MAX_SPEED_PERCENT),
+    }
+}
+
+// which curve drives a fan
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanAssignment {
+    pub device: u32,
+    pub fan: u32,
+    pub curve: String,
+}
+
+// every saved curve and which fans they drive, stored as a single TOML file.
+// Fans without an assignment are left to the driver
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FanCurveStore {
+    #[serde(default, rename = "curve")]
+    pub curves: Vec<FanCurve>,
+    #[serde(default, rename = "assignment")]
+    pub assignments: Vec<FanAssignment>,
+}
+
+impl FanCurveStore {
+    // read the store from disk. A missing file is just an empty store
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+
+    // write the store to disk, creating the config directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| error(&e))?;
+        }
+
+        let contents = toml::to_string_pretty(self).map_err(|e| error(&e))?;
+        fs::write(path, contents).map_err(|e| error(&e))
+    }
+
+    pub fn curve(&self, name: &str) -> Option<&FanCurve> {
+        self.curves.iter().find(|curve| curve.name == name)
+    }
+
+    pub fn curve_mut(&mut self, name: &str) -> Option<&mut FanCurve> {
+        self.curves.iter_mut().find(|curve| curve.name == name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.curves.iter().map(|curve| curve.name.clone()).collect()
+    }
+
+    // add a new curve, refusing empty or duplicate names
+    pub fn create(&mut self, name: &str) -> Result<(), String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(String::from("fan curve names can not be empty"));
+        }
+        if self.curve(name).is_some() {
+            return Err(format!("a fan curve named \"{name}\" already exists"));
+        }
+
+        self.curves.push(FanCurve::new(name.to_string()));
+        Ok(())
+    }
+
+    // remove a curve along with every assignment using it
+    pub fn delete(&mut self, name: &str) {
+        self.curves.retain(|curve| curve.name != name);
+        self.assignments
+            .retain(|assignment| assignment.curve != name);
+    }
+
+    pub fn assigned_curve(&self, device: u32, fan: u32) -> Option<&str> {
+        self.assignments
+            .iter()
+            .find(|assignment| assignment.device == device && assignment.fan == fan)
+            .map(|assignment| assignment.curve.as_str())
+    }
+
+    // drive a fan from a curve, or hand it back to the driver with None
+    pub fn assign(&mut self, device: u32, fan: u32, curve: Option<String>) {
+        self.assignments
+            .retain(|assignment| assignment.device != device || assignment.fan != fan);
+
+        if let Some(curve) = curve {
+            self.assignments.push(FanAssignment { device, fan, curve });
+        }
+    }
+
+    // hand every fan of a gpu back to the driver
+    pub fn unassign_device(&mut self, device: u32) {
+        self.assignments
+            .retain(|assignment| assignment.device != device);
+    }
+}
+
+pub fn default_fan_curves_path() -> PathBuf {
+    profile::config_dir().join(FAN_CURVES_FILE_NAME)
+}
+
+// the engine's view of one fan it is driving
+#[derive(Debug, Clone, Copy)]
+struct FanState {
+    // temperature the current target was picked for
+    reference_temp_c: f32,
+    // speed the ramp limit is moving the fan at right now
+    duty_percent: f32,
+    // last speed actually written to the fan
+    applied_percent: Option<u32>,
+}
+
+impl FanState {
+    fn update(&mut self, curve: &FanCurve, temp_c: f32, elapsed_secs: f32) {
+        // follow rising temperatures straight away but only follow falling
+        // ones once they drop past the hysteresis, so the fans do not hunt
+        if temp_c > self.reference_temp_c
+            || temp_c <= self.reference_temp_c - curve.hysteresis_c as f32
+        {
+            self.reference_temp_c = temp_c;
+        }
+
+        let target = curve.speed_at(self.reference_temp_c);
+        let max_step = match curve.ramp_percent_per_sec {
+            0 => f32::INFINITY,
+            ramp => ramp as f32 * elapsed_secs,
+        };
+
+        self.duty_percent += (target - self.duty_percent).clamp(-max_step, max_step);
+    }
+}
+
+// drives fans from their assigned curves on every update tick. Any fan the
+// engine can not read or write is handed back to automatic control and left
+// alone until its assignment changes
+#[derive(Debug, Default)]
+pub struct FanCurveEngine {
+    states: HashMap<(u32, u32), FanState>,
+    failed: HashSet<(u32, u32)>,
+    last_tick: Option<Instant>,
+}
+
+impl FanCurveEngine {
+    // the speed the engine is currently running a fan at
+    pub fn duty(&self, device: u32, fan: u32) -> Option<f32> {
+        self.states
+            .get(&(device, fan))
+            .map(|state| state.duty_percent)
+    }
+
+    // let fans that failed be driven again, called after the assignments change
+    pub fn clear_failures(&mut self) {
+        self.failed.clear();
+    }
+
+    // move every assigned fan one step along its curve, using the readings
+    // from the latest update. Returns what went wrong, if anything
+    pub fn tick(&mut self, store: &FanCurveStore, gpu: &mut Gpu, now: Instant) -> Vec<String> {
+        let elapsed_secs = self
+            .last_tick
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        self.last_tick = Some(now);
+
+        let mut errors = Vec::new();
+
+        // hand back fans that lost their curve since the last tick
+        let released: Vec<(u32, u32)> = self
+            .states
+            .keys()
+            .filter(|(device, fan)| {
+                store
+                    .assigned_curve(*device, *fan)
+                    .and_then(|name| store.curve(name))
+                    .is_none()
+            })
+            .copied()
+            .collect();
+        for (device, fan) in released {
+            self.release(gpu, device, fan, &mut errors);
+        }
+
+        for assignment in &store.assignments {
+            let key = (assignment.device, assignment.fan);
+            let Some(curve) = store.curve(&assignment.curve) else {
+                continue;
+            };
+            if self.failed.contains(&key) {
+                continue;
+            }
+
+            let Some(device) = gpu.device(assignment.device) else {
+                continue;
+            };
+            let temp_c = match &device.sample.temperature_c {
+                Ok(temp_c) => *temp_c as f32,
+                Err(e) => {
+                    self.fail(
+                        gpu,
+                        key,
+                        format!("could not read the temperature: {e}"),
+                        &mut errors,
+                    );
+                    continue;
+                }
+            };
+
+            // start from wherever the fan is now so the ramp limit applies
+            // from the first tick
+            let start = device
+                .sample
+                .fans
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .find(|fan| fan.fan == assignment.fan)
+                .and_then(|fan| fan.speed_percent.as_ref().ok())
+                .map_or_else(|| curve.speed_at(temp_c), |speed| *speed as f32);
+            let state = self.states.entry(key).or_insert(FanState {
+                reference_temp_c: temp_c,
+                duty_percent: start,
+                applied_percent: None,
+            });
+            state.update(curve, temp_c, elapsed_secs);
+
+            let speed = state.duty_percent.round() as u32;
+            if state.applied_percent == Some(speed) {
+                continue;
+            }
+
+            match gpu.set_fan_speed(assignment.device, assignment.fan, speed) {
+                Ok(()) => state.applied_percent = Some(speed),
+                Err(e) => self.fail(
+                    gpu,
+                    key,
+                    format!("could not set the speed: {e}"),
+                    &mut errors,
+                ),
+            }
+        }
+
+        errors
+    }
+
+    // hand every fan the engine is driving back to the driver
+    pub fn release_all(&mut self, gpu: &mut Gpu) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        let driven: Vec<(u32, u32)> = self.states.keys().copied().collect();
+        for (device, fan) in driven {
+            self.release(gpu, device, fan, &mut errors);
+        }
+        self.last_tick = None;
+
+        errors
+    }
+
+    fn fail(&mut self, gpu: &mut Gpu, key: (u32, u32), error: String, errors: &mut Vec<String>) {
+        let (device, fan) = key;
+        errors.push(format!(
+            "GPU {device} fan {fan}: {error}, handing it back to automatic control"
+        ));
+
+        self.failed.insert(key);
+        self.release(gpu, device, fan, errors);
+    }
+
+    fn release(&mut self, gpu: &mut Gpu, device: u32, fan: u32, errors: &mut Vec<String>) {
+        self.states.remove(&(device, fan));
+
+        if let Err(e) = gpu.set_default_fan_speed(device, fan) {
+            errors.push(format!(
+                "GPU {device} fan {fan}: could not restore automatic control: {e}"
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::backend::{FanControlPolicy, SimBackend};
+    use crate::testing::TempDir;
+
+    fn point(temp_c: u32, speed_percent: u32) -> CurvePoint {
+        CurvePoint {
+            temp_c,
+            speed_percent,
+        }
+    }
+
+    fn curve(points: Vec<CurvePoint>) -> FanCurve {
+        FanCurve {
+            points,
+            ..FanCurve::new(String::from("test"))
+        }
+    }
+
+    #[test]
+    fn speed_is_linear_between_points_and_flat_past_the_ends() {
+        let curve = curve(vec![point(40, 30), point(80, 70)]);
+
+        assert_eq!(curve.speed_at(20.0), 30.0);
+        assert_eq!(curve.speed_at(40.0), 30.0);
+        assert_eq!(curve.speed_at(60.0), 50.0);
+        assert_eq!(curve.speed_at(70.0), 60.0);
+        assert_eq!(curve.speed_at(95.0), 70.0);
+        assert_eq!(self::curve(Vec::new()).speed_at(50.0), 100.0);
+    }
+
+    #[test]
+    fn edits_keep_the_points_in_order() {
+        let mut curve = curve(vec![point(40, 30), point(80, 70)]);
+
+        curve.insert(point(60, 50));
+        curve.insert(point(80, 90));
+        curve.insert(point(150, 150));
+        assert_eq!(
+            curve.points,
+            [point(40, 30), point(60, 50), point(80, 90), point(100, 100)]
+        );
+
+        // a point can not be dragged past its neighbours
+        curve.move_point(1, point(95, 55));
+        assert_eq!(curve.points[1], point(79, 55));
+        curve.move_point(1, point(0, 55));
+        assert_eq!(curve.points[1], point(41, 55));
+
+        for _ in 0..5 {
+            curve.remove(0);
+        }
+        assert_eq!(curve.points, [point(100, 100)]);
+    }
+
+    #[test]
+    fn hysteresis_holds_the_speed_until_the_temperature_drops_far_enough() {
+        let curve = FanCurve {
+            hysteresis_c: 5,
+            ramp_percent_per_sec: 0,
+            ..curve(vec![point(40, 40), point(80, 80)])
+        };
+        let mut state = FanState {
+            reference_temp_c: 60.0,
+            duty_percent: 60.0,
+            applied_percent: None,
+        };
+
+        state.update(&curve, 57.0, 1.0);
+        assert_eq!(state.duty_percent, 60.0);
+        state.update(&curve, 70.0, 1.0);
+        assert_eq!(state.duty_percent, 70.0);
+        state.update(&curve, 65.0, 1.0);
+        assert_eq!(state.duty_percent, 65.0);
+    }
+
+    #[test]
+    fn ramp_limits_how_fast_the_speed_changes() {
+        let curve = FanCurve {
+            ramp_percent_per_sec: 10,
+            ..curve(vec![point(40, 30), point(80, 100)])
+        };
+        let mut state = FanState {
+            reference_temp_c: 40.0,
+            duty_percent: 30.0,
+            applied_percent: None,
+        };
+
+        state.update(&curve, 80.0, 2.0);
+        assert_eq!(state.duty_percent, 50.0);
+        state.update(&curve, 80.0, 0.5);
+        assert_eq!(state.duty_percent, 55.0);
+        state.update(&curve, 80.0, 10.0);
+        assert_eq!(state.duty_percent, 100.0);
+    }
+
+    #[test]
+    fn store_survives_a_round_trip() {
+        let dir = TempDir::new();
+        let path = dir.join("config/fan_curves.toml");
+
+        let mut store = FanCurveStore::default();
+        store.create("quiet").unwrap();
+        store.create("loud").unwrap();
+        store.assign(0, 1, Some(String::from("quiet")));
+        store.save(&path).unwrap();
+
+        let loaded = FanCurveStore::load(&path).unwrap();
+        assert_eq!(loaded.curves, store.curves);
+        assert_eq!(loaded.assignments, store.assignments);
+        assert!(
+            FanCurveStore::load(&dir.join("missing.toml"))
+                .unwrap()
+                .curves
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn store_names_and_assignments() {
+        let mut store = FanCurveStore::default();
+        store.create(" quiet ").unwrap();
+        assert!(store.create("quiet").is_err());
+        assert!(store.create(" ").is_err());
+
+        store.assign(0, 0, Some(String::from("quiet")));
+        store.assign(0, 1, Some(String::from("quiet")));
+        store.assign(0, 1, None);
+        assert_eq!(store.assigned_curve(0, 0), Some("quiet"));
+        assert_eq!(store.assigned_curve(0, 1), None);
+
+        // deleting a curve hands its fans back
+        store.delete("quiet");
+        assert!(store.assignments.is_empty());
+    }
+
+    #[test]
+    fn engine_drives_assigned_fans_and_releases_them() {
+        let mut gpu = Gpu::with_backend(Box::new(SimBackend::new(1))).unwrap();
+        gpu.update_gpu_info();
+        let temp_c = *gpu.devices[0].sample.temperature_c.as_ref().unwrap();
+
+        let mut store = FanCurveStore::default();
+        store.curves.push(FanCurve {
+            ramp_percent_per_sec: 0,
+            ..curve(vec![point(0, 20), point(100, 100)])
+        });
+        store.assign(0, 0, Some(String::from("test")));
+
+        let mut engine = FanCurveEngine::default();
+        let now = Instant::now();
+        assert!(engine.tick(&store, &mut gpu, now).is_empty());
+
+        let expected = store.curves[0].speed_at(temp_c as f32).round() as u32;
+        gpu.update_gpu_info();
+        let fan = &gpu.devices[0].sample.fans.as_ref().unwrap()[0];
+        assert_eq!(fan.policy, Ok(FanControlPolicy::Manual));
+        assert_eq!(fan.speed_percent, Ok(expected));
+
+        store.assign(0, 0, None);
+        assert!(
+            engine
+                .tick(&store, &mut gpu, now + Duration::from_secs(1))
+                .is_empty()
+        );
+        assert_eq!(engine.duty(0, 0), None);
+        gpu.update_gpu_info();
+        let fan = &gpu.devices[0].sample.fans.as_ref().unwrap()[0];
+        assert_eq!(fan.policy, Ok(FanControlPolicy::Auto));
+    }
+
+    #[test]
+    fn engine_gives_up_on_fans_it_can_not_write() {
+        let mut gpu = Gpu::with_backend(Box::new(SimBackend::new(1))).unwrap();
+        gpu.update_gpu_info();
+
+        let mut store = FanCurveStore::default();
+        store.create("test").unwrap();
+        store.assign(0, 5, Some(String::from("test")));
+
+        let mut engine = FanCurveEngine::default();
+        let now = Instant::now();
+        let errors = engine.tick(&store, &mut gpu, now);
+        assert!(
+            errors[0].starts_with("GPU 0 fan 5: could not set the speed"),
+            "{errors:?}"
+        );
+
+        // left alone until the assignments change
+        assert!(engine.tick(&store, &mut gpu, now).is_empty());
+        engine.clear_failures();
+        assert!(!engine.tick(&store, &mut gpu, now).is_empty());
+    }
+}
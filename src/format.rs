@@ -0,0 +1,142 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+
+use crate::backend::{ClockLock, PowerConstraints};
+use crate::telemetry::{
+    FanReading, Metric, TelemetrySample, active_throttle_reasons, bytes_to_mib, mw_to_watts,
+};
+
+// text formatting shared by the gui and the command line
+
+// show a reading, or N/A when it could not be taken
+pub fn metric_text<T: ToString>(metric: &Metric<T>) -> String {
+    match metric {
+        Ok(value) => value.to_string(),
+        Err(_) => String::from("N/A"),
+    }
+}
+
+// show a reading through the given formatter, or N/A when it could not be taken
+pub fn metric_text_with<T>(metric: &Metric<T>, format: impl FnOnce(&T) -> String) -> String {
+    match metric {
+        Ok(value) => format(value),
+        Err(_) => String::from("N/A"),
+    }
+}
+
+pub fn power_text(power_mw: &Metric<u32>) -> String {
+    metric_text_with(power_mw, |power_mw| {
+        format!("{:.1} W", mw_to_watts(*power_mw))
+    })
+}
+
+// the supported power limit range and the default limit
+pub fn power_constraints_text(constraints: &Metric<PowerConstraints>) -> String {
+    match constraints {
+        Ok(constraints) => format!(
+            "{}-{} W, default {} W",
+            mw_to_watts(constraints.min_mw),
+            mw_to_watts(constraints.max_mw),
+            mw_to_watts(constraints.default_mw)
+        ),
+        Err(_) => String::from("Power limit not available"),
+    }
+}
+
+// memory usage as used/total in MiB
+pub fn mem_usage_text(sample: &TelemetrySample) -> String {
+    match (&sample.mem_used_bytes, &sample.mem_total_bytes) {
+        (Ok(used), Ok(total)) => {
+            format!("{} MiB/{} MiB", bytes_to_mib(*used), bytes_to_mib(*total))
+        }
+        _ => String::from("N/A"),
+    }
+}
+
+// speed, RPM, target and policy of a fan, leaving out what could not be read
+pub fn fan_text(fan: &FanReading) -> String {
+    let mut text = metric_text_with(&fan.speed_percent, |speed| format!("{speed} %"));
+
+    if let Ok(rpm) = &fan.speed_rpm {
+        text += &format!(", {rpm} RPM");
+    }
+    if let Ok(target) = &fan.target_percent {
+        text += &format!(", target {target} %");
+    }
+    if let Ok(policy) = &fan.policy {
+        text += &format!(", {policy}");
+    }
+
+    text
+}
+
+// a clock lock as a range, or a single clock when both ends are the same
+pub fn clock_lock_text(lock: ClockLock) -> String {
+    if lock.min_mhz == lock.max_mhz {
+        format!("{} MHz", lock.min_mhz)
+    } else {
+        format!("{}-{} MHz", lock.min_mhz, lock.max_mhz)
+    }
+}
+
+// the range of clocks a gpu supports and how many steps there are in it
+pub fn supported_clocks_text(clocks: &Metric<Vec<u32>>) -> String {
+    match clocks {
+        Ok(clocks) => match (clocks.iter().min(), clocks.iter().max()) {
+            (Some(lowest), Some(highest)) => {
+                format!("{lowest}-{highest} MHz, {} clocks", clocks.len())
+            }
+            _ => String::from("N/A"),
+        },
+        Err(_) => String::from("N/A"),
+    }
+}
+
+// the active throttle reasons as a readable list
+pub fn throttle_text(reasons: &Metric<ThrottleReasons>) -> String {
+    metric_text_with(reasons, |reasons| {
+        let labels: Vec<&str> = active_throttle_reasons(*reasons)
+            .map(|reason| reason.label)
+            .collect();
+
+        if labels.is_empty() {
+            String::from("None")
+        } else {
+            labels.join(", ")
+        }
+    })
+}
+
+// a point in time as UTC date and time, down to the second
+pub fn timestamp_text(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // civil date from days since the epoch, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
@@ -1,198 +1,940 @@
-use nvml_wrapper::enum_wrappers::device::{Clock, TemperatureSensor};
-use nvml_wrapper::{
-    Nvml,
-    error::{NvmlError, nvml_try},
-};
-use nvml_wrapper_sys::bindings::{NvmlLib, nvmlDevice_t, nvmlReturn_t};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::Clock;
+
+use crate::backend::{self, BackendKind, ClockLock, GpuBackend, OffsetRange, PowerConstraints};
+use crate::error::GpuError;
+use crate::profile::{FanPolicy, Profile};
+use crate::telemetry::{CLOCK_COUNT, FanReading, Metric, TelemetrySample, mw_to_watts};
 
 // define an array of available clocks to iterate through
-const CLOCKS_ARRAY: [Clock; 4] = [Clock::Graphics, Clock::SM, Clock::Memory, Clock::Video];
+pub const CLOCKS_ARRAY: [Clock; CLOCK_COUNT] =
+    [Clock::Graphics, Clock::SM, Clock::Memory, Clock::Video];
+
+// the info for a single gpu
+pub struct DeviceState {
+    pub index: u32,
+    pub name: Metric<String>,
+    // these do not change at runtime so they are only read once
+    pub power_constraints: Metric<PowerConstraints>,
+    pub gpc_offset_range: Metric<OffsetRange>,
+    pub mem_offset_range: Metric<OffsetRange>,
+    // clocks the gpu can be locked to, highest first
+    pub graphics_clocks: Metric<Vec<u32>>,
+    pub memory_clocks: Metric<Vec<u32>>,
+    // locks set through this app, the driver has no way to read them back
+    pub gpu_clock_lock: Option<ClockLock>,
+    pub mem_clock_lock: Option<ClockLock>,
+    // the most recent readings
+    pub sample: TelemetrySample,
+    pub core_offset: Metric<i32>,
+    pub mem_offset: Metric<i32>,
+}
+
+// samplers read on their own thread through a reader handle of the backend,
+// so the lock on the one the writes go through is never held across a sweep
+type SharedBackend = Arc<Mutex<Box<dyn GpuBackend>>>;
+
+fn lock(backend: &SharedBackend) -> MutexGuard<'_, Box<dyn GpuBackend>> {
+    // a panic while holding the lock leaves nothing half written that
+    // matters, the next call just talks to the driver again
+    backend.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// everything read from one gpu on every update
+#[derive(Debug, Clone)]
+pub struct DeviceReadings {
+    pub sample: TelemetrySample,
+    pub core_offset: Metric<i32>,
+    pub mem_offset: Metric<i32>,
+}
+
+impl DeviceReadings {
+    // placeholder readings, filled in group by group
+    fn empty(index: u32) -> Self {
+        Self {
+            sample: TelemetrySample::empty(index),
+            core_offset: Err(GpuError::NotSupported),
+            mem_offset: Err(GpuError::NotSupported),
+        }
+    }
+}
+
+// readings that are always taken together, so each group can be polled at
+// its own rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetricGroup {
+    // power draw, power limit and utilization
+    Power,
+    // current clocks, throttle reasons and offsets
+    Clocks,
+    // temperature and fans
+    Thermal,
+    Memory,
+    // max clocks, they do not change so they only need reading once
+    Static,
+}
+
+impl MetricGroup {
+    pub const ALL: [MetricGroup; 5] = [
+        MetricGroup::Power,
+        MetricGroup::Clocks,
+        MetricGroup::Thermal,
+        MetricGroup::Memory,
+        MetricGroup::Static,
+    ];
+}
+
+impl std::fmt::Display for MetricGroup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetricGroup::Power => write!(f, "Power"),
+            MetricGroup::Clocks => write!(f, "Clocks"),
+            MetricGroup::Thermal => write!(f, "Thermal"),
+            MetricGroup::Memory => write!(f, "Memory"),
+            MetricGroup::Static => write!(f, "Static"),
+        }
+    }
+}
+
+// takes readings of every gpu without needing the Gpu itself, so sampling
+// can run on another thread and hand the results back
+#[derive(Clone)]
+pub struct Sampler {
+    backend: SharedBackend,
+    indices: Vec<u32>,
+}
+
+impl Sampler {
+    // a full set of readings of every gpu
+    pub fn sample(&self) -> Vec<DeviceReadings> {
+        let mut readings = self
+            .indices
+            .iter()
+            .map(|&index| DeviceReadings::empty(index))
+            .collect::<Vec<_>>();
+        self.update(&mut readings, &MetricGroup::ALL);
+
+        readings
+    }
+
+    // read only the given groups again, keeping the rest of the readings
+    pub fn update(&self, readings: &mut [DeviceReadings], groups: &[MetricGroup]) {
+        // let the backend take a fresh sample
+        lock(&self.backend).refresh();
+
+        // the lock is taken per gpu so other samplers get a turn
+        for readings in readings.iter_mut() {
+            let backend = lock(&self.backend);
+            for &group in groups {
+                read_group(backend.as_ref(), group, readings);
+            }
+            readings.sample.timestamp = std::time::SystemTime::now();
+        }
+    }
+}
+
+// the clocks that can be locked to a range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockedClock {
+    Graphics,
+    Memory,
+}
+
+impl std::fmt::Display for LockedClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockedClock::Graphics => write!(f, "graphics"),
+            LockedClock::Memory => write!(f, "memory"),
+        }
+    }
+}
+
+// how resetting one part of a gpu went
+#[derive(Debug)]
+pub struct ResetStep {
+    pub device: u32,
+    pub part: String,
+    pub result: Result<(), GpuError>,
+}
+
+impl ResetStep {
+    // parts the gpu does not support have nothing to reset, so only real
+    // errors count as failures
+    pub fn failed(&self) -> bool {
+        self.result.as_ref().is_err_and(|e| !e.is_not_supported())
+    }
+}
+
+impl std::fmt::Display for ResetStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "GPU {} {}: ", self.device, self.part)?;
+        match &self.result {
+            Ok(()) => write!(f, "reset"),
+            Err(GpuError::NotSupported) => write!(f, "not supported"),
+            Err(e) if e.is_not_supported() => write!(f, "not supported, {e}"),
+            Err(e) => write!(f, "failed, {e}"),
+        }
+    }
+}
+
+// what a reset to defaults did, part by part
+#[derive(Debug, Default)]
+pub struct ResetReport {
+    pub steps: Vec<ResetStep>,
+}
+
+impl ResetReport {
+    pub fn failures(&self) -> impl Iterator<Item = &ResetStep> {
+        self.steps.iter().filter(|step| step.failed())
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    fn record(&mut self, device: u32, part: impl Into<String>, result: Result<(), GpuError>) {
+        self.steps.push(ResetStep {
+            device,
+            part: part.into(),
+            result,
+        });
+    }
+}
 
 // struct to hold all the gpu info. Should be available to the main application
 pub struct Gpu {
-    pub nvml: Nvml,
-    pub power_watts: String,
-    pub gpu_temp: String,
-    pub gpu_mem_free: String,
-    pub gpu_mem_total: String,
-    pub gpu_mem_used: String,
-    pub fan_speed: String,
-    pub gpu_utilization: u32,
-    pub mem_utilization: u32,
-    pub clock_speed_array: [u32; 4],
-    pub clock_speed_max_array: [u32; 4],
-}
-
-fn get_value<T, F>(f: F) -> Result<T, Option<NvmlError>>
-where
-    T: Default,
-    F: FnOnce(*mut T) -> nvmlReturn_t,
-{
-    let mut value = T::default();
-    let status = f(&mut value);
-    if status == 0 {
-        Ok(value)
-    } else {
-        Err(nvml_try(status).err())
+    backend: SharedBackend,
+    // only for readings, shared by every sampler
+    reader: SharedBackend,
+    // read once, it can not change while the driver is loaded
+    driver_version: Metric<String>,
+    pub devices: Vec<DeviceState>,
+}
+
+impl DeviceState {
+    fn new(index: u32, backend: &dyn GpuBackend) -> Self {
+        let memory_clocks = backend.supported_memory_clocks(index);
+        // the graphics clocks on offer depend on the memory clock, take the
+        // ones for the fastest memory clock as that is where the gpu runs
+        let graphics_clocks = memory_clocks.clone().and_then(|clocks| {
+            let mem_clock = clocks.iter().max().copied().unwrap_or(0);
+            backend.supported_graphics_clocks(index, mem_clock)
+        });
+
+        Self {
+            index,
+            name: backend.name(index),
+            power_constraints: backend.power_constraints(index),
+            gpc_offset_range: backend.gpc_clk_vf_offset_range(index),
+            mem_offset_range: backend.mem_clk_vf_offset_range(index),
+            graphics_clocks,
+            memory_clocks,
+            gpu_clock_lock: None,
+            mem_clock_lock: None,
+            sample: TelemetrySample::empty(index),
+            core_offset: Err(GpuError::NotSupported),
+            mem_offset: Err(GpuError::NotSupported),
+        }
+    }
+
+    pub fn supported_clocks(&self, clock: LockedClock) -> &Metric<Vec<u32>> {
+        match clock {
+            LockedClock::Graphics => &self.graphics_clocks,
+            LockedClock::Memory => &self.memory_clocks,
+        }
+    }
+
+    pub fn clock_lock(&self, clock: LockedClock) -> Option<ClockLock> {
+        match clock {
+            LockedClock::Graphics => self.gpu_clock_lock,
+            LockedClock::Memory => self.mem_clock_lock,
+        }
+    }
+
+    fn clock_lock_mut(&mut self, clock: LockedClock) -> &mut Option<ClockLock> {
+        match clock {
+            LockedClock::Graphics => &mut self.gpu_clock_lock,
+            LockedClock::Memory => &mut self.mem_clock_lock,
+        }
+    }
+}
+
+// take one group of readings from a gpu. Every metric is read on its own
+// so one unsupported query does not take the others down with it
+fn read_group(backend: &dyn GpuBackend, group: MetricGroup, readings: &mut DeviceReadings) {
+    let sample = &mut readings.sample;
+    let index = sample.device_index;
+
+    // split a combined reading out into its parts
+    fn part<T, U: Copy>(metric: &Metric<T>, field: fn(&T) -> U) -> Metric<U> {
+        metric.as_ref().map(field).map_err(Clone::clone)
+    }
+
+    match group {
+        MetricGroup::Power => {
+            let utilization_rates = backend.utilization_rates(index);
+
+            sample.power_mw = backend.power_usage(index);
+            sample.power_limit_mw = backend.enforced_power_limit(index);
+            sample.gpu_utilization_percent = part(&utilization_rates, |rates| rates.gpu);
+            sample.mem_utilization_percent = part(&utilization_rates, |rates| rates.memory);
+        }
+        MetricGroup::Clocks => {
+            // loop through the clocks for the current speeds
+            sample.clocks_mhz =
+                std::array::from_fn(|clock| backend.clock_info(index, CLOCKS_ARRAY[clock].clone()));
+            sample.throttle_reasons = backend.throttle_reasons(index);
+            readings.core_offset = backend.gpc_clk_vf_offset(index);
+            readings.mem_offset = backend.mem_clk_vf_offset(index);
+        }
+        MetricGroup::Thermal => {
+            sample.temperature_c = backend.temperature(index);
+            sample.fans = read_fans(backend, index);
+        }
+        MetricGroup::Memory => {
+            let mem_info = backend.memory_info(index);
+
+            sample.mem_free_bytes = part(&mem_info, |mem_info| mem_info.free);
+            sample.mem_used_bytes = part(&mem_info, |mem_info| mem_info.used);
+            sample.mem_total_bytes = part(&mem_info, |mem_info| mem_info.total);
+        }
+        MetricGroup::Static => {
+            sample.max_clocks_mhz = std::array::from_fn(|clock| {
+                backend.max_clock_info(index, CLOCKS_ARRAY[clock].clone())
+            });
+        }
     }
 }
 
+// read every fan the gpu reports
+fn read_fans(backend: &dyn GpuBackend, index: u32) -> Metric<Vec<FanReading>> {
+    let num_fans = backend.num_fans(index)?;
+
+    Ok((0..num_fans)
+        .map(|fan| FanReading {
+            fan,
+            speed_percent: backend.fan_speed(index, fan),
+            target_percent: backend.target_fan_speed(index, fan),
+            speed_rpm: backend.fan_speed_rpm(index, fan),
+            policy: backend.fan_control_policy(index, fan),
+        })
+        .collect())
+}
+
 impl Gpu {
-    pub fn new() -> Self {
-        // initialize values to something sane
-        Self {
-            // actually initialize the NVML library here...
-            nvml: Nvml::init().expect("Failed to initialize NVML"),
-            power_watts: 0.to_string(),
-            gpu_temp: 0.to_string(),
-            gpu_mem_free: 0.to_string(),
-            gpu_mem_total: 0.to_string(),
-            gpu_mem_used: 0.to_string(),
-            fan_speed: 0.to_string(),
-            gpu_utilization: 0,
-            mem_utilization: 0,
-            clock_speed_array: [0; 4],
-            clock_speed_max_array: [0; 4],
-        }
-    }
-
-    // function to update the gpu information.
-    // this fn will be run on a subscription by the main iced runtime
+    pub fn new(kind: BackendKind) -> Result<Self, GpuError> {
+        // actually initialize the backend here...
+        Self::with_backend(backend::create(kind)?)
+    }
+
+    // wrap a backend that is already set up
+    pub fn with_backend(backend: Box<dyn GpuBackend>) -> Result<Self, GpuError> {
+        // enumerate every gpu the backend can see
+        let device_count = backend.device_count()?;
+        let devices = (0..device_count)
+            .map(|index| DeviceState::new(index, backend.as_ref()))
+            .collect();
+
+        Ok(Self {
+            driver_version: backend.driver_version(),
+            reader: Arc::new(Mutex::new(backend.reader()?)),
+            backend: Arc::new(Mutex::new(backend)),
+            devices,
+        })
+    }
+
+    fn backend(&self) -> MutexGuard<'_, Box<dyn GpuBackend>> {
+        lock(&self.backend)
+    }
+
+    // a sampler for every gpu, to take readings away from the caller's thread
+    pub fn sampler(&self) -> Sampler {
+        Sampler {
+            backend: Arc::clone(&self.reader),
+            indices: self.devices.iter().map(|device| device.index).collect(),
+        }
+    }
+
+    // read every device right here, for callers that do not mind waiting
     pub fn update_gpu_info(&mut self) {
-        // create the device to read info from
-        let nvml_device = self
-            .nvml
-            .device_by_index(0)
-            .expect("Failed to get device by index 0");
-
-        // get the power from the device in milliwatts
-        let power_mw = nvml_device
-            .power_usage()
-            .expect("Failed to get power usage");
-
-        // divide it out to make Watts
-        let power_watts = power_mw / 1000;
-
-        // convert to a string
-        self.power_watts = power_watts.to_string();
-
-        // loop through the clocks
-        for (index, clock) in CLOCKS_ARRAY.iter().enumerate() {
-            // get the current clock speeds
-            self.clock_speed_array[index] = nvml_device
-                .clock_info(clock.clone())
-                .expect("Failed to get clock speed");
-
-            // get the max clock speeds
-            self.clock_speed_max_array[index] = nvml_device
-                .max_clock_info(clock.clone())
-                .expect("Failed to get max clock speed");
-        }
-
-        // get the gpu temp
-        self.gpu_temp = nvml_device
-            .temperature(TemperatureSensor::Gpu)
-            .expect("Failed to get temp sensor info")
-            .to_string();
-
-        // get the memory info struct from the device
-        let mem_info: nvml_wrapper::struct_wrappers::device::MemoryInfo = nvml_device
-            .memory_info()
-            .expect("Failed to get memopry info");
-
-        // convert memory info to scaled strings
-        self.gpu_mem_free = (mem_info.free / 1024000).to_string();
-        self.gpu_mem_used = (mem_info.used / 1024000).to_string();
-        self.gpu_mem_total = (mem_info.total / 1024000).to_string();
-
-        // read in the fan speed for fan 0
-        // Probably need to check which fans are available and see if there
-        // needs to be an array of fans
-        self.fan_speed = nvml_device
-            .fan_speed(0)
-            .expect("Unable to get speed for fan 0")
-            .to_string();
-
-        // get the utilization rates
-        let utilization_rates: nvml_wrapper::struct_wrappers::device::Utilization = nvml_device
-            .utilization_rates()
-            .expect("Failed to get utilization rates");
-
-        // split it out into variables
-        self.gpu_utilization = utilization_rates.gpu;
-        self.mem_utilization = utilization_rates.memory;
-    }
-
-    pub fn get_gpu_name(&self) -> String {
-        let nvml_device = self
-            .nvml
-            .device_by_index(0)
-            .expect("Failed to get device by index 0");
-
-        nvml_device.name().expect("Failed to get device name")
-    }
-
-    pub fn get_driver_version(&self) -> String {
-        self.nvml
-            .sys_driver_version()
-            .expect("Could not get driver version")
-    }
-
-    // attempt to apply the overclock to the gpu
-    pub fn apply_oc(&self, core_offset: String, mem_offset: String) -> Result<(), String> {
-        // setup variables
-        let nvml_device = self.nvml.device_by_index(0).expect("Failed to get device");
-        let core_off_int = core_offset.parse::<i32>().unwrap();
-        let mem_off_int = mem_offset.parse::<i32>().unwrap();
-
-        // check to see if we are running as root.
-        if sudo2::running_as_root() {
-            // run unsafe block
-            unsafe {
-                let raw_device_handle: nvmlDevice_t = nvml_device.handle();
-                let nvml_lib =
-                    NvmlLib::new("libnvidia-ml.so").expect("Failed to load NVML Library");
-
-                // attempt to set the GPU clock offset
-                let result = nvml_lib.nvmlDeviceSetGpcClkVfOffset(raw_device_handle, core_off_int);
-                if result != 0 {
-                    return Err(format!("{}", result));
+        let readings = self.sampler().sample();
+        self.store(readings);
+    }
+
+    // keep readings taken by a sampler
+    pub fn store(&mut self, readings: Vec<DeviceReadings>) {
+        for readings in readings {
+            if let Some(device) = self.devices.get_mut(readings.sample.device_index as usize) {
+                device.sample = readings.sample;
+                device.core_offset = readings.core_offset;
+                device.mem_offset = readings.mem_offset;
+            }
+        }
+    }
+
+    pub fn device(&self, index: u32) -> Option<&DeviceState> {
+        self.devices.get(index as usize)
+    }
+
+    pub fn get_driver_version(&self) -> Result<String, GpuError> {
+        self.driver_version.clone()
+    }
+
+    // attempt to apply the overclock to each of the given gpus
+    pub fn apply_oc(
+        &mut self,
+        devices: &[u32],
+        core_offset: i32,
+        mem_offset: i32,
+    ) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            gpu.apply_oc_device(index, core_offset, mem_offset)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    // the core and memory offset ranges every one of the given gpus supports.
+    // devices that can not report a range are left out, None means no device could
+    pub fn offset_ranges(&self, devices: &[u32]) -> (Option<OffsetRange>, Option<OffsetRange>) {
+        let common = |range: fn(&DeviceState) -> &Metric<OffsetRange>| {
+            devices
+                .iter()
+                .filter_map(|&index| self.device(index))
+                .filter_map(|device| range(device).as_ref().ok())
+                .copied()
+                .reduce(|a, b| OffsetRange {
+                    min: a.min.max(b.min),
+                    max: a.max.min(b.max),
+                })
+        };
+
+        (
+            common(|device| &device.gpc_offset_range),
+            common(|device| &device.mem_offset_range),
+        )
+    }
+
+    // the clocks every one of the given gpus can be locked to, highest first
+    pub fn supported_clocks(&self, devices: &[u32], clock: LockedClock) -> Vec<u32> {
+        let mut lists = devices
+            .iter()
+            .filter_map(|&index| self.device(index))
+            .filter_map(|device| device.supported_clocks(clock).as_ref().ok());
+
+        let Some(first) = lists.next() else {
+            return Vec::new();
+        };
+
+        let mut common = first.clone();
+        for list in lists {
+            common.retain(|mhz| list.contains(mhz));
+        }
+        common.sort_unstable_by(|a, b| b.cmp(a));
+        common
+    }
+
+    // keep a clock of each of the given gpus within a range
+    pub fn lock_clocks(
+        &mut self,
+        devices: &[u32],
+        clock: LockedClock,
+        lock: ClockLock,
+    ) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            gpu.validate_clock_lock(index, clock, lock)?;
+
+            let result = match clock {
+                LockedClock::Graphics => gpu.backend().set_gpu_locked_clocks(index, lock),
+                LockedClock::Memory => gpu.backend().set_mem_locked_clocks(index, lock),
+            };
+            result.map_err(|e| e.to_string())?;
+
+            if let Some(device) = gpu.devices.get_mut(index as usize) {
+                *device.clock_lock_mut(clock) = Some(lock);
+            }
+            Ok(())
+        })
+    }
+
+    // let the driver pick a clock of each of the given gpus again
+    pub fn unlock_clocks(&mut self, devices: &[u32], clock: LockedClock) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            let result = match clock {
+                LockedClock::Graphics => gpu.backend().reset_gpu_locked_clocks(index),
+                LockedClock::Memory => gpu.backend().reset_mem_locked_clocks(index),
+            };
+            result.map_err(|e| e.to_string())?;
+
+            if let Some(device) = gpu.devices.get_mut(index as usize) {
+                *device.clock_lock_mut(clock) = None;
+            }
+            Ok(())
+        })
+    }
+
+    // check a clock lock against the clocks the device supports
+    pub fn validate_clock_lock(
+        &self,
+        index: u32,
+        clock: LockedClock,
+        lock: ClockLock,
+    ) -> Result<(), String> {
+        if lock.min_mhz > lock.max_mhz {
+            return Err(format!(
+                "the lowest {clock} clock of {} MHz is above the highest of {} MHz",
+                lock.min_mhz, lock.max_mhz
+            ));
+        }
+
+        let clocks = self
+            .device(index)
+            .ok_or_else(|| String::from("no such device"))?
+            .supported_clocks(clock)
+            .as_ref()
+            .map_err(|e| format!("{clock} clocks can not be locked: {e}"))?;
+
+        if let (Some(lowest), Some(highest)) = (clocks.iter().min(), clocks.iter().max())
+            && (lock.min_mhz < *lowest || lock.max_mhz > *highest)
+        {
+            return Err(format!(
+                "{}-{} MHz is outside the supported {clock} clocks of {lowest}-{highest} MHz",
+                lock.min_mhz, lock.max_mhz
+            ));
+        }
+
+        Ok(())
+    }
+
+    // attempt to set the power limit, given in watts, on each of the given gpus
+    pub fn apply_power_limit(&mut self, devices: &[u32], limit_watts: f64) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            let limit_mw = gpu.validate_power_limit(index, limit_watts)?;
+
+            gpu.backend()
+                .set_power_limit(index, limit_mw)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    // check a power limit in watts against what the device supports,
+    // returning it in milliwatts
+    pub fn validate_power_limit(&self, index: u32, limit_watts: f64) -> Result<u32, String> {
+        let limit_mw = (limit_watts * 1000.0).round();
+
+        let constraints = self
+            .device(index)
+            .ok_or_else(|| String::from("no such device"))?
+            .power_constraints
+            .as_ref()
+            .map_err(|e| format!("power limit can not be changed: {e}"))?;
+
+        if limit_mw < constraints.min_mw as f64 || limit_mw > constraints.max_mw as f64 {
+            return Err(format!(
+                "{limit_watts} W is outside the supported range of {}-{} W",
+                mw_to_watts(constraints.min_mw),
+                mw_to_watts(constraints.max_mw)
+            ));
+        }
+
+        Ok(limit_mw as u32)
+    }
+
+    // set the fans of each of the given gpus to the policy
+    pub fn apply_fan_policy(&mut self, devices: &[u32], policy: FanPolicy) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            let num_fans = gpu.backend().num_fans(index);
+
+            // cards without fan control are always on automatic
+            let num_fans = match (num_fans, policy) {
+                (Ok(num_fans), _) => num_fans,
+                (Err(GpuError::NotSupported), FanPolicy::Auto) => return Ok(()),
+                (Err(e), _) => return Err(e.to_string()),
+            };
+
+            for fan in 0..num_fans {
+                let result = match policy {
+                    FanPolicy::Auto => gpu.backend().set_default_fan_speed(index, fan),
+                    FanPolicy::Fixed { speed } => gpu.backend().set_fan_speed(index, fan, speed),
+                };
+
+                match (result, policy) {
+                    (Err(e), FanPolicy::Auto) if e.is_not_supported() => {}
+                    (Err(e), _) => return Err(format!("fan {fan}: {e}")),
+                    (Ok(()), _) => {}
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    // apply every setting stored in a profile to each of the given gpus
+    pub fn apply_profile(&mut self, devices: &[u32], profile: &Profile) -> Result<(), String> {
+        let offsets = self.apply_oc(devices, profile.core_offset, profile.mem_offset);
+        let rest = self.apply_power_and_fans(devices, profile);
+
+        match (offsets, rest) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(e), Ok(())) | (Ok(()), Err(e)) => Err(e),
+            (Err(offsets), Err(rest)) => Err(format!("{offsets}\n{rest}")),
+        }
+    }
+
+    // apply the settings of a profile other than the offsets
+    pub fn apply_power_and_fans(
+        &mut self,
+        devices: &[u32],
+        profile: &Profile,
+    ) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        if let Some(limit_watts) = profile.power_limit_watts
+            && let Err(e) = self.apply_power_limit(devices, limit_watts)
+        {
+            errors.push(e);
+        }
+
+        if let Err(e) = self.apply_fan_policy(devices, profile.fan_policy) {
+            errors.push(e);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    // set only the core offset on each of the given gpus
+    pub fn apply_core_offset(&mut self, devices: &[u32], core_offset: i32) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            gpu.backend()
+                .set_gpc_clk_vf_offset(index, core_offset)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    // set only the memory offset on each of the given gpus
+    pub fn apply_mem_offset(&mut self, devices: &[u32], mem_offset: i32) -> Result<(), String> {
+        self.apply_to_devices(devices, |gpu, index| {
+            gpu.backend()
+                .set_mem_clk_vf_offset(index, mem_offset)
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    // put every tunable of the given gpus back to its default: both offsets
+    // to 0, the default power limit, fans on automatic control and no
+    // locked clocks. Every part is tried even when an earlier one fails
+    pub fn reset_defaults(&mut self, devices: &[u32]) -> Result<ResetReport, GpuError> {
+        self.check_write_access()?;
+
+        let mut report = ResetReport::default();
+        for &index in devices {
+            let mut backend = lock(&self.backend);
+
+            report.record(
+                index,
+                "core offset",
+                backend.set_gpc_clk_vf_offset(index, 0),
+            );
+            report.record(
+                index,
+                "memory offset",
+                backend.set_mem_clk_vf_offset(index, 0),
+            );
+
+            let power_limit = backend
+                .power_constraints(index)
+                .and_then(|constraints| backend.set_power_limit(index, constraints.default_mw));
+            report.record(index, "power limit", power_limit);
+
+            match backend.num_fans(index) {
+                Ok(num_fans) => {
+                    for fan in 0..num_fans {
+                        let result = backend.set_default_fan_speed(index, fan);
+                        report.record(index, format!("fan {fan}"), result);
+                    }
                 }
+                Err(e) => report.record(index, "fans", Err(e)),
+            }
 
-                // attempt to set the gpu mem offset
-                let result = nvml_lib.nvmlDeviceSetMemClkVfOffset(raw_device_handle, mem_off_int);
-                if result != 0 {
-                    return Err(format!("{}", result));
-                } else {
-                    return Ok(());
+            for clock in [LockedClock::Graphics, LockedClock::Memory] {
+                let result = match clock {
+                    LockedClock::Graphics => backend.reset_gpu_locked_clocks(index),
+                    LockedClock::Memory => backend.reset_mem_locked_clocks(index),
+                };
+                if result.is_ok()
+                    && let Some(device) = self.devices.get_mut(index as usize)
+                {
+                    *device.clock_lock_mut(clock) = None;
                 }
-            };
+                report.record(index, format!("{clock} clock lock"), result);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // check to see if we are allowed to change settings, which for NVML
+    // means running as root
+    pub fn check_write_access(&self) -> Result<(), GpuError> {
+        if self.backend().requires_root() && !sudo2::running_as_root() {
+            return Err(GpuError::NoPermission);
         }
+
+        Ok(())
+    }
+
+    // run a write operation on each of the given gpus, collecting the errors
+    fn apply_to_devices<F>(&mut self, devices: &[u32], mut apply: F) -> Result<(), String>
+    where
+        F: FnMut(&mut Self, u32) -> Result<(), String>,
+    {
         // if we are not running as root then return an error.
-        else {
-            Err(format!("Not running as root"))
+        self.check_write_access().map_err(|e| e.to_string())?;
+
+        // keep going after a failure so one bad card does not block the rest
+        let mut errors = Vec::new();
+        for &index in devices {
+            if let Err(e) = apply(self, index) {
+                errors.push(format!("GPU {index}: {e}"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
         }
     }
 
-    pub fn get_gpu_offset(&self) -> Result<i32, Option<NvmlError>> {
-        let nvml_device = self.nvml.device_by_index(0).expect("Failed to get device");
+    // apply the offsets to a single gpu
+    fn apply_oc_device(
+        &mut self,
+        index: u32,
+        core_offset: i32,
+        mem_offset: i32,
+    ) -> Result<(), GpuError> {
+        // attempt to set the GPU clock offset
+        self.backend().set_gpc_clk_vf_offset(index, core_offset)?;
+
+        // attempt to set the gpu mem offset
+        self.backend().set_mem_clk_vf_offset(index, mem_offset)
+    }
+
+    // take manual control of a single fan, used by the fan curves
+    pub fn set_fan_speed(&mut self, index: u32, fan: u32, speed: u32) -> Result<(), GpuError> {
+        self.check_write_access()?;
+        self.backend().set_fan_speed(index, fan, speed)
+    }
+
+    // hand a single fan back to the driver
+    pub fn set_default_fan_speed(&mut self, index: u32, fan: u32) -> Result<(), GpuError> {
+        self.check_write_access()?;
+        self.backend().set_default_fan_speed(index, fan)
+    }
+
+    pub fn throttle_reasons(&self, index: u32) -> Result<ThrottleReasons, GpuError> {
+        self.backend().throttle_reasons(index)
+    }
 
-        unsafe {
-            let raw_device_handle: nvmlDevice_t = nvml_device.handle();
-            let nvml_lib = NvmlLib::new("libnvidia-ml.so").expect("Failed to load NVML Library");
+    pub fn get_gpu_offset(&self, index: u32) -> Result<i32, GpuError> {
+        self.backend().gpc_clk_vf_offset(index)
+    }
 
-            get_value(|offset| nvml_lib.nvmlDeviceGetGpcClkVfOffset(raw_device_handle, offset))
+    pub fn get_mem_offset(&self, index: u32) -> Result<i32, GpuError> {
+        self.backend().mem_clk_vf_offset(index)
+    }
+}
+
+// parse a power limit typed in watts
+pub fn parse_power_limit(input: &str) -> Result<f64, String> {
+    input
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|limit_watts| limit_watts.is_finite())
+        .ok_or_else(|| format!("\"{}\" is not a number", input.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{FanControlPolicy, SimBackend};
+
+    fn sim_gpu(device_count: u32) -> Gpu {
+        Gpu::with_backend(Box::new(SimBackend::new(device_count))).unwrap()
+    }
+
+    #[test]
+    fn clock_indices_match_the_order_clocks_are_read_in() {
+        for (index, clock) in CLOCKS_ARRAY.into_iter().enumerate() {
+            assert_eq!(crate::telemetry::clock_index(clock), index);
         }
     }
 
-    pub fn get_mem_offset(&self) -> Result<i32, Option<NvmlError>> {
-        let nvml_device = self.nvml.device_by_index(0).expect("Failed to get device");
+    #[test]
+    fn enumerates_every_simulated_device() {
+        let mut gpu = sim_gpu(3);
+        gpu.update_gpu_info();
+
+        assert_eq!(gpu.devices.len(), 3);
+        for (index, device) in gpu.devices.iter().enumerate() {
+            assert_eq!(device.index, index as u32);
+            assert_eq!(device.sample.device_index, index as u32);
+            assert_eq!(
+                device.name.as_deref().unwrap(),
+                format!("Simulated GPU {index}")
+            );
+        }
+        assert!(gpu.device(3).is_none());
+    }
 
-        unsafe {
-            let raw_device_handle: nvmlDevice_t = nvml_device.handle();
-            let nvml_lib = NvmlLib::new("libnvidia-ml.so").expect("Failed to load NVML Library");
+    #[test]
+    fn apply_oc_writes_every_device() {
+        let mut gpu = sim_gpu(2);
+        gpu.apply_oc(&[0, 1], 150, 500).unwrap();
+        gpu.update_gpu_info();
 
-            get_value(|offset| nvml_lib.nvmlDeviceGetMemClkVfOffset(raw_device_handle, offset))
+        for device in &gpu.devices {
+            assert_eq!(device.core_offset, Ok(150));
+            assert_eq!(device.mem_offset, Ok(500));
         }
     }
+
+    #[test]
+    fn writes_do_not_wait_for_readings() {
+        let mut gpu = sim_gpu(1);
+
+        // a sampler stuck in a slow driver call
+        let reader = Arc::clone(&gpu.reader);
+        let _reading = lock(&reader);
+        gpu.apply_oc(&[0], 100, 0).unwrap();
+        assert_eq!(gpu.get_gpu_offset(0), Ok(100));
+    }
+
+    #[test]
+    fn apply_oc_reports_out_of_range_offsets_per_device() {
+        let mut gpu = sim_gpu(2);
+        let error = gpu.apply_oc(&[0, 1], 5000, 0).unwrap_err();
+
+        assert!(error.contains("GPU 0:"), "{error}");
+        assert!(error.contains("GPU 1:"), "{error}");
+        assert_eq!(gpu.get_gpu_offset(0), Ok(0));
+
+        // a device that does not exist fails on its own
+        let error = gpu.apply_oc(&[0, 7], 100, 0).unwrap_err();
+        assert!(!error.contains("GPU 0:"), "{error}");
+        assert!(error.contains("GPU 7:"), "{error}");
+        assert_eq!(gpu.get_gpu_offset(0), Ok(100));
+    }
+
+    #[test]
+    fn reset_defaults_reports_every_part() {
+        let mut gpu = sim_gpu(2);
+        gpu.apply_oc(&[0, 1], 200, 400).unwrap();
+        gpu.apply_power_limit(&[0], 150.0).unwrap();
+        gpu.apply_fan_policy(&[0], FanPolicy::Fixed { speed: 80 })
+            .unwrap();
+        let lock = ClockLock {
+            min_mhz: 7000,
+            max_mhz: 7000,
+        };
+        gpu.lock_clocks(&[0], LockedClock::Memory, lock).unwrap();
+
+        let report = gpu.reset_defaults(&[0, 1]).unwrap();
+        assert!(report.succeeded());
+
+        let parts: Vec<&str> = report
+            .steps
+            .iter()
+            .filter(|step| step.device == 0)
+            .map(|step| step.part.as_str())
+            .collect();
+        assert_eq!(
+            parts,
+            [
+                "core offset",
+                "memory offset",
+                "power limit",
+                "fan 0",
+                "fan 1",
+                "graphics clock lock",
+                "memory clock lock",
+            ]
+        );
+        assert_eq!(report.steps.len(), parts.len() * 2);
+
+        assert_eq!(gpu.get_gpu_offset(0), Ok(0));
+        assert_eq!(gpu.get_mem_offset(0), Ok(0));
+        assert_eq!(gpu.backend().enforced_power_limit(0), Ok(220_000));
+        assert_eq!(
+            gpu.backend().fan_control_policy(0, 0),
+            Ok(FanControlPolicy::Auto)
+        );
+        assert_eq!(gpu.devices[0].mem_clock_lock, None);
+    }
+
+    #[test]
+    fn reset_defaults_keeps_going_after_a_failure() {
+        let mut gpu = sim_gpu(1);
+        let report = gpu.reset_defaults(&[0, 4]).unwrap();
+
+        assert!(!report.succeeded());
+        assert!(report.failures().all(|step| step.device == 4));
+        assert!(
+            report
+                .steps
+                .iter()
+                .filter(|step| step.device == 0)
+                .all(|step| step.result.is_ok())
+        );
+    }
+
+    #[test]
+    fn offset_ranges_intersect_across_devices() {
+        let mut gpu = sim_gpu(3);
+        gpu.devices[1].gpc_offset_range = Ok(OffsetRange {
+            min: -200,
+            max: 1500,
+        });
+        gpu.devices[2].gpc_offset_range = Ok(OffsetRange {
+            min: -800,
+            max: 600,
+        });
+        gpu.devices[2].mem_offset_range = Err(GpuError::NotSupported);
+
+        let (gpc, mem) = gpu.offset_ranges(&[0, 1, 2]);
+        assert_eq!(
+            gpc,
+            Some(OffsetRange {
+                min: -200,
+                max: 600
+            })
+        );
+        // the device without a range is left out
+        assert_eq!(
+            mem,
+            Some(OffsetRange {
+                min: -1000,
+                max: 2000
+            })
+        );
+
+        let (gpc, _) = gpu.offset_ranges(&[1]);
+        assert_eq!(
+            gpc,
+            Some(OffsetRange {
+                min: -200,
+                max: 1500
+            })
+        );
+
+        gpu.devices[0].mem_offset_range = Err(GpuError::NotSupported);
+        gpu.devices[1].mem_offset_range = Err(GpuError::NotSupported);
+        assert_eq!(gpu.offset_ranges(&[0, 1, 2]).1, None);
+    }
 }
@@ -0,0 +1,846 @@
+// privileged helper. It runs as root and does the write operations for the
+// GUI and CLI, so they can run as a normal user and only this small piece
+// ever needs root.
+//
+// protocol
+// --------
+// clients connect to a unix socket, /run/nvidia-tweaker/helper.sock by
+// default, and send one JSON request per line. Each request gets exactly one
+// JSON response line back and a connection can carry any number of requests,
+// for up to a minute. A request line over 4096 bytes drops the connection.
+//
+// requests are picked by "op". "device" is the gpu index and fans are
+// numbered from 0:
+//   {"op":"set_core_offset","device":0,"offset_mhz":100}
+//   {"op":"set_mem_offset","device":0,"offset_mhz":500}
+//   {"op":"set_power_limit","device":0,"limit_mw":200000}
+//   {"op":"set_fan_speed","device":0,"fan":0,"speed_percent":60}
+//   {"op":"set_default_fan_speed","device":0,"fan":0}
+//   {"op":"set_gpu_locked_clocks","device":0,"min_mhz":1500,"max_mhz":1800}
+//   {"op":"set_mem_locked_clocks","device":0,"min_mhz":7000,"max_mhz":7000}
+//   {"op":"reset_gpu_locked_clocks","device":0}
+//   {"op":"reset_mem_locked_clocks","device":0}
+// there is no single reset request. Clients reset a device with the
+// requests above so every part reports how it went.
+//
+// responses:
+//   {"ok":true}
+//   {"ok":false,"error":"not_allowed","message":"..."}
+//   {"ok":false,"error":"missing_function","detail":"nvmlDeviceSetFanSpeed_v2","message":"..."}
+// error is one of the driver errors not_supported, missing_function,
+// no_permission, library_not_found, driver_not_loaded, version_mismatch,
+// gpu_lost, reset_required, invalid_argument, insufficient_power, in_use,
+// blocked, helper and unknown, or one of the helper's own not_allowed and
+// bad_request. not_allowed means the value is outside the allow-list or the
+// range the device reports, or that the range could not be read. bad_request
+// means the line could not be parsed. detail is whatever the error names:
+// the missing function, why the library did not load or the description of
+// an unknown error. message is for people and always there on errors.
+//
+// every value is checked against the range the device reports and the
+// optional allow-list in /etc/nvidia-tweaker/helper.toml before it is applied.
+// Keys missing from the allow-list fall back to the device range:
+//   devices = [0]
+//   core_offset = { min = -200, max = 300 }
+//   mem_offset = { min = 0, max = 1000 }
+//   power_limit_watts = { min = 150.0, max = 250.0 }
+//   fan_speed_percent = { min = 30, max = 100 }
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{ClockLock, GpuBackend};
+use crate::error::GpuError;
+use crate::telemetry::mw_to_watts;
+
+pub const DEFAULT_SOCKET: &str = "/run/nvidia-tweaker/helper.sock";
+pub const DEFAULT_POLICY_PATH: &str = "/etc/nvidia-tweaker/helper.toml";
+
+// the socket is readable and writable by its owner and group only, pick the
+// group allowed to tune gpus with --group
+const SOCKET_MODE: u32 = 0o660;
+
+// drop clients that stop talking so they can not block everyone else
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// clients are served one at a time, so none gets to keep the helper for
+// longer than this however busy it keeps the connection
+const CONNECTION_DEADLINE: Duration = Duration::from_secs(60);
+
+// the longest request line read, every valid one is far shorter
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    SetCoreOffset {
+        device: u32,
+        offset_mhz: i32,
+    },
+    SetMemOffset {
+        device: u32,
+        offset_mhz: i32,
+    },
+    SetPowerLimit {
+        device: u32,
+        limit_mw: u32,
+    },
+    SetFanSpeed {
+        device: u32,
+        fan: u32,
+        speed_percent: u32,
+    },
+    SetDefaultFanSpeed {
+        device: u32,
+        fan: u32,
+    },
+    SetGpuLockedClocks {
+        device: u32,
+        min_mhz: u32,
+        max_mhz: u32,
+    },
+    SetMemLockedClocks {
+        device: u32,
+        min_mhz: u32,
+        max_mhz: u32,
+    },
+    ResetGpuLockedClocks {
+        device: u32,
+    },
+    ResetMemLockedClocks {
+        device: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NotSupported,
+    MissingFunction,
+    NoPermission,
+    LibraryNotFound,
+    DriverNotLoaded,
+    VersionMismatch,
+    GpuLost,
+    ResetRequired,
+    InvalidArgument,
+    InsufficientPower,
+    InUse,
+    Blocked,
+    Helper,
+    NotAllowed,
+    BadRequest,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Request {
+    fn device(&self) -> u32 {
+        match *self {
+            Request::SetCoreOffset { device, .. }
+            | Request::SetMemOffset { device, .. }
+            | Request::SetPowerLimit { device, .. }
+            | Request::SetFanSpeed { device, .. }
+            | Request::SetDefaultFanSpeed { device, .. }
+            | Request::SetGpuLockedClocks { device, .. }
+            | Request::SetMemLockedClocks { device, .. }
+            | Request::ResetGpuLockedClocks { device }
+            | Request::ResetMemLockedClocks { device } => device,
+        }
+    }
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self {
+            ok: true,
+            error: None,
+            detail: None,
+            message: None,
+        }
+    }
+
+    fn error(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(kind),
+            detail: None,
+            message: Some(message.into()),
+        }
+    }
+
+    fn from_result(result: Result<(), GpuError>) -> Self {
+        let error = match result {
+            Ok(()) => return Self::ok(),
+            Err(error) => error,
+        };
+
+        let message = error.to_string();
+        let (kind, detail) = match error {
+            GpuError::NotSupported => (ErrorKind::NotSupported, None),
+            GpuError::MissingFunction(name) => (ErrorKind::MissingFunction, Some(name)),
+            GpuError::NoPermission => (ErrorKind::NoPermission, None),
+            GpuError::LibraryNotFound(reason) => (ErrorKind::LibraryNotFound, Some(reason)),
+            GpuError::DriverNotLoaded => (ErrorKind::DriverNotLoaded, None),
+            GpuError::VersionMismatch => (ErrorKind::VersionMismatch, None),
+            GpuError::GpuLost => (ErrorKind::GpuLost, None),
+            GpuError::ResetRequired => (ErrorKind::ResetRequired, None),
+            GpuError::InvalidArgument => (ErrorKind::InvalidArgument, None),
+            GpuError::InsufficientPower => (ErrorKind::InsufficientPower, None),
+            GpuError::InUse => (ErrorKind::InUse, None),
+            GpuError::Blocked => (ErrorKind::Blocked, None),
+            GpuError::Helper(description) => (ErrorKind::Helper, Some(description)),
+            GpuError::Unknown(description) => (ErrorKind::Unknown, Some(description)),
+        };
+
+        Self {
+            detail,
+            ..Self::error(kind, message)
+        }
+    }
+
+    // turn the response back into the error the backend would have given
+    pub fn into_result(self) -> Result<(), GpuError> {
+        if self.ok {
+            return Ok(());
+        }
+
+        // an older helper sends no detail, the message is the next best thing
+        let message = self.message.unwrap_or_default();
+        let detail = self.detail.unwrap_or_else(|| message.clone());
+        Err(match self.error.unwrap_or(ErrorKind::Unknown) {
+            ErrorKind::NotSupported => GpuError::NotSupported,
+            ErrorKind::MissingFunction => GpuError::MissingFunction(detail),
+            ErrorKind::NoPermission => GpuError::NoPermission,
+            ErrorKind::LibraryNotFound => GpuError::LibraryNotFound(detail),
+            ErrorKind::DriverNotLoaded => GpuError::DriverNotLoaded,
+            ErrorKind::VersionMismatch => GpuError::VersionMismatch,
+            ErrorKind::GpuLost => GpuError::GpuLost,
+            ErrorKind::ResetRequired => GpuError::ResetRequired,
+            ErrorKind::InvalidArgument => GpuError::InvalidArgument,
+            ErrorKind::InsufficientPower => GpuError::InsufficientPower,
+            ErrorKind::InUse => GpuError::InUse,
+            ErrorKind::Blocked => GpuError::Blocked,
+            ErrorKind::Helper => GpuError::Helper(detail),
+            ErrorKind::NotAllowed | ErrorKind::BadRequest => GpuError::Helper(message),
+            ErrorKind::Unknown => GpuError::Unknown(detail),
+        })
+    }
+}
+
+// inclusive range of allowed values
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Limits<T> {
+    pub min: T,
+    pub max: T,
+}
+
+impl<T: PartialOrd + Copy> Limits<T> {
+    fn contains(&self, value: T) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+// what the helper is willing to do on top of what the devices allow.
+// Anything left out is only limited by the device
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HelperPolicy {
+    pub devices: Option<Vec<u32>>,
+    pub core_offset: Option<Limits<i32>>,
+    pub mem_offset: Option<Limits<i32>>,
+    pub power_limit_watts: Option<Limits<f64>>,
+    pub fan_speed_percent: Option<Limits<u32>>,
+}
+
+impl HelperPolicy {
+    // read the allow-list from disk. A missing file allows every device range
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+}
+
+pub struct Helper {
+    backend: Box<dyn GpuBackend>,
+    policy: HelperPolicy,
+}
+
+impl Helper {
+    pub fn new(backend: Box<dyn GpuBackend>, policy: HelperPolicy) -> Self {
+        Self { backend, policy }
+    }
+
+    // check and carry out a single request
+    pub fn handle(&mut self, request: &Request) -> Response {
+        if let Err(message) = self.check(request) {
+            return Response::error(ErrorKind::NotAllowed, message);
+        }
+
+        Response::from_result(self.apply(request))
+    }
+
+    // make sure the request is allowed by both the policy and the device
+    fn check(&self, request: &Request) -> Result<(), String> {
+        let device = request.device();
+        let device_count = self.backend.device_count().map_err(|e| e.to_string())?;
+
+        if device >= device_count {
+            return Err(format!("there is no GPU with index {device}"));
+        }
+
+        if let Some(devices) = &self.policy.devices
+            && !devices.contains(&device)
+        {
+            return Err(format!("GPU {device} is not in the allow-list"));
+        }
+
+        match *request {
+            Request::SetCoreOffset { offset_mhz, .. } => check_offset(
+                "core offset",
+                offset_mhz,
+                self.policy.core_offset,
+                self.backend.gpc_clk_vf_offset_range(device),
+            ),
+            Request::SetMemOffset { offset_mhz, .. } => check_offset(
+                "memory offset",
+                offset_mhz,
+                self.policy.mem_offset,
+                self.backend.mem_clk_vf_offset_range(device),
+            ),
+            Request::SetPowerLimit { limit_mw, .. } => {
+                let limit_watts = mw_to_watts(limit_mw);
+
+                let constraints = self
+                    .backend
+                    .power_constraints(device)
+                    .map_err(|e| format!("the device power limit range could not be read: {e}"))?;
+
+                if !(constraints.min_mw..=constraints.max_mw).contains(&limit_mw) {
+                    return Err(format!(
+                        "{limit_watts} W is outside the device range of {}-{} W",
+                        mw_to_watts(constraints.min_mw),
+                        mw_to_watts(constraints.max_mw)
+                    ));
+                }
+
+                match self.policy.power_limit_watts {
+                    Some(limits) if !limits.contains(limit_watts) => Err(format!(
+                        "{limit_watts} W is outside the allowed range of {}-{} W",
+                        limits.min, limits.max
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            Request::SetFanSpeed {
+                fan, speed_percent, ..
+            } => {
+                self.check_fan(device, fan)?;
+
+                if speed_percent > 100 {
+                    return Err(format!("{speed_percent} % is not a valid fan speed"));
+                }
+
+                match self.policy.fan_speed_percent {
+                    Some(limits) if !limits.contains(speed_percent) => Err(format!(
+                        "{speed_percent} % is outside the allowed range of {}-{} %",
+                        limits.min, limits.max
+                    )),
+                    _ => Ok(()),
+                }
+            }
+            Request::SetDefaultFanSpeed { fan, .. } => self.check_fan(device, fan),
+            Request::SetGpuLockedClocks {
+                min_mhz, max_mhz, ..
+            } => {
+                let clocks = self
+                    .backend
+                    .supported_memory_clocks(device)
+                    .and_then(|mem_clocks| {
+                        let mem_clock = mem_clocks.iter().max().copied().unwrap_or(0);
+                        self.backend.supported_graphics_clocks(device, mem_clock)
+                    });
+                check_clock_lock("graphics", min_mhz, max_mhz, clocks)
+            }
+            Request::SetMemLockedClocks {
+                min_mhz, max_mhz, ..
+            } => check_clock_lock(
+                "memory",
+                min_mhz,
+                max_mhz,
+                self.backend.supported_memory_clocks(device),
+            ),
+            Request::ResetGpuLockedClocks { .. } | Request::ResetMemLockedClocks { .. } => Ok(()),
+        }
+    }
+
+    fn check_fan(&self, device: u32, fan: u32) -> Result<(), String> {
+        let num_fans = self
+            .backend
+            .num_fans(device)
+            .map_err(|e| format!("the fans of GPU {device} could not be read: {e}"))?;
+
+        if fan >= num_fans {
+            return Err(format!("GPU {device} has no fan with index {fan}"));
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self, request: &Request) -> Result<(), GpuError> {
+        match *request {
+            Request::SetCoreOffset { device, offset_mhz } => {
+                self.backend.set_gpc_clk_vf_offset(device, offset_mhz)
+            }
+            Request::SetMemOffset { device, offset_mhz } => {
+                self.backend.set_mem_clk_vf_offset(device, offset_mhz)
+            }
+            Request::SetPowerLimit { device, limit_mw } => {
+                self.backend.set_power_limit(device, limit_mw)
+            }
+            Request::SetFanSpeed {
+                device,
+                fan,
+                speed_percent,
+            } => self.backend.set_fan_speed(device, fan, speed_percent),
+            Request::SetDefaultFanSpeed { device, fan } => {
+                self.backend.set_default_fan_speed(device, fan)
+            }
+            Request::SetGpuLockedClocks {
+                device,
+                min_mhz,
+                max_mhz,
+            } => self
+                .backend
+                .set_gpu_locked_clocks(device, ClockLock { min_mhz, max_mhz }),
+            Request::SetMemLockedClocks {
+                device,
+                min_mhz,
+                max_mhz,
+            } => self
+                .backend
+                .set_mem_locked_clocks(device, ClockLock { min_mhz, max_mhz }),
+            Request::ResetGpuLockedClocks { device } => {
+                self.backend.reset_gpu_locked_clocks(device)
+            }
+            Request::ResetMemLockedClocks { device } => {
+                self.backend.reset_mem_locked_clocks(device)
+            }
+        }
+    }
+}
+
+fn check_offset(
+    name: &str,
+    offset: i32,
+    allowed: Option<Limits<i32>>,
+    device_range: Result<crate::backend::OffsetRange, GpuError>,
+) -> Result<(), String> {
+    // a range that can not be read allows nothing
+    let range =
+        device_range.map_err(|e| format!("the device {name} range could not be read: {e}"))?;
+
+    if !(range.min..=range.max).contains(&offset) {
+        return Err(format!(
+            "{offset} MHz is outside the device {name} range of {} to {} MHz",
+            range.min, range.max
+        ));
+    }
+
+    match allowed {
+        Some(limits) if !limits.contains(offset) => Err(format!(
+            "{offset} MHz is outside the allowed {name} range of {} to {} MHz",
+            limits.min, limits.max
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_clock_lock(
+    name: &str,
+    min_mhz: u32,
+    max_mhz: u32,
+    supported: Result<Vec<u32>, GpuError>,
+) -> Result<(), String> {
+    if min_mhz > max_mhz {
+        return Err(format!(
+            "the {name} clock lock minimum of {min_mhz} MHz is above the maximum of {max_mhz} MHz"
+        ));
+    }
+
+    let clocks =
+        supported.map_err(|e| format!("the supported {name} clocks could not be read: {e}"))?;
+    let (Some(lowest), Some(highest)) = (clocks.iter().min(), clocks.iter().max()) else {
+        return Err(format!("the device reports no supported {name} clocks"));
+    };
+
+    if min_mhz < *lowest || max_mhz > *highest {
+        return Err(format!(
+            "{min_mhz}-{max_mhz} MHz is outside the supported {name} clocks of {lowest}-{highest} MHz"
+        ));
+    }
+
+    Ok(())
+}
+
+// create the listening socket, replacing a stale one left by an earlier run.
+// the socket is handed to the given group when there is one
+pub fn bind(path: &Path, group: Option<u32>) -> io::Result<UnixListener> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(SOCKET_MODE))?;
+    if let Some(gid) = group {
+        std::os::unix::fs::chown(path, None, Some(gid))?;
+    }
+
+    Ok(listener)
+}
+
+// look up a group id in /etc/group
+pub fn group_id(name: &str) -> io::Result<u32> {
+    fs::read_to_string("/etc/group")?
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(':');
+            let group = fields.next()?;
+            let gid = fields.nth(1)?.parse::<u32>().ok()?;
+            (group == name).then_some(gid)
+        })
+        .next()
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no group named \"{name}\""),
+            )
+        })
+}
+
+// answer clients one at a time until the listener fails
+pub fn serve(listener: UnixListener, helper: &mut Helper) -> io::Result<()> {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = serve_client(stream, helper) {
+                    eprintln!("client dropped: {e}");
+                }
+            }
+            Err(e) => eprintln!("failed to accept a client: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn serve_client(stream: UnixStream, helper: &mut Helper) -> io::Result<()> {
+    let deadline = Instant::now() + CONNECTION_DEADLINE;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = Vec::new();
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connection open for too long",
+            ));
+        }
+        reader
+            .get_ref()
+            .set_read_timeout(Some(remaining.min(CLIENT_TIMEOUT)))?;
+
+        // one byte over the limit is enough to tell the line is too long
+        line.clear();
+        (&mut reader)
+            .take(MAX_REQUEST_BYTES + 1)
+            .read_until(b'\n', &mut line)?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        if line.len() as u64 > MAX_REQUEST_BYTES {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("request longer than {MAX_REQUEST_BYTES} bytes"),
+            ));
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut reply = serde_json::to_string(&respond(helper, &line)).map_err(io::Error::other)?;
+        reply.push('\n');
+        writer.write_all(reply.as_bytes())?;
+    }
+}
+
+// answer a single request line
+fn respond(helper: &mut Helper, line: &str) -> Response {
+    match serde_json::from_str::<Request>(line) {
+        Ok(request) => {
+            let response = helper.handle(&request);
+            match &response.message {
+                None => eprintln!("{request:?}: ok"),
+                Some(message) => eprintln!("{request:?}: {message}"),
+            }
+            response
+        }
+        Err(e) => Response::error(ErrorKind::BadRequest, e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{OffsetRange, SimBackend};
+
+    fn helper(policy: HelperPolicy) -> Helper {
+        Helper::new(Box::new(SimBackend::new(2)), policy)
+    }
+
+    fn error_kind(response: &Response) -> Option<ErrorKind> {
+        assert!(!response.ok, "{response:?}");
+        response.error
+    }
+
+    #[test]
+    fn applies_requests_within_range() {
+        let mut helper = helper(HelperPolicy::default());
+
+        let response = helper.handle(&Request::SetCoreOffset {
+            device: 1,
+            offset_mhz: 150,
+        });
+        assert_eq!(response, Response::ok());
+        assert_eq!(helper.backend.gpc_clk_vf_offset(1), Ok(150));
+    }
+
+    #[test]
+    fn rejects_values_outside_the_allow_list() {
+        let mut helper = helper(HelperPolicy {
+            devices: Some(vec![0]),
+            core_offset: Some(Limits {
+                min: -100,
+                max: 200,
+            }),
+            power_limit_watts: Some(Limits {
+                min: 150.0,
+                max: 250.0,
+            }),
+            fan_speed_percent: Some(Limits { min: 30, max: 100 }),
+            ..HelperPolicy::default()
+        });
+
+        let rejected = [
+            Request::SetCoreOffset {
+                device: 1,
+                offset_mhz: 0,
+            },
+            Request::SetCoreOffset {
+                device: 0,
+                offset_mhz: 300,
+            },
+            Request::SetPowerLimit {
+                device: 0,
+                limit_mw: 280_000,
+            },
+            Request::SetFanSpeed {
+                device: 0,
+                fan: 0,
+                speed_percent: 20,
+            },
+        ];
+        for request in rejected {
+            let response = helper.handle(&request);
+            assert_eq!(error_kind(&response), Some(ErrorKind::NotAllowed));
+        }
+
+        assert_eq!(helper.backend.gpc_clk_vf_offset(0), Ok(0));
+        assert_eq!(helper.backend.enforced_power_limit(0), Ok(220_000));
+    }
+
+    #[test]
+    fn rejects_values_outside_the_device_range() {
+        let mut helper = helper(HelperPolicy::default());
+
+        let rejected = [
+            Request::SetCoreOffset {
+                device: 2,
+                offset_mhz: 0,
+            },
+            Request::SetMemOffset {
+                device: 0,
+                offset_mhz: 5000,
+            },
+            Request::SetPowerLimit {
+                device: 0,
+                limit_mw: 50_000,
+            },
+            Request::SetFanSpeed {
+                device: 0,
+                fan: 2,
+                speed_percent: 50,
+            },
+            Request::SetFanSpeed {
+                device: 0,
+                fan: 0,
+                speed_percent: 101,
+            },
+            Request::SetGpuLockedClocks {
+                device: 0,
+                min_mhz: 100,
+                max_mhz: 1500,
+            },
+            Request::SetMemLockedClocks {
+                device: 0,
+                min_mhz: 7000,
+                max_mhz: 405,
+            },
+        ];
+        for request in rejected {
+            let response = helper.handle(&request);
+            assert_eq!(
+                error_kind(&response),
+                Some(ErrorKind::NotAllowed),
+                "{request:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn fails_closed_when_the_range_can_not_be_read() {
+        let unreadable = GpuError::NotSupported;
+
+        assert!(check_offset("core offset", 0, None, Err(unreadable.clone())).is_err());
+        assert!(check_clock_lock("memory", 405, 405, Err(unreadable)).is_err());
+        assert!(check_clock_lock("memory", 405, 405, Ok(Vec::new())).is_err());
+        assert!(check_offset("core offset", 0, None, Ok(OffsetRange { min: 0, max: 0 })).is_ok());
+    }
+
+    #[test]
+    fn answers_unknown_and_broken_requests_with_bad_request() {
+        let mut helper = helper(HelperPolicy::default());
+
+        for line in [
+            r#"{"op":"format_disk","device":0}"#,
+            r#"{"op":"set_core_offset","device":0}"#,
+            "not json",
+        ] {
+            let response = respond(&mut helper, line);
+            assert_eq!(error_kind(&response), Some(ErrorKind::BadRequest), "{line}");
+        }
+
+        let response = respond(
+            &mut helper,
+            r#"{"op":"set_mem_offset","device":0,"offset_mhz":500}"#,
+        );
+        assert_eq!(response, Response::ok());
+    }
+
+    #[test]
+    fn every_error_survives_the_protocol() {
+        let errors = [
+            GpuError::NotSupported,
+            GpuError::MissingFunction(String::from("nvmlDeviceSetFanSpeed_v2")),
+            GpuError::NoPermission,
+            GpuError::LibraryNotFound(String::from("libnvidia-ml.so.1: not found")),
+            GpuError::DriverNotLoaded,
+            GpuError::VersionMismatch,
+            GpuError::GpuLost,
+            GpuError::ResetRequired,
+            GpuError::InvalidArgument,
+            GpuError::InsufficientPower,
+            GpuError::InUse,
+            GpuError::Blocked,
+            GpuError::Helper(String::from("busy")),
+            GpuError::Unknown(String::from("something odd")),
+        ];
+
+        for error in errors {
+            let line = serde_json::to_string(&Response::from_result(Err(error.clone()))).unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+
+            assert_eq!(
+                response.message.as_deref(),
+                Some(error.to_string().as_str())
+            );
+            assert_eq!(response.into_result(), Err(error));
+        }
+    }
+
+    #[test]
+    fn reads_responses_without_a_detail() {
+        let response: Response =
+            serde_json::from_str(r#"{"ok":false,"error":"unknown","message":"it broke"}"#).unwrap();
+        assert_eq!(
+            response.into_result(),
+            Err(GpuError::Unknown(String::from("it broke")))
+        );
+
+        let response: Response =
+            serde_json::from_str(r#"{"ok":false,"error":"not_allowed","message":"too high"}"#)
+                .unwrap();
+        assert_eq!(
+            response.into_result(),
+            Err(GpuError::Helper(String::from("too high")))
+        );
+    }
+
+    #[test]
+    fn drops_clients_sending_overlong_requests() {
+        let mut helper = helper(HelperPolicy::default());
+        let (mut client, server) = UnixStream::pair().unwrap();
+
+        let mut request = br#"{"op":"reset_gpu_locked_clocks","device":0}"#.to_vec();
+        request.push(b'\n');
+        request.extend(std::iter::repeat_n(b' ', MAX_REQUEST_BYTES as usize + 1));
+        client.write_all(&request).unwrap();
+
+        let error = serve_client(server, &mut helper).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        // the request before the long one was still answered
+        let mut reply = String::new();
+        BufReader::new(client).read_line(&mut reply).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Response>(&reply).unwrap(),
+            Response::ok()
+        );
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+
+use crate::telemetry::{
+    CLOCK_COUNT, Metric, THROTTLE_REASONS, TelemetrySample, ThrottleReason, mw_to_watts,
+};
+
+// hard cap on the points kept per device, enough for an hour of samples
+// every 100 ms. Keeps memory bounded however fast the app polls
+const MAX_POINTS: usize = 36_000;
+
+// how far back the charts can look
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HistoryWindow {
+    #[default]
+    OneMinute,
+    TenMinutes,
+    OneHour,
+}
+
+impl HistoryWindow {
+    pub const ALL: [HistoryWindow; 3] = [
+        HistoryWindow::OneMinute,
+        HistoryWindow::TenMinutes,
+        HistoryWindow::OneHour,
+    ];
+
+    pub fn duration(self) -> Duration {
+        match self {
+            HistoryWindow::OneMinute => Duration::from_secs(60),
+            HistoryWindow::TenMinutes => Duration::from_secs(10 * 60),
+            HistoryWindow::OneHour => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+impl fmt::Display for HistoryWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryWindow::OneMinute => write!(f, "1 min"),
+            HistoryWindow::TenMinutes => write!(f, "10 min"),
+            HistoryWindow::OneHour => write!(f, "1 h"),
+        }
+    }
+}
+
+// the charted values of a single sample. Readings that failed are None so
+// they show up as gaps instead of drops to zero
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryPoint {
+    pub timestamp: SystemTime,
+    pub temperature_c: Option<f32>,
+    pub power_watts: Option<f32>,
+    pub clocks_mhz: [Option<f32>; CLOCK_COUNT],
+    pub gpu_utilization_percent: Option<f32>,
+    pub mem_utilization_percent: Option<f32>,
+    pub fan_speed_percent: Option<f32>,
+    pub throttle_reasons: Option<ThrottleReasons>,
+}
+
+impl HistoryPoint {
+    fn new(sample: &TelemetrySample) -> Self {
+        fn value(metric: &Metric<u32>) -> Option<f32> {
+            metric.as_ref().ok().map(|value| *value as f32)
+        }
+
+        Self {
+            timestamp: sample.timestamp,
+            temperature_c: value(&sample.temperature_c),
+            power_watts: sample
+                .power_mw
+                .as_ref()
+                .ok()
+                .map(|power_mw| mw_to_watts(*power_mw) as f32),
+            clocks_mhz: std::array::from_fn(|clock| value(&sample.clocks_mhz[clock])),
+            gpu_utilization_percent: value(&sample.gpu_utilization_percent),
+            mem_utilization_percent: value(&sample.mem_utilization_percent),
+            fan_speed_percent: value(&sample.fan_speed_percent()),
+            throttle_reasons: sample.throttle_reasons.as_ref().ok().copied(),
+        }
+    }
+}
+
+// the recent samples of one gpu, oldest first. Points older than the
+// longest window are dropped as new ones come in
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    points: VecDeque<HistoryPoint>,
+}
+
+impl History {
+    pub fn push(&mut self, sample: &TelemetrySample) {
+        let point = HistoryPoint::new(sample);
+        let longest = HistoryWindow::OneHour.duration();
+
+        while self.points.len() >= MAX_POINTS
+            || self
+                .points
+                .front()
+                .is_some_and(|oldest| age(point.timestamp, oldest.timestamp) > longest)
+        {
+            self.points.pop_front();
+        }
+
+        self.points.push_back(point);
+    }
+
+    pub fn latest(&self) -> Option<&HistoryPoint> {
+        self.points.back()
+    }
+
+    // the points inside the window, measured back from the newest one
+    pub fn window(&self, window: HistoryWindow) -> impl Iterator<Item = &HistoryPoint> {
+        let newest = self.latest().map(|point| point.timestamp);
+        let window = window.duration();
+
+        // points are in time order so everything before the first one inside
+        // the window can be skipped
+        let start = newest.map_or(0, |newest| {
+            self.points
+                .partition_point(|point| age(newest, point.timestamp) > window)
+        });
+
+        self.points.range(start..)
+    }
+
+    // the share of the window, in percent, each throttle reason was active
+    // for. Reasons that never showed up are left out
+    pub fn throttle_shares(&self, window: HistoryWindow) -> Vec<(&'static ThrottleReason, f32)> {
+        let readings: Vec<ThrottleReasons> = self
+            .window(window)
+            .filter_map(|point| point.throttle_reasons)
+            .collect();
+        if readings.is_empty() {
+            return Vec::new();
+        }
+
+        THROTTLE_REASONS
+            .iter()
+            .filter_map(|reason| {
+                let active = readings
+                    .iter()
+                    .filter(|reasons| reasons.contains(reason.flag))
+                    .count();
+                (active > 0).then(|| (reason, active as f32 * 100.0 / readings.len() as f32))
+            })
+            .collect()
+    }
+}
+
+// how long before newest a point was taken, zero if the clock went backwards
+pub fn age(newest: SystemTime, timestamp: SystemTime) -> Duration {
+    newest.duration_since(timestamp).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::GpuError;
+
+    fn sample_at(base: SystemTime, millis: u64) -> TelemetrySample {
+        let mut sample = TelemetrySample::empty(0);
+        sample.timestamp = base + Duration::from_millis(millis);
+        sample
+    }
+
+    fn seconds(history: &History, window: HistoryWindow, base: SystemTime) -> Vec<u64> {
+        history
+            .window(window)
+            .map(|point| age(point.timestamp, base).as_secs())
+            .collect()
+    }
+
+    #[test]
+    fn windows_count_back_from_the_newest_point() {
+        let base = SystemTime::now();
+        let mut history = History::default();
+        for secs in [0, 600, 3000, 3500, 3570, 3600] {
+            history.push(&sample_at(base, secs * 1000));
+        }
+
+        assert_eq!(
+            seconds(&history, HistoryWindow::OneMinute, base),
+            [3570, 3600]
+        );
+        assert_eq!(
+            seconds(&history, HistoryWindow::TenMinutes, base),
+            [3000, 3500, 3570, 3600]
+        );
+        assert_eq!(
+            seconds(&history, HistoryWindow::OneHour, base),
+            [0, 600, 3000, 3500, 3570, 3600]
+        );
+    }
+
+    #[test]
+    fn drops_points_older_than_an_hour() {
+        let base = SystemTime::now();
+        let mut history = History::default();
+        for secs in [0, 1800, 3601] {
+            history.push(&sample_at(base, secs * 1000));
+        }
+
+        assert_eq!(
+            seconds(&history, HistoryWindow::OneHour, base),
+            [1800, 3601]
+        );
+        assert_eq!(seconds(&history, HistoryWindow::OneMinute, base), [3601]);
+    }
+
+    #[test]
+    fn keeps_at_most_max_points() {
+        let base = SystemTime::now();
+        let mut history = History::default();
+        for millis in 0..MAX_POINTS as u64 + 10 {
+            history.push(&sample_at(base, millis));
+        }
+
+        assert_eq!(history.points.len(), MAX_POINTS);
+        assert_eq!(
+            history
+                .points
+                .front()
+                .map(|point| age(point.timestamp, base)),
+            Some(Duration::from_millis(10))
+        );
+        assert_eq!(history.window(HistoryWindow::OneMinute).count(), MAX_POINTS);
+    }
+
+    #[test]
+    fn throttle_shares_are_the_percent_of_readings_with_each_reason() {
+        let base = SystemTime::now();
+        let mut history = History::default();
+        let readings = [
+            Ok(ThrottleReasons::SW_POWER_CAP),
+            Ok(ThrottleReasons::SW_POWER_CAP | ThrottleReasons::HW_SLOWDOWN),
+            Ok(ThrottleReasons::GPU_IDLE),
+            Ok(ThrottleReasons::empty()),
+            // a failed reading is left out instead of counting as no reasons
+            Err(GpuError::NotSupported),
+        ];
+        for (secs, reasons) in readings.into_iter().enumerate() {
+            let mut sample = sample_at(base, secs as u64 * 1000);
+            sample.throttle_reasons = reasons;
+            history.push(&sample);
+        }
+
+        let shares: Vec<(&str, f32)> = history
+            .throttle_shares(HistoryWindow::OneMinute)
+            .into_iter()
+            .map(|(reason, share)| (reason.key, share))
+            .collect();
+        assert_eq!(
+            shares,
+            [
+                ("sw_power_cap", 50.0),
+                ("hw_slowdown", 25.0),
+                ("idle", 25.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn no_throttle_shares_without_readings() {
+        let mut history = History::default();
+        let mut sample = TelemetrySample::empty(0);
+        sample.throttle_reasons = Err(GpuError::NotSupported);
+        history.push(&sample);
+
+        assert!(history.throttle_shares(HistoryWindow::OneHour).is_empty());
+    }
+
+    #[test]
+    fn an_empty_history_has_no_points() {
+        let history = History::default();
+        assert!(history.latest().is_none());
+        assert_eq!(history.window(HistoryWindow::OneHour).count(), 0);
+    }
+}
@@ -1,12 +1,50 @@
-use iced::time::{self, Duration};
 use iced::widget::{
-    Column, button, column, container, progress_bar, row, text, text_input, toggler,
+    Column, Row, Space, button, checkbox, column, container, pick_list, progress_bar, row,
+    scrollable, slider, text, text_input, toggler,
 };
-use iced::{Border, Center, Element, Fill, Left, Right, Bottom, Subscription, Task, Theme};
-use native_dialog::{MessageDialog, MessageType};
+use iced::{Border, Bottom, Center, Element, Fill, Left, Right, Subscription, Task, Theme, window};
 
+mod autotune;
+mod backend;
+mod chart;
+mod cli;
+mod curve_editor;
+mod error;
+mod event_log;
+mod exporter;
+mod fan_curve;
+mod format;
 mod gpu;
-use gpu::Gpu;
+mod helper;
+mod history;
+mod profile;
+mod recorder;
+mod rollback;
+mod sampling;
+mod service;
+mod telemetry;
+#[cfg(test)]
+mod testing;
+use backend::{BackendKind, ClockLock, OffsetRange};
+use clap::Parser;
+use cli::Cli;
+use curve_editor::CurveEdit;
+use error::GpuError;
+use event_log::{EventLog, Severity};
+use fan_curve::{FanCurveEngine, FanCurveStore};
+use format::{
+    clock_lock_text, fan_text, mem_usage_text, metric_text, metric_text_with,
+    power_constraints_text, power_text, throttle_text,
+};
+use gpu::{DeviceReadings, Gpu, LockedClock, MetricGroup};
+use history::{History, HistoryWindow};
+use profile::{FanPolicy, Profile, ProfileStore, parse_fan_policy};
+use recorder::{RecordFormat, Recorder, Rotation};
+use rollback::Rollback;
+use sampling::{PollRate, PollSettings};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use telemetry::CLOCK_LABELS;
 
 const FONT_SIZE_SM: f32 = 15.0;
 const FONT_SIZE_MED: f32 = 20.0;
@@ -14,23 +52,121 @@ const FONT_SIZE_LG: f32 = 25.0;
 
 const DARK_THEME: Theme = Theme::Oxocarbon;
 
+// how often a pending rollback checks its deadline and redraws the countdown
+const ROLLBACK_TICK: Duration = Duration::from_millis(250);
+
+// the range picked for one of the clock locks
+#[derive(Debug, Clone, Copy, Default)]
+struct ClockLockInput {
+    min_mhz: Option<u32>,
+    max_mhz: Option<u32>,
+}
+
+impl ClockLockInput {
+    // the lock to apply, once both ends are picked and in order
+    fn lock(&self) -> Option<ClockLock> {
+        match (self.min_mhz, self.max_mhz) {
+            (Some(min_mhz), Some(max_mhz)) if min_mhz <= max_mhz => {
+                Some(ClockLock { min_mhz, max_mhz })
+            }
+            _ => None,
+        }
+    }
+}
+
+// entry in the device selector
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceChoice {
+    index: u32,
+    name: String,
+}
+
+impl std::fmt::Display for DeviceChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.index, self.name)
+    }
+}
+
+// entry in the picker choosing what drives a fan
+#[derive(Debug, Clone, PartialEq)]
+enum FanControl {
+    Auto,
+    Curve(String),
+}
+
+impl std::fmt::Display for FanControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FanControl::Auto => write!(f, "Auto"),
+            FanControl::Curve(name) => write!(f, "Curve: {name}"),
+        }
+    }
+}
+
 struct Tweaks {
     theme: Theme,
 
     power_watts_input: String,
     core_offset_input: String,
     mem_offset_input: String,
+    // fan speed in percent, empty for automatic control
+    fan_speed_input: String,
+
+    // the offset fields after validation, Apply is disabled while either is an error
+    core_offset: Result<i32, String>,
+    mem_offset: Result<i32, String>,
 
     core_offset_real: String,
     mem_offset_real: String,
 
-    power_watts: String,
-    gpu_temp: String,
+    toggler_value: bool,
+    // holds the error instead when no backend could be initialized
+    nvml: Result<Gpu, GpuError>,
+
+    // the gpu shown in the view
+    selected_device: u32,
+    device_choices: Vec<DeviceChoice>,
+    // which gpus the overclock gets applied to, indexed by device
+    apply_targets: Vec<bool>,
 
-    mem_usage: String,
+    // recent samples for the charts, indexed by device
+    history: Vec<History>,
+    history_window: HistoryWindow,
 
-    toggler_value: bool,
-    nvml: Gpu,
+    profiles: ProfileStore,
+    profiles_path: PathBuf,
+    selected_profile: Option<String>,
+    profile_name_input: String,
+
+    record_path_input: String,
+    record_format: RecordFormat,
+    // size in MiB to rotate the file at, empty to never rotate
+    record_max_size_input: String,
+    // the running recording, if any
+    recorder: Option<Recorder>,
+
+    fan_curves: FanCurveStore,
+    fan_curves_path: PathBuf,
+    // drives the fans with a curve assigned, stepped on every update
+    fan_engine: FanCurveEngine,
+    // the curve open in the editor
+    selected_fan_curve: Option<String>,
+    fan_curve_name_input: String,
+
+    // offsets to go back to unless the last apply gets confirmed
+    rollback: Option<Rollback>,
+
+    gpu_lock_input: ClockLockInput,
+    mem_lock_input: ClockLockInput,
+
+    poll_settings: PollSettings,
+    poll_settings_path: PathBuf,
+    // minimized, polling slows down then if adaptive polling is on
+    window_minimized: bool,
+
+    // what happened and what went wrong, instead of popping up dialogs
+    events: EventLog,
+    event_log_path_input: String,
 }
 
 #[derive(Debug, Clone)]
@@ -38,41 +174,155 @@ enum Message {
     PowerChanged(String),
     CoreChanged(String),
     MemChanged(String),
+    FanChanged(String),
     TogglerToggled(bool),
+    DeviceSelected(DeviceChoice),
+    ApplyTargetToggled(u32, bool),
+    ApplyAllToggled(bool),
     ApplyPressed,
-    UpdateGPUStats,
+    ResetPressed,
+    UpdateGPUStats(Vec<DeviceReadings>),
+    HistoryWindowSelected(HistoryWindow),
+    ProfileSelected(String),
+    ProfileNameChanged(String),
+    ProfileCreatePressed,
+    ProfileSavePressed,
+    ProfileRenamePressed,
+    ProfileDuplicatePressed,
+    ProfileDeletePressed,
+    ProfileApplyPressed,
+    RecordPathChanged(String),
+    RecordFormatSelected(RecordFormat),
+    RecordMaxSizeChanged(String),
+    RecordPressed,
+    FanCurveSelected(String),
+    FanCurveNameChanged(String),
+    FanCurveCreatePressed,
+    FanCurveDeletePressed,
+    FanCurveEdited(CurveEdit),
+    FanCurveHysteresisChanged(u32),
+    FanCurveRampChanged(u32),
+    FanCurveSave,
+    FanControlSelected(u32, FanControl),
+    RollbackConfirmPressed,
+    RollbackRevertPressed,
+    RollbackTick(Instant),
+    ClockLockMinSelected(LockedClock, u32),
+    ClockLockMaxSelected(LockedClock, u32),
+    ClockLockPressed(LockedClock),
+    ClockUnlockPressed(LockedClock),
+    PollRateSelected(MetricGroup, PollRate),
+    AdaptivePollingToggled(bool),
+    WindowChanged(window::Id),
+    WindowMinimized(Option<bool>),
+    NoticesDismissed,
+    EventLogPathChanged(String),
+    EventLogExportPressed,
+    EventLogClearPressed,
+    CloseRequested(window::Id),
 }
 
 impl Tweaks {
-    fn new() -> (Self, Task<Message>) {
+    fn new(backend: BackendKind) -> (Self, Task<Message>) {
+        let nvml = Gpu::new(backend);
+
+        // build the device selector once, the set of gpus does not change at runtime
+        let device_choices: Vec<DeviceChoice> = nvml
+            .iter()
+            .flat_map(|nvml| nvml.devices.iter())
+            .map(|device| DeviceChoice {
+                index: device.index,
+                name: metric_text(&device.name),
+            })
+            .collect();
+
+        // by default only the first gpu gets overclocked
+        let apply_targets = (0..device_choices.len()).map(|index| index == 0).collect();
+
+        let history = device_choices.iter().map(|_| History::default()).collect();
+
+        let mut events = EventLog::default();
+
+        // start with no profiles rather than refusing to start over a broken file
+        let profiles_path = profile::default_profiles_path();
+        let profiles = ProfileStore::load(&profiles_path).unwrap_or_else(|e| {
+            events.error(format!("Error loading the saved profiles. {e}"));
+            ProfileStore::default()
+        });
+
+        let fan_curves_path = fan_curve::default_fan_curves_path();
+        let fan_curves = FanCurveStore::load(&fan_curves_path).unwrap_or_else(|e| {
+            events.error(format!("Error loading the saved fan curves. {e}"));
+            FanCurveStore::default()
+        });
+        let selected_fan_curve = fan_curves.names().into_iter().next();
+
+        let poll_settings_path = sampling::default_poll_settings_path();
+        let poll_settings = PollSettings::load(&poll_settings_path).unwrap_or_else(|e| {
+            events.error(format!("Error loading the polling settings. {e}"));
+            PollSettings::default()
+        });
+
         (
             Self {
                 theme: DARK_THEME,
-                power_watts_input: 0.to_string(),
+                // left empty the power limit is not touched on apply
+                power_watts_input: String::new(),
                 core_offset_input: 0.to_string(),
                 mem_offset_input: 0.to_string(),
+                fan_speed_input: String::new(),
+                core_offset: Ok(0),
+                mem_offset: Ok(0),
                 core_offset_real: 0.to_string(),
                 mem_offset_real: 0.to_string(),
-                power_watts: 0.to_string(),
-                gpu_temp: 0.to_string(),
-                mem_usage: 0.to_string(),
                 toggler_value: true,
-                nvml: Gpu::new(),
+                nvml,
+                selected_device: 0,
+                device_choices,
+                apply_targets,
+                history,
+                history_window: HistoryWindow::default(),
+                profiles,
+                profiles_path,
+                selected_profile: None,
+                profile_name_input: String::new(),
+                record_path_input: String::from("telemetry.csv"),
+                record_format: RecordFormat::default(),
+                record_max_size_input: String::new(),
+                recorder: None,
+                fan_curve_name_input: selected_fan_curve.clone().unwrap_or_default(),
+                fan_curves,
+                fan_curves_path,
+                fan_engine: FanCurveEngine::default(),
+                selected_fan_curve,
+                rollback: None,
+                gpu_lock_input: ClockLockInput::default(),
+                mem_lock_input: ClockLockInput::default(),
+                poll_settings,
+                poll_settings_path,
+                window_minimized: false,
+                events,
+                event_log_path_input: String::from("events.log"),
             },
             Task::none(),
         )
     }
 
-    fn update(&mut self, message: Message) {
+    fn update(&mut self, message: Message) -> Task<Message> {
         match message {
             Message::PowerChanged(value) => {
                 self.power_watts_input = value;
             }
             Message::CoreChanged(value) => {
                 self.core_offset_input = value;
+                self.validate_offsets();
             }
             Message::MemChanged(value) => {
                 self.mem_offset_input = value;
+                self.validate_offsets();
+            }
+            Message::FanChanged(value) => {
+                self.fan_speed_input = value;
             }
             Message::TogglerToggled(value) => {
                 self.toggler_value = value;
@@ -82,73 +332,249 @@ impl Tweaks {
                     self.theme = Theme::Light;
                 }
             }
+            Message::DeviceSelected(choice) => {
+                self.selected_device = choice.index;
+            }
+            Message::ApplyTargetToggled(index, value) => {
+                if let Some(target) = self.apply_targets.get_mut(index as usize) {
+                    *target = value;
+                }
+                // the supported range depends on the targets
+                self.validate_offsets();
+            }
+            Message::ApplyAllToggled(value) => {
+                self.apply_targets
+                    .iter_mut()
+                    .for_each(|target| *target = value);
+                self.validate_offsets();
+            }
             Message::ApplyPressed => {
-                // run the overclock application function
-                let result = self.nvml.apply_oc(
-                    self.core_offset_input.clone(),
-                    self.mem_offset_input.clone(),
-                );
+                // the button is disabled while the input is invalid, but be safe
+                match self.settings_from_inputs(String::new()) {
+                    Ok(settings) => self.apply_settings(&settings),
+                    Err(e) => self.events.error(e),
+                }
+            }
+            Message::ResetPressed => self.reset_settings(),
+            Message::HistoryWindowSelected(window) => {
+                self.history_window = window;
+            }
+            Message::RecordPathChanged(value) => {
+                self.record_path_input = value;
+            }
+            Message::RecordFormatSelected(format) => {
+                // the picker can not be disabled, so ignore it while recording
+                if self.recorder.is_some() {
+                    return Task::none();
+                }
 
-                // check the result we get back to handle errors
-                match result {
-                    Ok(_m) => {}
-                    Err(error) => {
-                        let _ = MessageDialog::new()
-                            .set_type(MessageType::Error)
-                            .set_title("Error")
-                            .set_text(&format!("Error while setting the overclock. Make sure the app is running as sudo. Error Code: {error:?}"))
-                            .show_alert();
+                // keep the file name in step with the format when it has a known extension
+                let path = Path::new(&self.record_path_input);
+                if path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .and_then(RecordFormat::parse)
+                    .is_some()
+                {
+                    self.record_path_input = path
+                        .with_extension(format.extension())
+                        .to_string_lossy()
+                        .into_owned();
+                }
+                self.record_format = format;
+            }
+            Message::RecordMaxSizeChanged(value) => {
+                self.record_max_size_input = value;
+            }
+            Message::RecordPressed => match self.recorder.take() {
+                Some(recorder) => self.events.info(format!(
+                    "Stopped recording to {}, {} samples",
+                    recorder.path().display(),
+                    recorder.samples()
+                )),
+                None => self.start_recording(),
+            },
+
+            Message::ProfileSelected(_)
+            | Message::ProfileNameChanged(_)
+            | Message::ProfileCreatePressed
+            | Message::ProfileSavePressed
+            | Message::ProfileRenamePressed
+            | Message::ProfileDuplicatePressed
+            | Message::ProfileDeletePressed
+            | Message::ProfileApplyPressed => self.update_profiles(message),
+
+            Message::FanCurveSelected(_)
+            | Message::FanCurveNameChanged(_)
+            | Message::FanCurveCreatePressed
+            | Message::FanCurveDeletePressed
+            | Message::FanCurveEdited(_)
+            | Message::FanCurveHysteresisChanged(_)
+            | Message::FanCurveRampChanged(_)
+            | Message::FanCurveSave
+            | Message::FanControlSelected(_, _) => self.update_fan_curves(message),
+
+            Message::RollbackConfirmPressed => {
+                self.rollback = None;
+                self.events.info("Kept the new offsets");
+            }
+            Message::RollbackRevertPressed => self.revert_offsets(),
+            Message::ClockLockMinSelected(_, _)
+            | Message::ClockLockMaxSelected(_, _)
+            | Message::ClockLockPressed(_)
+            | Message::ClockUnlockPressed(_) => self.update_clock_locks(message),
+            Message::PollRateSelected(group, rate) => {
+                if let Some(current) = self.poll_settings.rates.rate_mut(group) {
+                    *current = rate;
+                    self.save_poll_settings();
+                }
+            }
+            Message::AdaptivePollingToggled(adaptive) => {
+                self.poll_settings.adaptive = adaptive;
+                self.save_poll_settings();
+            }
+            // minimizing shows up differently on every platform, so ask
+            // whenever the window might have been
+            Message::WindowChanged(id) => {
+                return window::get_minimized(id).map(Message::WindowMinimized);
+            }
+            Message::WindowMinimized(minimized) => {
+                self.window_minimized = minimized.unwrap_or(false);
+            }
+            Message::NoticesDismissed => self.events.dismiss_notices(),
+            Message::EventLogPathChanged(value) => {
+                self.event_log_path_input = value;
+            }
+            Message::EventLogExportPressed => {
+                let path = PathBuf::from(self.event_log_path_input.trim());
+                match self.events.export(&path) {
+                    Ok(()) => self
+                        .events
+                        .info(format!("Exported the event log to {}", path.display())),
+                    Err(e) => self
+                        .events
+                        .error(format!("Error exporting the event log. {e}")),
+                }
+            }
+            Message::EventLogClearPressed => self.events.clear(),
+            Message::CloseRequested(id) => {
+                // offsets nobody confirmed do not outlive the window
+                self.revert_offsets();
+
+                // never leave the fans stuck at a manual speed after exiting
+                if let Ok(nvml) = &mut self.nvml {
+                    let errors = self.fan_engine.release_all(nvml);
+                    if !errors.is_empty() {
+                        self.events.error(errors.join("\n"));
                     }
                 }
+                return window::close(id);
+            }
+
+            Message::RollbackTick(now) => {
+                // nobody confirmed the last apply in time
+                if self
+                    .rollback
+                    .as_ref()
+                    .is_some_and(|rollback| rollback.expired(now))
+                {
+                    self.revert_offsets();
+                }
             }
 
-            Message::UpdateGPUStats => {
-                // run the actual upate in the background
-                self.nvml.update_gpu_info();
-
-                self.power_watts = self.nvml.power_watts.clone();
-                self.power_watts.push_str(" W");
-                self.gpu_temp = self.nvml.gpu_temp.clone();
-
-                self.mem_usage = String::from("");
-                self.mem_usage.push_str(&self.nvml.gpu_mem_used);
-                self.mem_usage.push_str(" MiB/");
-                self.mem_usage.push_str(&self.nvml.gpu_mem_total);
-                self.mem_usage.push_str(" MiB");
-
-                let core_off = self.nvml.get_gpu_offset();
-                match core_off {
-                    Ok(core_off) => self.core_offset_real = core_off.to_string(),
-                    Err(e) => {
-                        let _ = MessageDialog::new()
-                            .set_type(MessageType::Error)
-                            .set_title("Error")
-                            .set_text(&format!(
-                                "Error reading the core clock offset. Error Code {e:?}"
-                            ))
-                            .show_alert();
+            Message::UpdateGPUStats(readings) => {
+                let Ok(nvml) = &mut self.nvml else {
+                    return Task::none();
+                };
+
+                // the readings were taken by the sampler, just keep them
+                nvml.store(readings);
+
+                // move the fans along their curves using the fresh readings
+                let fan_errors = self.fan_engine.tick(&self.fan_curves, nvml, Instant::now());
+                if !fan_errors.is_empty() {
+                    self.events.error(fan_errors.join("\n"));
+                }
+
+                for device in &nvml.devices {
+                    if let Some(history) = self.history.get_mut(device.index as usize) {
+                        history.push(&device.sample);
                     }
                 }
 
-                let mem_off = self.nvml.get_mem_offset();
-                match mem_off {
-                    Ok(mem_off) => self.mem_offset_real = mem_off.to_string(),
-                    Err(e) => {
-                        let _ = MessageDialog::new()
-                            .set_type(MessageType::Error)
-                            .set_title("Error")
-                            .set_text(&format!(
-                                "Error reading the mem clock offset. Error Code {e:?}"
-                            ))
-                            .show_alert();
+                // a failed write stops the recording rather than failing every update
+                let record_error = self.recorder.as_mut().and_then(|recorder| {
+                    recorder
+                        .record(nvml.devices.iter().map(|device| &device.sample))
+                        .err()
+                        .map(|e| format!("{}: {e}", recorder.path().display()))
+                });
+                if let Some(e) = record_error {
+                    self.recorder = None;
+                    self.events
+                        .error(format!("Recording stopped, could not write to {e}"));
+                }
+
+                // cards without VF offset support just show N/A
+                if let Some(device) = nvml.device(self.selected_device) {
+                    match &device.core_offset {
+                        Ok(core_off) => self.core_offset_real = core_off.to_string(),
+                        Err(e) if e.is_not_supported() => {
+                            self.core_offset_real = metric_text(&device.core_offset)
+                        }
+                        Err(e) => self
+                            .events
+                            .error(format!("Error reading the core clock offset. {e}")),
+                    }
+
+                    match &device.mem_offset {
+                        Ok(mem_off) => self.mem_offset_real = mem_off.to_string(),
+                        Err(e) if e.is_not_supported() => {
+                            self.mem_offset_real = metric_text(&device.mem_offset)
+                        }
+                        Err(e) => self
+                            .events
+                            .error(format!("Error reading the mem clock offset. {e}")),
                     }
                 }
             }
         }
+
+        Task::none()
     }
 
-    fn view(&self) -> Element<Message> {
-        let power_input = text_input("0", &self.power_watts_input)
+    fn view(&self) -> Element<'_, Message> {
+        let nvml = match &self.nvml {
+            Ok(nvml) => nvml,
+            Err(e) => {
+                return container(
+                    text(format!("Failed to initialize the GPU backend: {e}")).size(FONT_SIZE_LG),
+                )
+                .padding(10)
+                .into();
+            }
+        };
+
+        let Some(device) = nvml.device(self.selected_device) else {
+            return container(text("No GPUs found").size(FONT_SIZE_LG))
+                .padding(10)
+                .into();
+        };
+        let sample = &device.sample;
+
+        // show the supported range under the power input, or what is wrong with it
+        let power_hint = if self.power_watts_input.trim().is_empty() {
+            power_constraints_text(&device.power_constraints)
+        } else {
+            match gpu::parse_power_limit(&self.power_watts_input).and_then(|limit_watts| {
+                nvml.validate_power_limit(self.selected_device, limit_watts)
+            }) {
+                Ok(_) => power_constraints_text(&device.power_constraints),
+                Err(e) => e,
+            }
+        };
+
+        let power_input = text_input("Unchanged", &self.power_watts_input)
             .on_input(Message::PowerChanged)
             .padding(10)
             .size(FONT_SIZE_MED);
@@ -163,24 +589,77 @@ impl Tweaks {
             .padding(10)
             .size(FONT_SIZE_MED);
 
+        let fan_input = text_input("Auto", &self.fan_speed_input)
+            .on_input(Message::FanChanged)
+            .padding(10)
+            .size(FONT_SIZE_MED);
+
         let toggler = toggler(self.toggler_value)
             .label("Dark Mode")
             .on_toggle(Message::TogglerToggled)
             .spacing(FONT_SIZE_MED);
 
+        // only allow applying once every field is valid
+        let inputs_valid = self.settings_from_inputs(String::new()).is_ok();
+        let apply_message = inputs_valid.then_some(Message::ApplyPressed);
+
         let styled_button = |label| {
             button(text(label).width(50).center())
                 .padding(15)
-                .on_press(Message::ApplyPressed)
+                .on_press_maybe(apply_message.clone())
         };
 
         let apply_button = styled_button("Apply");
+        let reset_button = button(text("Reset").width(50).center())
+            .padding(15)
+            .style(button::danger)
+            .on_press(Message::ResetPressed);
 
         //------------------------ Info Section -------------------------------
+        let selected_choice = self
+            .device_choices
+            .iter()
+            .find(|choice| choice.index == self.selected_device)
+            .cloned();
+
+        // one row per fan, or a single N/A row when they could not be read
+        let fan_row = |label: String, value: String| {
+            row![
+                text(label).size(FONT_SIZE_MED).width(100),
+                container(text(value).size(FONT_SIZE_MED))
+                    .style(container::rounded_box)
+                    .padding(5)
+                    .align_x(Center)
+                    .width(Fill)
+            ]
+            .spacing(12)
+            .align_y(Center)
+        };
+        let fan_rows = match &sample.fans {
+            Ok(fans) if !fans.is_empty() => {
+                fans.iter().fold(Column::new().spacing(12), |rows, fan| {
+                    rows.push(fan_row(format!("Fan {}", fan.fan), fan_text(fan)))
+                })
+            }
+            _ => Column::new().push(fan_row(String::from("Fan"), String::from("N/A"))),
+        };
+
         let info_labels = column![
+            row![
+                text("GPU").size(FONT_SIZE_MED).width(100),
+                pick_list(
+                    self.device_choices.as_slice(),
+                    selected_choice,
+                    Message::DeviceSelected
+                )
+                .text_size(FONT_SIZE_MED)
+                .width(Fill)
+            ]
+            .spacing(12)
+            .align_y(Center),
             row![
                 text("Name").size(FONT_SIZE_MED).width(100),
-                container(text(self.nvml.get_gpu_name()).size(FONT_SIZE_MED))
+                container(text(metric_text(&device.name)).size(FONT_SIZE_MED))
                     .style(container::rounded_box)
                     .padding(5)
                     .align_x(Center)
@@ -190,7 +669,7 @@ impl Tweaks {
             .align_y(Center),
             row![
                 text("Driver").size(FONT_SIZE_MED).width(100),
-                container(text(self.nvml.get_driver_version()).size(FONT_SIZE_MED))
+                container(text(metric_text(&nvml.get_driver_version())).size(FONT_SIZE_MED))
                     .style(container::rounded_box)
                     .padding(5)
                     .align_x(Center)
@@ -198,9 +677,42 @@ impl Tweaks {
             ]
             .spacing(12)
             .align_y(Center),
+            row![
+                text("Power").size(FONT_SIZE_MED).width(100),
+                container(
+                    text(format!(
+                        "{} / {}",
+                        power_text(&sample.power_mw),
+                        power_text(&sample.power_limit_mw)
+                    ))
+                    .size(FONT_SIZE_MED)
+                )
+                .style(container::rounded_box)
+                .padding(5)
+                .align_x(Center)
+                .width(Fill)
+            ]
+            .spacing(12)
+            .align_y(Center),
+            row![
+                text("Temp").size(FONT_SIZE_MED).width(100),
+                container(
+                    text(metric_text_with(&sample.temperature_c, |temp| format!(
+                        "{temp} °C"
+                    )))
+                    .size(FONT_SIZE_MED)
+                )
+                .style(container::rounded_box)
+                .padding(5)
+                .align_x(Center)
+                .width(Fill)
+            ]
+            .spacing(12)
+            .align_y(Center),
+            fan_rows,
             row![
                 text("Memory").size(FONT_SIZE_MED).width(100),
-                container(text(&self.mem_usage).size(FONT_SIZE_MED))
+                container(text(mem_usage_text(sample)).size(FONT_SIZE_MED))
                     .style(container::rounded_box)
                     .padding(5)
                     .align_x(Center)
@@ -210,9 +722,13 @@ impl Tweaks {
             .align_y(Center),
             row![
                 text("GPU Use").size(FONT_SIZE_MED).width(100),
-                progress_bar(0.0..=100.0, self.nvml.gpu_utilization.clone() as f32).width(Fill),
+                progress_bar(
+                    0.0..=100.0,
+                    *sample.gpu_utilization_percent.as_ref().unwrap_or(&0) as f32
+                )
+                .width(Fill),
                 container(row![
-                    text(self.nvml.gpu_utilization.to_string()).size(FONT_SIZE_MED),
+                    text(metric_text(&sample.gpu_utilization_percent)).size(FONT_SIZE_MED),
                     text("%").size(FONT_SIZE_MED)
                 ])
                 .style(container::rounded_box)
@@ -223,9 +739,13 @@ impl Tweaks {
             .align_y(Center),
             row![
                 text("Mem Use").size(FONT_SIZE_MED).width(100),
-                progress_bar(0.0..=100.0, self.nvml.mem_utilization.clone() as f32).width(Fill),
+                progress_bar(
+                    0.0..=100.0,
+                    *sample.mem_utilization_percent.as_ref().unwrap_or(&0) as f32
+                )
+                .width(Fill),
                 container(row![
-                    text(self.nvml.mem_utilization.to_string()).size(FONT_SIZE_MED),
+                    text(metric_text(&sample.mem_utilization_percent)).size(FONT_SIZE_MED),
                     text("%").size(FONT_SIZE_MED)
                 ])
                 .style(container::rounded_box)
@@ -248,23 +768,20 @@ impl Tweaks {
         //---------------------------------------------------------------------
         //-------------------------- Clocks Section ---------------------------
 
-        // set up some labels for the gpu clocks section
-        let graphics_labels = ["Graphics", "SM", "Memory", "Video"];
-
         // make a column to hold all the clock speeds
         let mut clock_data = Column::new().spacing(12).align_x(Left).padding(10);
 
         // loop through the labels to retreive the clock speeds and create rows
-        for (index, label) in graphics_labels.iter().enumerate() {
+        for (index, label) in CLOCK_LABELS.iter().enumerate() {
             clock_data = clock_data.push(
                 row![
                     text(*label).size(FONT_SIZE_MED).width(100),
-                    container(text(&self.nvml.clock_speed_array[index]).size(FONT_SIZE_MED))
+                    container(text(metric_text(&sample.clocks_mhz[index])).size(FONT_SIZE_MED))
                         .style(container::rounded_box)
                         .padding(5)
                         .width(Fill)
                         .align_x(Center),
-                    container(text(&self.nvml.clock_speed_max_array[index]).size(FONT_SIZE_MED))
+                    container(text(metric_text(&sample.max_clocks_mhz[index])).size(FONT_SIZE_MED))
                         .style(container::rounded_box)
                         .padding(5)
                         .width(Fill)
@@ -275,6 +792,20 @@ impl Tweaks {
             );
         }
 
+        // why the current clocks are below the max ones
+        clock_data = clock_data.push(
+            row![
+                text("Throttle").size(FONT_SIZE_MED).width(100),
+                container(text(throttle_text(&sample.throttle_reasons)).size(FONT_SIZE_MED))
+                    .style(container::rounded_box)
+                    .padding(5)
+                    .width(Fill)
+                    .align_x(Center),
+            ]
+            .spacing(12)
+            .align_y(Center),
+        );
+
         let clocks_container = column![
             text("Clocks (Current/Max)")
                 .size(FONT_SIZE_LG)
@@ -286,6 +817,25 @@ impl Tweaks {
         // --------------------------------------------------------------------
         // ----------------------- Overclocking Section -----------------------
 
+        // checkboxes to pick which gpus the overclock gets applied to
+        let all_targets = self.apply_targets.iter().all(|target| *target);
+        let mut apply_targets_row = Row::new()
+            .push(text("Apply to").size(FONT_SIZE_SM))
+            .push(checkbox("All", all_targets).on_toggle(Message::ApplyAllToggled))
+            .spacing(12)
+            .align_y(Center)
+            .padding(10);
+
+        for (index, target) in self.apply_targets.iter().enumerate() {
+            let index = index as u32;
+            apply_targets_row = apply_targets_row.push(
+                checkbox(format!("GPU {index}"), *target)
+                    .on_toggle(move |value| Message::ApplyTargetToggled(index, value)),
+            );
+        }
+
+        let (core_range, mem_range) = nvml.offset_ranges(&self.apply_target_indices());
+
         let oc_data = column![
             row![
                 column![
@@ -304,11 +854,19 @@ impl Tweaks {
                 ],
                 button(text("Apply").width(50).center())
                     .padding(15)
-                    .on_press(Message::ApplyPressed)
+                    .on_press_maybe(apply_message.clone())
             ]
             .spacing(12)
             .align_y(Bottom)
             .padding(10),
+            // the supported range or what is wrong with the input, under each field
+            row![
+                offset_hint(&self.core_offset, core_range),
+                offset_hint(&self.mem_offset, mem_range),
+                Space::with_width(80)
+            ]
+            .spacing(12)
+            .padding([0, 10]),
             row![
                 column![
                     text("Core Offset (MHz)").size(FONT_SIZE_SM),
@@ -333,6 +891,8 @@ impl Tweaks {
             .spacing(12)
             .align_y(Center)
             .padding(10),
+            self.clock_locks_view(nvml),
+            apply_targets_row,
         ];
 
         let oc_container = column![
@@ -343,42 +903,1118 @@ impl Tweaks {
         .padding(10);
         //---------------------------------------------------------------------
 
-        let bottom_row = row![toggler, apply_button].spacing(10);
+        let bottom_row = row![toggler, reset_button, apply_button].spacing(10);
+
+        // invalid offsets are also flagged right under these fields
+        let offset_error = |offset: &Result<i32, String>| {
+            offset
+                .as_ref()
+                .err()
+                .map(|e| text(e.clone()).size(FONT_SIZE_SM).style(text::danger))
+        };
+
+        let settings_column = Column::new()
+            .push(text("Settings").size(FONT_SIZE_LG))
+            .push(row![text("Core Offset ").size(FONT_SIZE_MED), core_input])
+            .push_maybe(offset_error(&self.core_offset))
+            .push(row![text("Mem Offset ").size(FONT_SIZE_MED), mem_input])
+            .push_maybe(offset_error(&self.mem_offset))
+            .push(row![text("Power (W) ").size(FONT_SIZE_MED), power_input])
+            .push(text(power_hint).size(FONT_SIZE_SM))
+            .push(row![text("Fan (%) ").size(FONT_SIZE_MED), fan_input])
+            .push_maybe(
+                parse_fan_policy(&self.fan_speed_input)
+                    .err()
+                    .map(|e| text(e).size(FONT_SIZE_SM).style(text::danger)),
+            )
+            .push(bottom_row)
+            .push(self.profiles_view())
+            .push(self.recording_view())
+            .push(self.polling_view())
+            .spacing(10)
+            .align_x(Right)
+            .padding(10);
+
+        let left_column = column![info_container, clocks_container, oc_container];
+
+        let settings_column_container = container(settings_column).style(custom_container);
 
-        let settings_column = column![
-            text("Settings").size(FONT_SIZE_LG),
-            row![text("Core Offset ").size(FONT_SIZE_MED), core_input],
-            row![text("Mem Offset ").size(FONT_SIZE_MED), mem_input],
-            row![text("Power ").size(FONT_SIZE_MED), power_input],
-            bottom_row
+        let content = column![
+            self.notices_view(),
+            self.rollback_view(),
+            row![left_column, settings_column_container].spacing(15),
+            self.fan_curves_view(),
+            self.history_view(),
+            self.event_log_view()
+        ];
+
+        scrollable(content).into()
+    }
+
+    // scrolling charts of the recent readings of the selected gpu
+    fn history_view(&self) -> Element<'_, Message> {
+        let Some(history) = self.history.get(self.selected_device as usize) else {
+            return Space::with_height(0).into();
+        };
+
+        let charts = chart::CHARTS
+            .iter()
+            .fold(Column::new().spacing(12).padding(10), |charts, spec| {
+                charts.push(chart::view(spec, history, self.history_window))
+            });
+
+        // how much of the window each throttle reason was active for
+        let shares: Vec<String> = history
+            .throttle_shares(self.history_window)
+            .into_iter()
+            .map(|(reason, percent)| format!("{} {percent:.0} %", reason.label))
+            .collect();
+        let throttled = if shares.is_empty() {
+            String::from("Not throttled")
+        } else {
+            format!("Throttled: {}", shares.join(", "))
+        };
+        let charts = charts.push(text(throttled).size(FONT_SIZE_SM));
+
+        column![
+            row![
+                text("History").size(FONT_SIZE_LG),
+                pick_list(
+                    HistoryWindow::ALL,
+                    Some(self.history_window),
+                    Message::HistoryWindowSelected
+                )
+                .text_size(FONT_SIZE_MED)
+            ]
+            .spacing(12)
+            .align_y(Center),
+            container(charts).style(custom_container)
+        ]
+        .align_x(Left)
+        .padding(10)
+        .into()
+    }
+
+    // the profile picker and the buttons to manage profiles
+    fn profiles_view(&self) -> Element<'_, Message> {
+        let has_selection = self.selected_profile.is_some();
+        let profile_button = |label, message: Message, enabled: bool| {
+            button(text(label).size(FONT_SIZE_SM).center())
+                .padding(8)
+                .on_press_maybe(enabled.then_some(message))
+        };
+
+        column![
+            text("Profiles").size(FONT_SIZE_LG),
+            pick_list(
+                self.profiles.names(),
+                self.selected_profile.clone(),
+                Message::ProfileSelected
+            )
+            .placeholder("No profile selected")
+            .text_size(FONT_SIZE_MED)
+            .width(Fill),
+            text_input("Profile name", &self.profile_name_input)
+                .on_input(Message::ProfileNameChanged)
+                .padding(10)
+                .size(FONT_SIZE_MED),
+            row![
+                profile_button("New", Message::ProfileCreatePressed, true),
+                profile_button("Save", Message::ProfileSavePressed, has_selection),
+                profile_button("Rename", Message::ProfileRenamePressed, has_selection),
+                profile_button("Duplicate", Message::ProfileDuplicatePressed, has_selection),
+                profile_button("Delete", Message::ProfileDeletePressed, has_selection),
+            ]
+            .spacing(6),
+            profile_button("Apply Profile", Message::ProfileApplyPressed, has_selection),
         ]
         .spacing(10)
         .align_x(Right)
-        .padding(10);
+        .into()
+    }
 
-        let left_column = column![info_container, clocks_container, oc_container];
+    // controls to record every sample to a file
+    fn recording_view(&self) -> Element<'_, Message> {
+        let recording = self.recorder.is_some();
 
-        let settings_column_container = container(settings_column).style(custom_container);
+        let max_size_error = parse_max_size(&self.record_max_size_input).err();
+        let can_start = !self.record_path_input.trim().is_empty() && max_size_error.is_none();
+
+        let status = match &self.recorder {
+            Some(recorder) => format!(
+                "Recording to {}, {} samples",
+                recorder.path().display(),
+                recorder.samples()
+            ),
+            None => String::from("Not recording"),
+        };
+
+        // the settings can not change under a running recording
+        let editable = |message: fn(String) -> Message| (!recording).then_some(message);
+
+        Column::new()
+            .push(text("Recording").size(FONT_SIZE_LG))
+            .push(
+                text_input("File", &self.record_path_input)
+                    .on_input_maybe(editable(Message::RecordPathChanged))
+                    .padding(10)
+                    .size(FONT_SIZE_MED),
+            )
+            .push(
+                row![
+                    pick_list(
+                        RecordFormat::ALL,
+                        Some(self.record_format),
+                        Message::RecordFormatSelected
+                    )
+                    .text_size(FONT_SIZE_MED),
+                    text_input("Rotate at (MiB)", &self.record_max_size_input)
+                        .on_input_maybe(editable(Message::RecordMaxSizeChanged))
+                        .padding(10)
+                        .size(FONT_SIZE_MED),
+                ]
+                .spacing(10)
+                .align_y(Center),
+            )
+            .push_maybe(max_size_error.map(|e| text(e).size(FONT_SIZE_SM).style(text::danger)))
+            .push(
+                row![
+                    text(status).size(FONT_SIZE_SM),
+                    button(text(if recording { "Stop" } else { "Record" }).center())
+                        .padding(8)
+                        .on_press_maybe((recording || can_start).then_some(Message::RecordPressed)),
+                ]
+                .spacing(10)
+                .align_y(Center),
+            )
+            .spacing(10)
+            .align_x(Right)
+            .into()
+    }
+
+    // how often each group of readings is taken
+    fn polling_view(&self) -> Element<'_, Message> {
+        let rates = MetricGroup::ALL.into_iter().filter_map(|group| {
+            let rate = self.poll_settings.rates.rate(group)?;
+
+            Some(
+                row![
+                    text(group.to_string()).size(FONT_SIZE_MED),
+                    pick_list(PollRate::CHOICES, Some(rate), move |rate| {
+                        Message::PollRateSelected(group, rate)
+                    })
+                    .text_size(FONT_SIZE_MED),
+                ]
+                .spacing(10)
+                .align_y(Center)
+                .into(),
+            )
+        });
+
+        Column::new()
+            .push(text("Polling").size(FONT_SIZE_LG))
+            .extend(rates)
+            .push(
+                toggler(self.poll_settings.adaptive)
+                    .label("Slow down while minimized")
+                    .on_toggle(Message::AdaptivePollingToggled)
+                    .text_size(FONT_SIZE_SM),
+            )
+            .spacing(10)
+            .align_x(Right)
+            .into()
+    }
+
+    fn save_poll_settings(&mut self) {
+        if let Err(e) = self.poll_settings.save(&self.poll_settings_path) {
+            self.events
+                .error(format!("Error saving the polling settings. {e}"));
+        }
+    }
+
+    fn start_recording(&mut self) {
+        let path = PathBuf::from(self.record_path_input.trim());
+        let rotation = match parse_max_size(&self.record_max_size_input) {
+            Ok(max_mib) => {
+                max_mib.map(|max_mib| Rotation::from_mib(max_mib, recorder::DEFAULT_MAX_FILES))
+            }
+            Err(e) => return self.events.error(e),
+        };
+
+        match Recorder::start(&path, self.record_format, rotation) {
+            Ok(recorder) => {
+                self.events
+                    .info(format!("Started recording to {}", path.display()));
+                self.recorder = Some(recorder);
+            }
+            Err(e) => self.events.error(format!(
+                "Could not start recording to {}. {e}",
+                path.display()
+            )),
+        }
+    }
+
+    // handle everything from the profiles section
+    fn update_profiles(&mut self, message: Message) {
+        // name typed into the profile name field
+        let new_name = self.profile_name_input.trim().to_string();
+
+        let result = match message {
+            Message::ProfileSelected(name) => {
+                if let Some(profile) = self.profiles.get(&name).cloned() {
+                    self.load_inputs(&profile);
+                }
+                self.profile_name_input = name.clone();
+                self.selected_profile = Some(name);
+                return;
+            }
+            Message::ProfileNameChanged(value) => {
+                self.profile_name_input = value;
+                return;
+            }
+            Message::ProfileApplyPressed => {
+                let Some(profile) = self.selected_profile() else {
+                    return;
+                };
+                self.load_inputs(&profile);
+                self.apply_settings(&profile);
+                return;
+            }
+            Message::ProfileCreatePressed => self
+                .settings_from_inputs(new_name.clone())
+                .and_then(|profile| self.profiles.create(profile).map_err(|e| e.to_string()))
+                .map(|_| Some(new_name)),
+            Message::ProfileSavePressed => {
+                let Some(name) = self.selected_profile.clone() else {
+                    return;
+                };
+                self.settings_from_inputs(name.clone())
+                    .and_then(|profile| self.profiles.update(profile).map_err(|e| e.to_string()))
+                    .map(|_| Some(name))
+            }
+            Message::ProfileRenamePressed => {
+                let Some(name) = self.selected_profile.clone() else {
+                    return;
+                };
+                self.profiles
+                    .rename(&name, &new_name)
+                    .map(|_| Some(new_name))
+                    .map_err(|e| e.to_string())
+            }
+            Message::ProfileDuplicatePressed => {
+                let Some(name) = self.selected_profile.clone() else {
+                    return;
+                };
+                self.profiles
+                    .duplicate(&name, &new_name)
+                    .map(|_| Some(new_name))
+                    .map_err(|e| e.to_string())
+            }
+            Message::ProfileDeletePressed => {
+                let Some(name) = self.selected_profile.clone() else {
+                    return;
+                };
+                self.profiles
+                    .delete(&name)
+                    .map(|_| None)
+                    .map_err(|e| e.to_string())
+            }
+            _ => return,
+        };
+
+        // write every change straight to disk
+        match result.and_then(|selected| {
+            self.profiles
+                .save(&self.profiles_path)
+                .map(|_| selected)
+                .map_err(|e| e.to_string())
+        }) {
+            Ok(selected) => {
+                self.profile_name_input = selected.clone().unwrap_or_default();
+                self.selected_profile = selected;
+            }
+            Err(e) => self
+                .events
+                .error(format!("Error updating the profiles. {e}")),
+        }
+    }
+
+    // pickers to lock the graphics and memory clocks of the apply targets
+    // to a range
+    fn clock_locks_view(&self, nvml: &Gpu) -> Element<'_, Message> {
+        let targets = self.apply_target_indices();
+        let mut locks = Column::new().spacing(10).padding(10);
+
+        for clock in [LockedClock::Graphics, LockedClock::Memory] {
+            let (label, input) = match clock {
+                LockedClock::Graphics => ("Lock Graphics Clock (MHz)", self.gpu_lock_input),
+                LockedClock::Memory => ("Lock Memory Clock (MHz)", self.mem_lock_input),
+            };
+            let clocks = nvml.supported_clocks(&targets, clock);
+
+            // locks can not be read back from the driver, so this only
+            // knows about the ones set from here
+            let status = match nvml
+                .device(self.selected_device)
+                .and_then(|device| device.clock_lock(clock))
+            {
+                Some(lock) => format!("Locked to {}", clock_lock_text(lock)),
+                None => String::from("Not locked"),
+            };
+
+            let pickers = if clocks.is_empty() {
+                row![text("Not supported").size(FONT_SIZE_MED).width(Fill)]
+            } else {
+                row![
+                    pick_list(clocks.clone(), input.min_mhz, move |mhz| {
+                        Message::ClockLockMinSelected(clock, mhz)
+                    })
+                    .placeholder("Min")
+                    .text_size(FONT_SIZE_MED)
+                    .width(Fill),
+                    text("to").size(FONT_SIZE_MED),
+                    pick_list(clocks, input.max_mhz, move |mhz| {
+                        Message::ClockLockMaxSelected(clock, mhz)
+                    })
+                    .placeholder("Max")
+                    .text_size(FONT_SIZE_MED)
+                    .width(Fill),
+                    button(text("Lock").center())
+                        .padding(8)
+                        .on_press_maybe(input.lock().map(|_| Message::ClockLockPressed(clock))),
+                ]
+            };
+
+            locks = locks.push(
+                column![
+                    text(label).size(FONT_SIZE_SM),
+                    pickers
+                        .push(
+                            button(text("Unlock").center())
+                                .padding(8)
+                                .on_press(Message::ClockUnlockPressed(clock)),
+                        )
+                        .spacing(12)
+                        .align_y(Center),
+                    text(status).size(FONT_SIZE_SM),
+                ]
+                .spacing(5),
+            );
+        }
+
+        locks.into()
+    }
+
+    fn clock_lock_input(&mut self, clock: LockedClock) -> &mut ClockLockInput {
+        match clock {
+            LockedClock::Graphics => &mut self.gpu_lock_input,
+            LockedClock::Memory => &mut self.mem_lock_input,
+        }
+    }
+
+    // handle everything from the clock lock pickers
+    fn update_clock_locks(&mut self, message: Message) {
+        let targets = self.apply_target_indices();
+
+        let result = match message {
+            Message::ClockLockMinSelected(clock, mhz) => {
+                self.clock_lock_input(clock).min_mhz = Some(mhz);
+                Ok(())
+            }
+            Message::ClockLockMaxSelected(clock, mhz) => {
+                self.clock_lock_input(clock).max_mhz = Some(mhz);
+                Ok(())
+            }
+            Message::ClockLockPressed(clock) => {
+                let lock = self.clock_lock_input(clock).lock();
+                match (&mut self.nvml, lock) {
+                    (Ok(nvml), Some(lock)) => nvml.lock_clocks(&targets, clock, lock).map(|_| {
+                        self.events.info(format!(
+                            "Locked the {clock} clock to {}-{} MHz on {}",
+                            lock.min_mhz,
+                            lock.max_mhz,
+                            devices_text(&targets)
+                        ))
+                    }),
+                    _ => Ok(()),
+                }
+            }
+            Message::ClockUnlockPressed(clock) => match &mut self.nvml {
+                Ok(nvml) => nvml.unlock_clocks(&targets, clock).map(|_| {
+                    self.events.info(format!(
+                        "Unlocked the {clock} clock on {}",
+                        devices_text(&targets)
+                    ))
+                }),
+                Err(_) => Ok(()),
+            },
+            _ => Ok(()),
+        };
+
+        if let Err(e) = result {
+            self.events
+                .error(format!("Error changing the clock lock. {e}"));
+        }
+    }
+
+    // the curve editor and which curve drives each fan of the selected gpu
+    fn fan_curves_view(&self) -> Element<'_, Message> {
+        let Ok(nvml) = &self.nvml else {
+            return Space::with_height(0).into();
+        };
 
-        let content = row![left_column, settings_column_container].spacing(15);
+        let selected_curve = self
+            .selected_fan_curve
+            .as_ref()
+            .and_then(|name| self.fan_curves.curve(name));
+
+        let curve_button = |label, message: Message, enabled: bool| {
+            button(text(label).size(FONT_SIZE_SM).center())
+                .padding(8)
+                .on_press_maybe(enabled.then_some(message))
+        };
+
+        let mut curve_data = Column::new().spacing(12).padding(10).push(
+            row![
+                pick_list(
+                    self.fan_curves.names(),
+                    self.selected_fan_curve.clone(),
+                    Message::FanCurveSelected
+                )
+                .placeholder("No curve selected")
+                .text_size(FONT_SIZE_MED)
+                .width(Fill),
+                text_input("Curve name", &self.fan_curve_name_input)
+                    .on_input(Message::FanCurveNameChanged)
+                    .padding(10)
+                    .size(FONT_SIZE_MED),
+                curve_button("New", Message::FanCurveCreatePressed, true),
+                curve_button(
+                    "Delete",
+                    Message::FanCurveDeletePressed,
+                    selected_curve.is_some()
+                ),
+            ]
+            .spacing(10)
+            .align_y(Center),
+        );
+
+        if let Some(curve) = selected_curve {
+            let temp_c = nvml
+                .device(self.selected_device)
+                .and_then(|device| device.sample.temperature_c.as_ref().ok())
+                .map(|temp_c| *temp_c as f32);
+
+            curve_data = curve_data
+                .push(
+                    text("Drag a point to move it, click to add one, right click to remove one")
+                        .size(FONT_SIZE_SM),
+                )
+                .push(curve_editor::view(curve, temp_c, Message::FanCurveEdited))
+                .push(
+                    row![
+                        text(format!("Hysteresis {} °C", curve.hysteresis_c))
+                            .size(FONT_SIZE_SM)
+                            .width(160),
+                        slider(
+                            0..=10,
+                            curve.hysteresis_c,
+                            Message::FanCurveHysteresisChanged
+                        )
+                        .on_release(Message::FanCurveSave),
+                    ]
+                    .spacing(12)
+                    .align_y(Center),
+                )
+                .push(
+                    row![
+                        text(match curve.ramp_percent_per_sec {
+                            0 => String::from("Ramp unlimited"),
+                            ramp => format!("Ramp {ramp} %/s"),
+                        })
+                        .size(FONT_SIZE_SM)
+                        .width(160),
+                        slider(
+                            0..=50,
+                            curve.ramp_percent_per_sec,
+                            Message::FanCurveRampChanged
+                        )
+                        .on_release(Message::FanCurveSave),
+                    ]
+                    .spacing(12)
+                    .align_y(Center),
+                );
+        }
+
+        // pick what drives each fan of the selected gpu
+        let controls: Vec<FanControl> = std::iter::once(FanControl::Auto)
+            .chain(self.fan_curves.names().into_iter().map(FanControl::Curve))
+            .collect();
+
+        // counted from the latest readings so the view never waits on the driver
+        let num_fans = match nvml.device(self.selected_device) {
+            Some(device) => device
+                .sample
+                .fans
+                .as_ref()
+                .map(|fans| fans.len() as u32)
+                .map_err(Clone::clone),
+            None => Err(GpuError::NotSupported),
+        };
+        match num_fans {
+            Ok(num_fans) => {
+                for fan in 0..num_fans {
+                    let control = match self.fan_curves.assigned_curve(self.selected_device, fan) {
+                        Some(name) => FanControl::Curve(name.to_string()),
+                        None => FanControl::Auto,
+                    };
+                    let duty = self
+                        .fan_engine
+                        .duty(self.selected_device, fan)
+                        .map_or(String::from("Driver"), |duty| format!("{duty:.0} %"));
+
+                    curve_data = curve_data.push(
+                        row![
+                            text(format!("Fan {fan}")).size(FONT_SIZE_MED).width(100),
+                            pick_list(controls.clone(), Some(control), move |control| {
+                                Message::FanControlSelected(fan, control)
+                            })
+                            .text_size(FONT_SIZE_MED)
+                            .width(Fill),
+                            container(text(duty).size(FONT_SIZE_MED))
+                                .style(container::rounded_box)
+                                .padding(5)
+                                .width(100)
+                                .align_x(Center),
+                        ]
+                        .spacing(12)
+                        .align_y(Center),
+                    );
+                }
+            }
+            Err(_) => {
+                curve_data = curve_data.push(
+                    text(format!("Fan control: {}", metric_text(&num_fans))).size(FONT_SIZE_SM),
+                );
+            }
+        }
+
+        column![
+            text("Fan Curves").size(FONT_SIZE_LG),
+            container(curve_data).style(custom_container)
+        ]
+        .align_x(Left)
+        .padding(10)
+        .into()
+    }
+
+    // handle everything from the fan curves section
+    fn update_fan_curves(&mut self, message: Message) {
+        let selected = self.selected_fan_curve.clone();
+        let selected_curve = selected
+            .as_ref()
+            .and_then(|name| self.fan_curves.curve_mut(name));
+
+        // edits that happen while dragging are only saved once the drag ends
+        let save = match message {
+            Message::FanCurveSelected(name) => {
+                self.fan_curve_name_input = name.clone();
+                self.selected_fan_curve = Some(name);
+                false
+            }
+            Message::FanCurveNameChanged(value) => {
+                self.fan_curve_name_input = value;
+                false
+            }
+            Message::FanCurveCreatePressed => {
+                let name = self.fan_curve_name_input.trim().to_string();
+                if let Err(e) = self.fan_curves.create(&name) {
+                    return self
+                        .events
+                        .error(format!("Error updating the fan curves. {e}"));
+                }
+                self.selected_fan_curve = Some(name);
+                true
+            }
+            Message::FanCurveDeletePressed => {
+                let Some(name) = selected else {
+                    return;
+                };
+                self.fan_curves.delete(&name);
+                self.selected_fan_curve = None;
+                self.fan_curve_name_input.clear();
+                true
+            }
+            Message::FanCurveEdited(edit) => {
+                let Some(curve) = selected_curve else {
+                    return;
+                };
+                match edit {
+                    CurveEdit::Move(index, point) => {
+                        curve.move_point(index, point);
+                        false
+                    }
+                    CurveEdit::Insert(point) => {
+                        curve.insert(point);
+                        true
+                    }
+                    CurveEdit::Remove(index) => {
+                        curve.remove(index);
+                        true
+                    }
+                    CurveEdit::Finished => true,
+                }
+            }
+            Message::FanCurveHysteresisChanged(hysteresis_c) => {
+                if let Some(curve) = selected_curve {
+                    curve.hysteresis_c = hysteresis_c;
+                }
+                false
+            }
+            Message::FanCurveRampChanged(ramp) => {
+                if let Some(curve) = selected_curve {
+                    curve.ramp_percent_per_sec = ramp;
+                }
+                false
+            }
+            Message::FanCurveSave => true,
+            Message::FanControlSelected(fan, control) => {
+                let curve = match control {
+                    FanControl::Auto => None,
+                    FanControl::Curve(name) => Some(name),
+                };
+                self.fan_curves.assign(self.selected_device, fan, curve);
+                // give fans that failed before another go
+                self.fan_engine.clear_failures();
+                true
+            }
+            _ => return,
+        };
+
+        // write every change straight to disk
+        if save && let Err(e) = self.fan_curves.save(&self.fan_curves_path) {
+            self.events
+                .error(format!("Error saving the fan curves. {e}"));
+        }
+    }
+
+    fn selected_profile(&self) -> Option<Profile> {
+        self.selected_profile
+            .as_ref()
+            .and_then(|name| self.profiles.get(name))
+            .cloned()
+    }
+
+    // collect the values in the input fields into a profile with the given name
+    fn settings_from_inputs(&self, name: String) -> Result<Profile, String> {
+        let core_offset = self.core_offset.clone()?;
+        let mem_offset = self.mem_offset.clone()?;
+
+        // an empty power field leaves the limit alone
+        let power_limit_watts = if self.power_watts_input.trim().is_empty() {
+            None
+        } else {
+            Some(gpu::parse_power_limit(&self.power_watts_input)?)
+        };
 
-        content.into()
+        Ok(Profile {
+            name,
+            core_offset,
+            mem_offset,
+            power_limit_watts,
+            fan_policy: parse_fan_policy(&self.fan_speed_input)?,
+        })
+    }
+
+    // fill the input fields from a profile
+    fn load_inputs(&mut self, profile: &Profile) {
+        self.core_offset_input = profile.core_offset.to_string();
+        self.mem_offset_input = profile.mem_offset.to_string();
+        self.power_watts_input = profile
+            .power_limit_watts
+            .map(|limit_watts| limit_watts.to_string())
+            .unwrap_or_default();
+        self.fan_speed_input = match profile.fan_policy {
+            FanPolicy::Auto => String::new(),
+            FanPolicy::Fixed { speed } => speed.to_string(),
+        };
+        self.validate_offsets();
+    }
+
+    // apply a set of settings to the selected gpus
+    fn apply_settings(&mut self, settings: &Profile) {
+        // collect the gpus the user wants to overclock
+        let targets = self.apply_target_indices();
+
+        let Ok(nvml) = &mut self.nvml else {
+            return;
+        };
+
+        // remember the offsets from before so they can be put back if the
+        // new ones are not confirmed in time
+        let previous = Rollback::capture(nvml, &targets, rollback::DEFAULT_CONFIRM_TIMEOUT);
+
+        // only offsets that were actually written need confirming. A write
+        // that failed may still have reached some of the gpus, so those go
+        // back straight away like they do from the command line
+        let mut offsets = nvml.apply_oc(&targets, settings.core_offset, settings.mem_offset);
+        if let Some(previous) = previous {
+            match &mut offsets {
+                Ok(()) => match &mut self.rollback {
+                    Some(rollback) => rollback.extend(previous),
+                    None => self.rollback = Some(previous),
+                },
+                Err(e) => {
+                    if let Err(revert) = previous.revert(nvml) {
+                        e.push_str(&format!("\nError reverting the overclock. {revert}"));
+                    }
+                }
+            }
+        }
+
+        let result = match (offsets, nvml.apply_power_and_fans(&targets, settings)) {
+            (Ok(()), Ok(())) => Ok(()),
+            (Err(e), Ok(())) | (Ok(()), Err(e)) => Err(e),
+            (Err(offsets), Err(rest)) => Err(format!("{offsets}\n{rest}")),
+        };
+
+        // check the result we get back to handle errors
+        match result {
+            Ok(()) => self.events.info(format!(
+                "Applied core offset {} MHz, memory offset {} MHz, power limit {}, fans {} to {}",
+                settings.core_offset,
+                settings.mem_offset,
+                settings
+                    .power_limit_watts
+                    .map_or(String::from("unchanged"), |limit_watts| format!(
+                        "{limit_watts} W"
+                    )),
+                settings.fan_policy,
+                devices_text(&targets)
+            )),
+            Err(error) => self
+                .events
+                .error(format!("Error while setting the overclock.\n{error}")),
+        }
+    }
+
+    // put every tunable of the selected gpus back to its default
+    fn reset_settings(&mut self) {
+        let targets = self.apply_target_indices();
+
+        let Ok(nvml) = &mut self.nvml else {
+            return;
+        };
+
+        // the fans would be taken over again by their curves otherwise
+        for &index in &targets {
+            self.fan_curves.unassign_device(index);
+        }
+        if let Err(e) = self.fan_curves.save(&self.fan_curves_path) {
+            self.events
+                .error(format!("Error saving the fan curves. {e}"));
+        }
+
+        // nothing left to go back to
+        self.rollback = None;
+
+        match nvml.reset_defaults(&targets) {
+            Ok(report) if !report.succeeded() => {
+                let failures: Vec<String> =
+                    report.failures().map(|step| step.to_string()).collect();
+                self.events.error(format!(
+                    "Some settings could not be reset.\n{}",
+                    failures.join("\n")
+                ));
+            }
+            Ok(_) => self
+                .events
+                .info(format!("Reset {} to the defaults", devices_text(&targets))),
+            Err(e) => self
+                .events
+                .error(format!("Error resetting the settings. {e}")),
+        }
+
+        self.load_inputs(&Profile {
+            name: String::new(),
+            core_offset: 0,
+            mem_offset: 0,
+            power_limit_watts: None,
+            fan_policy: FanPolicy::Auto,
+        });
+    }
+
+    // put back the offsets from before the last unconfirmed apply
+    fn revert_offsets(&mut self) {
+        let (Some(rollback), Ok(nvml)) = (self.rollback.take(), &mut self.nvml) else {
+            return;
+        };
+
+        match rollback.revert(nvml) {
+            Ok(()) => self
+                .events
+                .info("Reverted to the offsets from before the last apply"),
+            Err(e) => self
+                .events
+                .error(format!("Error reverting the overclock. {e}")),
+        }
+    }
+
+    // asks to keep the new offsets while a rollback is pending
+    fn rollback_view(&self) -> Element<'_, Message> {
+        let Some(rollback) = &self.rollback else {
+            return Space::with_height(0).into();
+        };
+
+        let remaining = rollback.remaining(Instant::now()).as_secs_f32().ceil();
+
+        container(
+            row![
+                text(format!(
+                    "Keep the new offsets? Reverting in {remaining:.0} s"
+                ))
+                .size(FONT_SIZE_MED)
+                .width(Fill),
+                button(text("Keep").center())
+                    .padding(8)
+                    .on_press(Message::RollbackConfirmPressed),
+                button(text("Revert").center())
+                    .padding(8)
+                    .style(button::danger)
+                    .on_press(Message::RollbackRevertPressed),
+            ]
+            .spacing(12)
+            .align_y(Center),
+        )
+        .style(custom_container)
+        .padding(10)
+        .into()
+    }
+
+    // errors that came up since the last dismiss, out of the way of the controls
+    fn notices_view(&self) -> Element<'_, Message> {
+        let notices = self.events.notices();
+        if notices.is_empty() {
+            return Space::with_height(0).into();
+        }
+
+        let messages = notices
+            .iter()
+            .fold(Column::new().spacing(4), |messages, notice| {
+                messages.push(text(notice).size(FONT_SIZE_SM).style(text::danger))
+            });
+
+        container(
+            row![
+                messages.width(Fill),
+                button(text("Dismiss").center())
+                    .padding(8)
+                    .on_press(Message::NoticesDismissed),
+            ]
+            .spacing(12)
+            .align_y(Center),
+        )
+        .style(custom_container)
+        .padding(10)
+        .into()
+    }
+
+    // everything logged so far, newest first
+    fn event_log_view(&self) -> Element<'_, Message> {
+        let entries =
+            self.events
+                .entries()
+                .rev()
+                .fold(Column::new().spacing(4), |entries, entry| {
+                    let line = text(entry.to_string()).size(FONT_SIZE_SM);
+                    entries.push(match entry.severity {
+                        Severity::Info => line,
+                        Severity::Error => line.style(text::danger),
+                    })
+                });
+
+        let entries: Element<'_, Message> = if self.events.is_empty() {
+            text("Nothing logged yet").size(FONT_SIZE_SM).into()
+        } else {
+            scrollable(entries.width(Fill)).height(200).into()
+        };
+
+        column![
+            text("Event Log").size(FONT_SIZE_LG),
+            container(
+                column![
+                    entries,
+                    row![
+                        text_input("File", &self.event_log_path_input)
+                            .on_input(Message::EventLogPathChanged)
+                            .padding(10)
+                            .size(FONT_SIZE_MED),
+                        button(text("Export").center()).padding(8).on_press_maybe(
+                            (!self.event_log_path_input.trim().is_empty())
+                                .then_some(Message::EventLogExportPressed)
+                        ),
+                        button(text("Clear").center())
+                            .padding(8)
+                            .on_press(Message::EventLogClearPressed),
+                    ]
+                    .spacing(10)
+                    .align_y(Center),
+                ]
+                .spacing(10)
+                .padding(10)
+            )
+            .style(custom_container)
+        ]
+        .align_x(Left)
+        .padding(10)
+        .into()
+    }
+
+    // indices of the gpus the overclock gets applied to
+    fn apply_target_indices(&self) -> Vec<u32> {
+        self.apply_targets
+            .iter()
+            .enumerate()
+            .filter(|(_, target)| **target)
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+
+    // check both offset fields against the range every apply target supports
+    fn validate_offsets(&mut self) {
+        let (core_range, mem_range) = match &self.nvml {
+            Ok(nvml) => nvml.offset_ranges(&self.apply_target_indices()),
+            Err(_) => (None, None),
+        };
+
+        self.core_offset = validate_offset(&mut self.core_offset_input, core_range);
+        self.mem_offset = validate_offset(&mut self.mem_offset_input, mem_range);
     }
 
     fn theme(&self) -> Theme {
         self.theme.clone()
     }
 
+    // readings come from a worker so a slow driver never blocks the ui
     fn gpu_update_stats(&self) -> Subscription<Message> {
-        time::every(Duration::from_millis(300)).map(|_x| Message::UpdateGPUStats)
+        let rates = self.poll_settings.active_rates(self.window_minimized);
+
+        match &self.nvml {
+            Ok(nvml) => sampling::subscription(nvml.sampler(), rates).map(Message::UpdateGPUStats),
+            Err(_) => Subscription::none(),
+        }
+    }
+
+    // counts down a pending rollback on its own, so a slow or paused
+    // sampler can not keep unconfirmed offsets around
+    fn rollback_timer(&self) -> Subscription<Message> {
+        match &self.rollback {
+            Some(_) => iced::time::every(ROLLBACK_TICK).map(Message::RollbackTick),
+            None => Subscription::none(),
+        }
+    }
+
+    fn subscription(&self) -> Subscription<Message> {
+        // closing is handled in update so the fans can be released first
+        Subscription::batch([
+            self.gpu_update_stats(),
+            self.rollback_timer(),
+            window::close_requests().map(Message::CloseRequested),
+            iced::event::listen_with(window_changed),
+        ])
     }
 }
 
 fn main() -> iced::Result {
+    let cli = Cli::parse();
+    let backend = cli.backend.unwrap_or_else(BackendKind::from_env);
+
+    // run headless when a command was given, otherwise start the gui
+    if let Some(command) = cli.command {
+        std::process::exit(cli::run(command, backend));
+    }
+
     iced::application("Nvidia Tweaker", Tweaks::update, Tweaks::view)
-        .subscription(Tweaks::gpu_update_stats)
+        .subscription(Tweaks::subscription)
         .theme(Tweaks::theme)
-        .run_with(Tweaks::new)
+        .exit_on_close_request(false)
+        .run_with(move || Tweaks::new(backend))
+}
+
+// parse the rotation size field, empty means never rotate
+fn parse_max_size(input: &str) -> Result<Option<u64>, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    input
+        .parse::<u64>()
+        .ok()
+        .filter(|max_mib| *max_mib > 0)
+        .map(Some)
+        .ok_or_else(|| String::from("Rotation size must be a whole number of MiB"))
+}
+
+// the gpus a change went to, for the event log
+fn devices_text(devices: &[u32]) -> String {
+    let indices: Vec<String> = devices.iter().map(u32::to_string).collect();
+    format!("GPU {}", indices.join(", "))
+}
+
+// events after which the window may have been minimized or restored.
+// iced does not report a window that is covered or on another workspace,
+// so only minimizing slows the polling down
+fn window_changed(
+    event: iced::Event,
+    _status: iced::event::Status,
+    id: window::Id,
+) -> Option<Message> {
+    match event {
+        iced::Event::Window(
+            window::Event::Focused | window::Event::Unfocused | window::Event::Resized(_),
+        ) => Some(Message::WindowChanged(id)),
+        _ => None,
+    }
+}
+
+// parse an offset field, clamping it into the supported range. The field is
+// rewritten when the value had to be clamped so it shows what will be applied
+fn validate_offset(input: &mut String, range: Option<OffsetRange>) -> Result<i32, String> {
+    let offset = input
+        .trim()
+        .parse::<i32>()
+        .map_err(|_| String::from("Enter a whole number of MHz"))?;
+
+    let Some(range) = range else {
+        return Ok(offset);
+    };
+
+    let clamped = offset.clamp(range.min, range.max);
+    if clamped != offset {
+        *input = clamped.to_string();
+    }
+
+    Ok(clamped)
+}
+
+// text shown under an offset field
+fn offset_hint<'a>(
+    offset: &Result<i32, String>,
+    range: Option<OffsetRange>,
+) -> Element<'a, Message> {
+    match (offset, range) {
+        (Err(e), _) => text(e.clone())
+            .size(FONT_SIZE_SM)
+            .style(text::danger)
+            .width(Fill)
+            .into(),
+        (Ok(_), Some(range)) => text(format!("{} to {} MHz", range.min, range.max))
+            .size(FONT_SIZE_SM)
+            .width(Fill)
+            .into(),
+        (Ok(_), None) => Space::with_width(Fill).into(),
+    }
 }
 
 // implement a custom container theme
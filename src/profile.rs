@@ -0,0 +1,320 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+// directory under the XDG config dir holding everything the app saves
+const CONFIG_DIR_NAME: &str = "nvidia-tweaker";
+const PROFILES_FILE_NAME: &str = "profiles.toml";
+
+// what to do with the fans when a profile is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum FanPolicy {
+    // leave the fans to the driver
+    #[default]
+    Auto,
+    // run every fan at a fixed percentage
+    Fixed {
+        speed: u32,
+    },
+}
+
+impl fmt::Display for FanPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FanPolicy::Auto => write!(f, "auto"),
+            FanPolicy::Fixed { speed } => write!(f, "fixed {speed} %"),
+        }
+    }
+}
+
+// a named set of tuning values
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub name: String,
+    // offsets in MHz
+    pub core_offset: i32,
+    pub mem_offset: i32,
+    // power limit in watts, left alone when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub power_limit_watts: Option<f64>,
+    #[serde(default)]
+    pub fan_policy: FanPolicy,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    NotFound(String),
+    AlreadyExists(String),
+    EmptyName,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "could not access the profile file: {e}"),
+            ProfileError::Parse(e) => write!(f, "could not read the profile file: {e}"),
+            ProfileError::Serialize(e) => write!(f, "could not write the profile file: {e}"),
+            ProfileError::NotFound(name) => write!(f, "there is no profile named \"{name}\""),
+            ProfileError::AlreadyExists(name) => {
+                write!(f, "a profile named \"{name}\" already exists")
+            }
+            ProfileError::EmptyName => write!(f, "profile names can not be empty"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<io::Error> for ProfileError {
+    fn from(error: io::Error) -> Self {
+        ProfileError::Io(error)
+    }
+}
+
+// every saved profile, stored as a single TOML file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileStore {
+    #[serde(default, rename = "profile")]
+    pub profiles: Vec<Profile>,
+}
+
+impl ProfileStore {
+    // read the store from disk. A missing file is just an empty store
+    pub fn load(path: &Path) -> Result<Self, ProfileError> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(ProfileError::Parse),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // write the store to disk, creating the config directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), ProfileError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents = toml::to_string_pretty(self).map_err(ProfileError::Serialize)?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles
+            .iter()
+            .map(|profile| profile.name.clone())
+            .collect()
+    }
+
+    // add a new profile, refusing to overwrite an existing one
+    pub fn create(&mut self, profile: Profile) -> Result<(), ProfileError> {
+        self.check_new_name(&profile.name)?;
+        self.profiles.push(profile);
+        Ok(())
+    }
+
+    // replace the values of an existing profile
+    pub fn update(&mut self, profile: Profile) -> Result<(), ProfileError> {
+        let existing = self.get_mut(&profile.name)?;
+        *existing = profile;
+        Ok(())
+    }
+
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), ProfileError> {
+        let new_name = new_name.trim();
+        if name != new_name {
+            self.check_new_name(new_name)?;
+        }
+
+        self.get_mut(name)?.name = new_name.to_string();
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<Profile, ProfileError> {
+        let position = self
+            .profiles
+            .iter()
+            .position(|profile| profile.name == name)
+            .ok_or_else(|| ProfileError::NotFound(name.to_string()))?;
+
+        Ok(self.profiles.remove(position))
+    }
+
+    // copy an existing profile under a new name
+    pub fn duplicate(&mut self, name: &str, new_name: &str) -> Result<(), ProfileError> {
+        let mut copy = self
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ProfileError::NotFound(name.to_string()))?;
+        copy.name = new_name.trim().to_string();
+
+        self.create(copy)
+    }
+
+    fn get_mut(&mut self, name: &str) -> Result<&mut Profile, ProfileError> {
+        self.profiles
+            .iter_mut()
+            .find(|profile| profile.name == name)
+            .ok_or_else(|| ProfileError::NotFound(name.to_string()))
+    }
+
+    fn check_new_name(&self, name: &str) -> Result<(), ProfileError> {
+        if name.trim().is_empty() {
+            return Err(ProfileError::EmptyName);
+        }
+
+        if self.get(name).is_some() {
+            return Err(ProfileError::AlreadyExists(name.to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+// the app's directory under $XDG_CONFIG_HOME, falling back to ~/.config
+pub fn config_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    base.join(CONFIG_DIR_NAME)
+}
+
+pub fn default_profiles_path() -> PathBuf {
+    config_dir().join(PROFILES_FILE_NAME)
+}
+
+// parse a fan setting typed by the user, empty or "auto" means automatic control
+pub fn parse_fan_policy(input: &str) -> Result<FanPolicy, String> {
+    let input = input.trim();
+    if input.is_empty() || input.eq_ignore_ascii_case("auto") {
+        return Ok(FanPolicy::Auto);
+    }
+
+    input
+        .parse::<u32>()
+        .ok()
+        .filter(|speed| *speed <= 100)
+        .map(|speed| FanPolicy::Fixed { speed })
+        .ok_or_else(|| String::from("Fan speed must be a percentage from 0 to 100"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn profile(name: &str, core_offset: i32) -> Profile {
+        Profile {
+            name: name.to_string(),
+            core_offset,
+            mem_offset: 500,
+            power_limit_watts: Some(250.0),
+            fan_policy: FanPolicy::Fixed { speed: 60 },
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_every_profile() {
+        let dir = TempDir::new();
+        let path = dir.join("config/profiles.toml");
+
+        let mut store = ProfileStore::default();
+        store.create(profile("gaming", 150)).unwrap();
+        store
+            .create(Profile {
+                power_limit_watts: None,
+                fan_policy: FanPolicy::Auto,
+                ..profile("quiet", -100)
+            })
+            .unwrap();
+        store.save(&path).unwrap();
+
+        let loaded = ProfileStore::load(&path).unwrap();
+        assert_eq!(loaded.profiles, store.profiles);
+    }
+
+    #[test]
+    fn missing_file_is_an_empty_store() {
+        let dir = TempDir::new();
+        let store = ProfileStore::load(&dir.join("profiles.toml")).unwrap();
+        assert!(store.profiles.is_empty());
+    }
+
+    #[test]
+    fn broken_file_is_a_parse_error() {
+        let dir = TempDir::new();
+        let path = dir.join("profiles.toml");
+        fs::write(&path, "[[profile]]\nname = 3\n").unwrap();
+
+        assert!(matches!(
+            ProfileStore::load(&path),
+            Err(ProfileError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn names_must_be_unique_and_not_empty() {
+        let mut store = ProfileStore::default();
+        store.create(profile("gaming", 150)).unwrap();
+
+        assert!(matches!(
+            store.create(profile("gaming", 0)),
+            Err(ProfileError::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            store.create(profile("  ", 0)),
+            Err(ProfileError::EmptyName)
+        ));
+        assert!(matches!(
+            store.duplicate("gaming", "gaming"),
+            Err(ProfileError::AlreadyExists(_))
+        ));
+    }
+
+    #[test]
+    fn edits_existing_profiles() {
+        let mut store = ProfileStore::default();
+        store.create(profile("gaming", 150)).unwrap();
+
+        store.update(profile("gaming", 200)).unwrap();
+        assert_eq!(store.get("gaming").unwrap().core_offset, 200);
+
+        store.duplicate("gaming", " copy ").unwrap();
+        store.rename("gaming", " fast ").unwrap();
+        assert_eq!(store.names(), ["fast", "copy"]);
+
+        let deleted = store.delete("copy").unwrap();
+        assert_eq!(deleted.core_offset, 200);
+        assert!(matches!(
+            store.delete("copy"),
+            Err(ProfileError::NotFound(_))
+        ));
+        assert!(matches!(
+            store.update(profile("missing", 0)),
+            Err(ProfileError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn parses_fan_policies() {
+        assert_eq!(parse_fan_policy(""), Ok(FanPolicy::Auto));
+        assert_eq!(parse_fan_policy(" AUTO "), Ok(FanPolicy::Auto));
+        assert_eq!(parse_fan_policy("75"), Ok(FanPolicy::Fixed { speed: 75 }));
+        assert!(parse_fan_policy("101").is_err());
+        assert!(parse_fan_policy("fast").is_err());
+    }
+}
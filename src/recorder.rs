@@ -0,0 +1,390 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::telemetry::{
+    BYTES_PER_MIB, CLOCK_LABELS, FanReading, Metric, TelemetrySample, unix_millis,
+};
+
+// rotated files kept when no count is given
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordFormat {
+    #[default]
+    Csv,
+    Jsonl,
+}
+
+impl RecordFormat {
+    pub const ALL: [RecordFormat; 2] = [RecordFormat::Csv, RecordFormat::Jsonl];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "json" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+
+    // guess the format from a file name, CSV unless it looks like JSON
+    pub fn from_path(path: &Path) -> Self {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Self::parse)
+            .unwrap_or_default()
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecordFormat::Csv => "csv",
+            RecordFormat::Jsonl => "jsonl",
+        }
+    }
+}
+
+impl fmt::Display for RecordFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordFormat::Csv => write!(f, "CSV"),
+            RecordFormat::Jsonl => write!(f, "JSON Lines"),
+        }
+    }
+}
+
+// start a new file once the current one would grow past max_bytes, keeping
+// up to max_files old ones next to it as name.1.csv, name.2.csv and so on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
+impl Rotation {
+    pub fn from_mib(max_mib: u64, max_files: usize) -> Self {
+        Self {
+            max_bytes: max_mib.saturating_mul(BYTES_PER_MIB),
+            max_files,
+        }
+    }
+}
+
+// writes telemetry samples to a file, one line per sample
+pub struct Recorder {
+    path: PathBuf,
+    format: RecordFormat,
+    rotation: Option<Rotation>,
+    file: BufWriter<File>,
+    // bytes and samples in the current file
+    written: u64,
+    lines: u64,
+    // samples across every file of this recording
+    samples: u64,
+}
+
+impl Recorder {
+    // start recording into path, replacing whatever was there
+    pub fn start(
+        path: &Path,
+        format: RecordFormat,
+        rotation: Option<Rotation>,
+    ) -> io::Result<Self> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut recorder = Self {
+            path: path.to_path_buf(),
+            format,
+            rotation,
+            file: BufWriter::new(File::create(path)?),
+            written: 0,
+            lines: 0,
+            samples: 0,
+        };
+        recorder.write_header()?;
+        recorder.file.flush()?;
+
+        Ok(recorder)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    // write a batch of samples and flush them, so the file is complete even
+    // if the app gets killed
+    pub fn record<'a>(
+        &mut self,
+        samples: impl IntoIterator<Item = &'a TelemetrySample>,
+    ) -> io::Result<()> {
+        for sample in samples {
+            let mut line = match self.format {
+                RecordFormat::Csv => csv_row(sample),
+                RecordFormat::Jsonl => sample.to_json().to_string(),
+            };
+            line.push('\n');
+
+            // never rotate an empty file, a single long line has to go somewhere
+            if let Some(rotation) = self.rotation
+                && self.lines > 0
+                && self.written + line.len() as u64 > rotation.max_bytes
+            {
+                self.rotate(rotation)?;
+            }
+
+            self.write(&line)?;
+            self.lines += 1;
+            self.samples += 1;
+        }
+
+        self.file.flush()
+    }
+
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.file.write_all(text.as_bytes())?;
+        self.written += text.len() as u64;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        match self.format {
+            RecordFormat::Csv => self.write(&(csv_header() + "\n")),
+            RecordFormat::Jsonl => Ok(()),
+        }
+    }
+
+    // move the current file out of the way and start a fresh one
+    fn rotate(&mut self, rotation: Rotation) -> io::Result<()> {
+        self.file.flush()?;
+
+        // shift the old files up by one, the oldest gets overwritten
+        if rotation.max_files > 0 {
+            for number in (1..rotation.max_files).rev() {
+                match fs::rename(
+                    rotated_path(&self.path, number),
+                    rotated_path(&self.path, number + 1),
+                ) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.written = 0;
+        self.lines = 0;
+        self.write_header()
+    }
+}
+
+// name of an older file, the number goes before the extension so the
+// files still open in whatever handles the format
+pub fn rotated_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}.{number}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{number}"),
+    };
+
+    path.with_file_name(name)
+}
+
+// column names, with the units in them like the JSON keys. The number of
+// fans differs between cards, so fan_speed_percent is the first fan like in
+// the JSON and the fan_speeds_ columns list every fan in order
+fn csv_header() -> String {
+    let mut columns: Vec<String> = [
+        "timestamp_ms",
+        "device_index",
+        "power_mw",
+        "power_limit_mw",
+        "temperature_c",
+        "mem_free_bytes",
+        "mem_used_bytes",
+        "mem_total_bytes",
+        "fan_speed_percent",
+        "fan_speeds_percent",
+        "fan_speeds_rpm",
+        "gpu_utilization_percent",
+        "mem_utilization_percent",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect();
+
+    columns.extend(
+        CLOCK_LABELS
+            .iter()
+            .map(|label| format!("{}_clock_mhz", label.to_lowercase())),
+    );
+    columns.extend(
+        CLOCK_LABELS
+            .iter()
+            .map(|label| format!("max_{}_clock_mhz", label.to_lowercase())),
+    );
+    columns.push(String::from("throttle_reasons"));
+
+    columns.join(",")
+}
+
+// a sample as a CSV row, readings that could not be taken are left empty
+fn csv_row(sample: &TelemetrySample) -> String {
+    fn cell<T: ToString>(metric: &Metric<T>) -> String {
+        metric.as_ref().map(ToString::to_string).unwrap_or_default()
+    }
+
+    let mut cells = vec![
+        unix_millis(sample.timestamp).to_string(),
+        sample.device_index.to_string(),
+        cell(&sample.power_mw),
+        cell(&sample.power_limit_mw),
+        cell(&sample.temperature_c),
+        cell(&sample.mem_free_bytes),
+        cell(&sample.mem_used_bytes),
+        cell(&sample.mem_total_bytes),
+        cell(&sample.fan_speed_percent()),
+        fan_cells(sample, |fan| cell(&fan.speed_percent)),
+        fan_cells(sample, |fan| cell(&fan.speed_rpm)),
+        cell(&sample.gpu_utilization_percent),
+        cell(&sample.mem_utilization_percent),
+    ];
+    cells.extend(sample.clocks_mhz.iter().map(cell));
+    cells.extend(sample.max_clocks_mhz.iter().map(cell));
+    // the keys never contain commas, so ; keeps them in a single cell
+    cells.push(
+        sample
+            .throttle_keys()
+            .map(|keys| keys.join(";"))
+            .unwrap_or_default(),
+    );
+
+    cells.join(",")
+}
+
+// one reading of every fan, separated by ; to stay in a single cell
+fn fan_cells(sample: &TelemetrySample, cell: impl Fn(&FanReading) -> String) -> String {
+    sample
+        .fans
+        .as_ref()
+        .map(|fans| fans.iter().map(cell).collect::<Vec<_>>().join(";"))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::FanControlPolicy;
+    use crate::error::GpuError;
+    use crate::testing::TempDir;
+
+    fn lines(path: &Path) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn rotates_into_numbered_files() {
+        let dir = TempDir::new();
+        let path = dir.join("log.jsonl");
+        let sample = TelemetrySample::empty(0);
+        let line_bytes = sample.to_json().to_string().len() as u64 + 1;
+
+        // two samples fit in a file, keeping two old files
+        let rotation = Rotation {
+            max_bytes: line_bytes * 2,
+            max_files: 2,
+        };
+        let mut recorder = Recorder::start(&path, RecordFormat::Jsonl, Some(rotation)).unwrap();
+        for _ in 0..7 {
+            recorder.record([&sample]).unwrap();
+        }
+
+        assert_eq!(recorder.samples(), 7);
+        assert_eq!(lines(&path).len(), 1);
+        assert_eq!(lines(&dir.join("log.1.jsonl")).len(), 2);
+        assert_eq!(lines(&dir.join("log.2.jsonl")).len(), 2);
+        // the oldest file was dropped
+        assert!(!dir.join("log.3.jsonl").exists());
+    }
+
+    #[test]
+    fn every_csv_file_starts_with_the_header() {
+        let dir = TempDir::new();
+        let path = dir.join("log.csv");
+        let rotation = Rotation {
+            max_bytes: 1,
+            max_files: 1,
+        };
+
+        let mut recorder = Recorder::start(&path, RecordFormat::Csv, Some(rotation)).unwrap();
+        recorder
+            .record([&TelemetrySample::empty(0), &TelemetrySample::empty(1)])
+            .unwrap();
+
+        // a line too long for the limit still goes into the fresh file
+        for path in [path, dir.join("log.1.csv")] {
+            let lines = lines(&path);
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0], csv_header());
+        }
+    }
+
+    #[test]
+    fn csv_rows_list_every_fan() {
+        let fan = |fan, speed_percent: u32, speed_rpm| FanReading {
+            fan,
+            speed_percent: Ok(speed_percent),
+            target_percent: Ok(speed_percent),
+            speed_rpm,
+            policy: Ok(FanControlPolicy::Auto),
+        };
+        let mut sample = TelemetrySample::empty(0);
+        sample.fans = Ok(vec![
+            fan(0, 40, Ok(1200)),
+            fan(1, 45, Err(GpuError::NotSupported)),
+        ]);
+
+        let header = csv_header();
+        let row = csv_row(&sample);
+        let cells: Vec<(&str, &str)> = header.split(',').zip(row.split(',')).collect();
+        assert_eq!(header.split(',').count(), row.split(',').count());
+        assert!(cells.contains(&("fan_speed_percent", "40")));
+        assert!(cells.contains(&("fan_speeds_percent", "40;45")));
+        assert!(cells.contains(&("fan_speeds_rpm", "1200;")));
+
+        sample.fans = Err(GpuError::NotSupported);
+        assert!(csv_row(&sample).contains(",,,"));
+    }
+
+    #[test]
+    fn rotation_sizes_are_in_mib() {
+        assert_eq!(Rotation::from_mib(3, 4).max_bytes, 3 * 1024 * 1024);
+        assert_eq!(Rotation::from_mib(u64::MAX, 4).max_bytes, u64::MAX);
+    }
+
+    #[test]
+    fn numbers_rotated_files_before_the_extension() {
+        assert_eq!(
+            rotated_path(Path::new("/tmp/log.csv"), 2),
+            Path::new("/tmp/log.2.csv")
+        );
+        assert_eq!(rotated_path(Path::new("log"), 1), Path::new("log.1"));
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::{Duration, Instant};
+
+use crate::gpu::Gpu;
+
+// how long the gui waits for the user to confirm new offsets
+pub const DEFAULT_CONFIRM_TIMEOUT: Duration = Duration::from_secs(15);
+
+// offsets of one gpu from before a change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SavedOffsets {
+    index: u32,
+    core_offset: i32,
+    mem_offset: i32,
+}
+
+// the offsets to go back to when a change is not confirmed in time, like
+// the prompt after changing the display mode
+#[derive(Debug, Clone)]
+pub struct Rollback {
+    saved: Vec<SavedOffsets>,
+    deadline: Instant,
+}
+
+impl Rollback {
+    // remember the current offsets of the given gpus. Gpus whose offsets
+    // can not be read are left out, None when that is all of them
+    pub fn capture(gpu: &Gpu, devices: &[u32], timeout: Duration) -> Option<Self> {
+        let saved: Vec<SavedOffsets> = devices
+            .iter()
+            .filter_map(|&index| {
+                let core_offset = gpu.get_gpu_offset(index).ok()?;
+                let mem_offset = gpu.get_mem_offset(index).ok()?;
+                Some(SavedOffsets {
+                    index,
+                    core_offset,
+                    mem_offset,
+                })
+            })
+            .collect();
+
+        (!saved.is_empty()).then(|| Self {
+            saved,
+            deadline: Instant::now() + timeout,
+        })
+    }
+
+    // another change went through before this one was confirmed. Gpus
+    // already saved keep their original offsets and the deadline moves to
+    // the newer change's
+    pub fn extend(&mut self, newer: Rollback) {
+        for saved in newer.saved {
+            if !self.saved.iter().any(|old| old.index == saved.index) {
+                self.saved.push(saved);
+            }
+        }
+
+        self.deadline = newer.deadline;
+    }
+
+    pub fn remaining(&self, now: Instant) -> Duration {
+        self.deadline.saturating_duration_since(now)
+    }
+
+    pub fn expired(&self, now: Instant) -> bool {
+        now >= self.deadline
+    }
+
+    // put the saved offsets back
+    pub fn revert(&self, gpu: &mut Gpu) -> Result<(), String> {
+        let mut errors = Vec::new();
+
+        for saved in &self.saved {
+            if let Err(e) = gpu.apply_oc(&[saved.index], saved.core_offset, saved.mem_offset) {
+                errors.push(e);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::SimBackend;
+
+    #[test]
+    fn reverts_to_the_offsets_from_before_the_first_change() {
+        let mut gpu = Gpu::with_backend(Box::new(SimBackend::new(2))).unwrap();
+        gpu.apply_oc(&[0, 1], 10, 20).unwrap();
+
+        let mut rollback = Rollback::capture(&gpu, &[0], Duration::from_secs(15)).unwrap();
+        gpu.apply_oc(&[0], 100, 200).unwrap();
+
+        // a second change before confirming keeps gpu 0 at its first offsets
+        let newer = Rollback::capture(&gpu, &[0, 1], Duration::ZERO).unwrap();
+        gpu.apply_oc(&[0, 1], 300, 400).unwrap();
+        rollback.extend(newer);
+        assert!(rollback.expired(Instant::now()));
+
+        rollback.revert(&mut gpu).unwrap();
+        assert_eq!(gpu.get_gpu_offset(0), Ok(10));
+        assert_eq!(gpu.get_mem_offset(0), Ok(20));
+        assert_eq!(gpu.get_gpu_offset(1), Ok(10));
+    }
+
+    #[test]
+    fn nothing_to_capture_without_readable_offsets() {
+        let gpu = Gpu::with_backend(Box::new(SimBackend::new(1))).unwrap();
+        assert!(Rollback::capture(&gpu, &[3], Duration::from_secs(15)).is_none());
+    }
+}
@@ -0,0 +1,296 @@
+use std::any::TypeId;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use iced::Subscription;
+use iced::futures::channel::mpsc;
+use serde::{Deserialize, Serialize};
+
+use crate::gpu::{DeviceReadings, MetricGroup, Sampler};
+use crate::profile;
+
+const POLL_SETTINGS_FILE_NAME: &str = "polling.toml";
+
+// readings that have not been picked up yet. A ui busy for longer than that
+// skips samples instead of falling further and further behind
+const BACKLOG: usize = 2;
+
+// nothing is polled faster than this while the window is minimized.
+// The fan curves and recordings still get fresh readings, just fewer
+const BACKGROUND_RATE: PollRate = PollRate(2000);
+
+// how often a group of readings is taken, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PollRate(pub u64);
+
+impl PollRate {
+    // the rates offered in the gui
+    pub const CHOICES: [PollRate; 7] = [
+        PollRate(100),
+        PollRate(200),
+        PollRate(300),
+        PollRate(500),
+        PollRate(1000),
+        PollRate(2000),
+        PollRate(5000),
+    ];
+
+    pub fn interval(self) -> Duration {
+        // a zero rate from a hand edited file would spin the worker
+        Duration::from_millis(self.0.max(Self::CHOICES[0].0))
+    }
+}
+
+impl fmt::Display for PollRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 >= 1000 && self.0.is_multiple_of(1000) {
+            write!(f, "{} s", self.0 / 1000)
+        } else {
+            write!(f, "{} ms", self.0)
+        }
+    }
+}
+
+// how often each group of readings is taken. Static readings are only
+// taken once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollRates {
+    pub power: PollRate,
+    pub clocks: PollRate,
+    pub thermal: PollRate,
+    pub memory: PollRate,
+}
+
+impl Default for PollRates {
+    fn default() -> Self {
+        Self {
+            power: PollRate(300),
+            clocks: PollRate(300),
+            thermal: PollRate(1000),
+            memory: PollRate(1000),
+        }
+    }
+}
+
+impl PollRates {
+    // the rate of a group, None for the ones read only once
+    pub fn rate(&self, group: MetricGroup) -> Option<PollRate> {
+        match group {
+            MetricGroup::Power => Some(self.power),
+            MetricGroup::Clocks => Some(self.clocks),
+            MetricGroup::Thermal => Some(self.thermal),
+            MetricGroup::Memory => Some(self.memory),
+            MetricGroup::Static => None,
+        }
+    }
+
+    pub fn rate_mut(&mut self, group: MetricGroup) -> Option<&mut PollRate> {
+        match group {
+            MetricGroup::Power => Some(&mut self.power),
+            MetricGroup::Clocks => Some(&mut self.clocks),
+            MetricGroup::Thermal => Some(&mut self.thermal),
+            MetricGroup::Memory => Some(&mut self.memory),
+            MetricGroup::Static => None,
+        }
+    }
+
+    // the rates to use while the window is minimized
+    pub fn background(self) -> Self {
+        Self {
+            power: self.power.max(BACKGROUND_RATE),
+            clocks: self.clocks.max(BACKGROUND_RATE),
+            thermal: self.thermal.max(BACKGROUND_RATE),
+            memory: self.memory.max(BACKGROUND_RATE),
+        }
+    }
+}
+
+// the polling settings of the gui, kept between runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PollSettings {
+    pub rates: PollRates,
+    // slow down while the window is minimized. A window that is only
+    // covered or on another workspace is not reported, so it keeps the
+    // full rates
+    pub adaptive: bool,
+}
+
+impl Default for PollSettings {
+    fn default() -> Self {
+        Self {
+            rates: PollRates::default(),
+            adaptive: true,
+        }
+    }
+}
+
+impl PollSettings {
+    // read the settings from disk. A missing file means the defaults
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                toml::from_str(&contents).map_err(|e| format!("{}: {e}", path.display()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {e}", path.display())),
+        }
+    }
+
+    // write the settings to disk, creating the config directory if needed
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let error = |e: &dyn std::fmt::Display| format!("{}: {e}", path.display());
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| error(&e))?;
+        }
+
+        let contents = toml::to_string_pretty(self).map_err(|e| error(&e))?;
+        fs::write(path, contents).map_err(|e| error(&e))
+    }
+
+    // the rates to poll at right now
+    pub fn active_rates(&self, window_minimized: bool) -> PollRates {
+        if self.adaptive && window_minimized {
+            self.rates.background()
+        } else {
+            self.rates
+        }
+    }
+}
+
+pub fn default_poll_settings_path() -> PathBuf {
+    profile::config_dir().join(POLL_SETTINGS_FILE_NAME)
+}
+
+// readings of every gpu, taken on a worker thread so a slow driver call
+// never holds up the ui. Each group is read again at its own rate and the
+// full set goes out whenever any of them was. The worker stops once the
+// subscription goes away
+pub fn subscription(sampler: Sampler, rates: PollRates) -> Subscription<Vec<DeviceReadings>> {
+    Subscription::run_with_id(
+        (TypeId::of::<Sampler>(), rates),
+        iced::stream::channel(BACKLOG, move |output| async move {
+            thread::spawn(move || poll(sampler, rates, output));
+        }),
+    )
+}
+
+fn poll(sampler: Sampler, rates: PollRates, mut output: mpsc::Sender<Vec<DeviceReadings>>) {
+    // everything is read up front, after that only what is due
+    let mut readings = sampler.sample();
+    let start = Instant::now();
+    let mut schedule: Vec<(MetricGroup, Duration, Instant)> = MetricGroup::ALL
+        .into_iter()
+        .filter_map(|group| {
+            let interval = rates.rate(group)?.interval();
+            Some((group, interval, start + interval))
+        })
+        .collect();
+
+    loop {
+        match output.try_send(readings.clone()) {
+            Err(e) if e.is_disconnected() => return,
+            _ => {}
+        }
+
+        let Some(next) = schedule.iter().map(|(_, _, due)| *due).min() else {
+            return;
+        };
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+
+        let now = Instant::now();
+        let mut due_groups = Vec::new();
+        for (group, interval, due) in &mut schedule {
+            if *due <= now {
+                due_groups.push(*group);
+                *due = now + *interval;
+            }
+        }
+
+        sampler.update(&mut readings, &due_groups);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn missing_settings_are_the_defaults() {
+        let dir = TempDir::new();
+        let settings = PollSettings::load(&dir.join(POLL_SETTINGS_FILE_NAME)).unwrap();
+        assert_eq!(settings, PollSettings::default());
+    }
+
+    #[test]
+    fn settings_survive_a_round_trip() {
+        let dir = TempDir::new();
+        let path = dir.join("config").join(POLL_SETTINGS_FILE_NAME);
+        let settings = PollSettings {
+            rates: PollRates {
+                power: PollRate(100),
+                clocks: PollRate(200),
+                thermal: PollRate(5000),
+                memory: PollRate(500),
+            },
+            adaptive: false,
+        };
+
+        settings.save(&path).unwrap();
+        assert_eq!(PollSettings::load(&path), Ok(settings));
+    }
+
+    #[test]
+    fn settings_left_out_of_the_file_are_the_defaults() {
+        let dir = TempDir::new();
+        let path = dir.join(POLL_SETTINGS_FILE_NAME);
+        fs::write(&path, "[rates]\npower = 100\n").unwrap();
+
+        let settings = PollSettings::load(&path).unwrap();
+        assert_eq!(settings.rates.power, PollRate(100));
+        assert_eq!(settings.rates.thermal, PollRates::default().thermal);
+        assert!(settings.adaptive);
+
+        fs::write(&path, "adaptive = \"sometimes\"").unwrap();
+        assert!(PollSettings::load(&path).is_err());
+    }
+
+    #[test]
+    fn slows_down_only_when_adaptive_and_minimized() {
+        let mut settings = PollSettings {
+            rates: PollRates {
+                power: PollRate(100),
+                clocks: PollRate(300),
+                thermal: PollRate(1000),
+                memory: PollRate(5000),
+            },
+            adaptive: true,
+        };
+
+        assert_eq!(settings.active_rates(false), settings.rates);
+
+        // rates already slower than the background one stay as they are
+        let background = settings.active_rates(true);
+        assert_eq!(background.power, BACKGROUND_RATE);
+        assert_eq!(background.thermal, BACKGROUND_RATE);
+        assert_eq!(background.memory, PollRate(5000));
+
+        settings.adaptive = false;
+        assert_eq!(settings.active_rates(true), settings.rates);
+    }
+
+    #[test]
+    fn a_zero_rate_does_not_spin() {
+        assert_eq!(PollRate(0).interval(), Duration::from_millis(100));
+        assert_eq!(PollRate(2000).to_string(), "2 s");
+        assert_eq!(PollRate(300).to_string(), "300 ms");
+    }
+}
@@ -0,0 +1,221 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{EXIT_PERMISSION, EXIT_USAGE};
+
+// name of the unit that restores a profile at boot
+pub const SERVICE_NAME: &str = "nvidia-tweaker-restore.service";
+
+// where unit files go, relative to the root being installed into
+const UNIT_DIR: &str = "etc/systemd/system";
+
+// how many times the unit may start within START_LIMIT_SECS before
+// systemd gives up on it
+const START_LIMIT_BURST: u32 = 6;
+const START_LIMIT_SECS: u32 = 60;
+
+// everything needed to write the restore unit
+pub struct RestoreUnit {
+    // the binary to run, should be an absolute path
+    pub exe: PathBuf,
+    pub profiles_path: PathBuf,
+    pub profile: String,
+    // extra arguments picking which gpus to restore
+    pub device_args: Vec<String>,
+}
+
+impl RestoreUnit {
+    // the contents of the unit file
+    pub fn contents(&self) -> String {
+        let mut command = vec![
+            quote(&self.exe.to_string_lossy()),
+            String::from("--backend"),
+            String::from("nvml"),
+            String::from("restore"),
+            String::from("--profiles"),
+            quote(&self.profiles_path.to_string_lossy()),
+        ];
+        command.extend(self.device_args.iter().map(|arg| quote(arg)));
+        command.push(quote(&self.profile));
+
+        format!(
+            "# written by nvidia-tweaker, re-run `nvidia-tweaker service install` to change it
+[Unit]
+Description=Restore the NVIDIA GPU profile \"{profile}\"
+After=systemd-modules-load.service nvidia-persistenced.service
+StartLimitIntervalSec={START_LIMIT_SECS}
+StartLimitBurst={START_LIMIT_BURST}
+
+[Service]
+Type=oneshot
+ExecStart={command}
+# the driver can come up after us, so give it a few more tries. A missing
+# profile or bad arguments will not fix themselves
+Restart=on-failure
+RestartSec=5
+RestartPreventExitStatus={EXIT_USAGE} {EXIT_PERMISSION}
+
+[Install]
+WantedBy=multi-user.target
+",
+            profile = describe(&self.profile),
+            command = command.join(" "),
+        )
+    }
+}
+
+// path of the unit file inside the given root
+pub fn unit_path(root: &Path) -> PathBuf {
+    root.join(UNIT_DIR).join(SERVICE_NAME)
+}
+
+// write the unit file under root, returning where it was written
+pub fn install(root: &Path, unit: &RestoreUnit) -> io::Result<PathBuf> {
+    let path = unit_path(root);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(&path, unit.contents())?;
+    Ok(path)
+}
+
+// remove the unit file under root, returning false if it was not there
+pub fn uninstall(root: &Path) -> io::Result<bool> {
+    match fs::remove_file(unit_path(root)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// quote a single argument for a unit file. systemd expands % specifiers
+// and $ variables even inside quotes so those need doubling up too, and
+// control characters would end the line so they go in as C escapes
+fn quote(arg: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in arg.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '%' => quoted.push_str("%%"),
+            '$' => quoted.push_str("$$"),
+            c if c.is_control() => quoted.push_str(&control_escape(c)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+// text for a free form setting like Description=, which takes no quotes
+// or variables but still expands % specifiers
+fn describe(text: &str) -> String {
+    let mut described = String::new();
+    for c in text.chars() {
+        match c {
+            '%' => described.push_str("%%"),
+            c if c.is_control() => described.push_str(&control_escape(c)),
+            c => described.push(c),
+        }
+    }
+
+    described
+}
+
+fn control_escape(c: char) -> String {
+    // \x is a raw byte, the c1 controls need their utf-8 encoding
+    if c.is_ascii() {
+        format!("\\x{:02x}", c as u32)
+    } else {
+        format!("\\u{:04x}", c as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn unit(profile: &str) -> RestoreUnit {
+        RestoreUnit {
+            exe: PathBuf::from("/usr/bin/nvidia-tweaker"),
+            profiles_path: PathBuf::from("/etc/nvidia-tweaker/profiles.toml"),
+            profile: profile.to_string(),
+            device_args: vec![String::from("--gpu"), String::from("1")],
+        }
+    }
+
+    fn setting<'a>(contents: &'a str, key: &str) -> Vec<&'a str> {
+        contents
+            .lines()
+            .filter_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+            .collect()
+    }
+
+    #[test]
+    fn unit_runs_restore_with_the_profile() {
+        let contents = unit("gaming").contents();
+
+        assert_eq!(
+            setting(&contents, "ExecStart"),
+            [concat!(
+                "\"/usr/bin/nvidia-tweaker\" --backend nvml restore ",
+                "--profiles \"/etc/nvidia-tweaker/profiles.toml\" \"--gpu\" \"1\" \"gaming\""
+            )]
+        );
+        assert_eq!(
+            setting(&contents, "Description"),
+            ["Restore the NVIDIA GPU profile \"gaming\""]
+        );
+        assert_eq!(setting(&contents, "Type"), ["oneshot"]);
+        assert_eq!(setting(&contents, "StartLimitIntervalSec"), ["60"]);
+        assert_eq!(setting(&contents, "StartLimitBurst"), ["6"]);
+        assert_eq!(setting(&contents, "RestartPreventExitStatus"), ["2 4"]);
+        assert_eq!(setting(&contents, "WantedBy"), ["multi-user.target"]);
+    }
+
+    #[test]
+    fn quotes_specifiers_variables_and_quotes() {
+        assert_eq!(quote(r#"50% "$HOME" \n"#), r#""50%% \"$$HOME\" \\n""#);
+    }
+
+    #[test]
+    fn profile_names_can_not_add_lines() {
+        let contents = unit("evil\nExecStartPre=/bin/sh\r%h").contents();
+
+        assert!(setting(&contents, "ExecStartPre").is_empty());
+        assert_eq!(setting(&contents, "ExecStart").len(), 1);
+        assert_eq!(
+            setting(&contents, "Description"),
+            ["Restore the NVIDIA GPU profile \"evil\\x0aExecStartPre=/bin/sh\\x0d%%h\""]
+        );
+        assert!(
+            setting(&contents, "ExecStart")[0]
+                .ends_with(r#""evil\x0aExecStartPre=/bin/sh\x0d%%h""#)
+        );
+        assert_eq!(quote("\u{85}"), r#""\u0085""#);
+    }
+
+    #[test]
+    fn installs_and_uninstalls_under_the_root() {
+        let root = TempDir::new();
+        let path = install(root.path(), &unit("gaming")).unwrap();
+
+        assert_eq!(path, root.join("etc/systemd/system").join(SERVICE_NAME));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            unit("gaming").contents()
+        );
+
+        // installing again replaces the unit
+        install(root.path(), &unit("quiet")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), unit("quiet").contents());
+
+        assert!(uninstall(root.path()).unwrap());
+        assert!(!path.exists());
+        assert!(!uninstall(root.path()).unwrap());
+    }
+}
@@ -0,0 +1,282 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use nvml_wrapper::bitmasks::device::ThrottleReasons;
+use nvml_wrapper::enum_wrappers::device::Clock;
+use serde_json::{Value, json};
+
+use crate::backend::FanControlPolicy;
+use crate::error::GpuError;
+
+// a single reading, or the reason it could not be taken
+pub type Metric<T> = Result<T, GpuError>;
+
+// number of entries in the clock arrays, see gpu::CLOCKS_ARRAY for the order
+pub const CLOCK_COUNT: usize = 4;
+
+// names for the entries of the clock arrays
+pub const CLOCK_LABELS: [&str; CLOCK_COUNT] = ["Graphics", "SM", "Memory", "Video"];
+
+// where a clock sits in the clock arrays
+pub fn clock_index(clock: Clock) -> usize {
+    match clock {
+        Clock::Graphics => 0,
+        Clock::SM => 1,
+        Clock::Memory => 2,
+        Clock::Video => 3,
+    }
+}
+
+pub const BYTES_PER_MIB: u64 = 1024 * 1024;
+
+// one reason the clocks can be held back, with a key for logs and a label
+// for people
+pub struct ThrottleReason {
+    pub flag: ThrottleReasons,
+    pub key: &'static str,
+    pub label: &'static str,
+}
+
+pub const THROTTLE_REASONS: [ThrottleReason; 9] = [
+    ThrottleReason {
+        flag: ThrottleReasons::SW_POWER_CAP,
+        key: "sw_power_cap",
+        label: "Power cap",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::SW_THERMAL_SLOWDOWN,
+        key: "sw_thermal_slowdown",
+        label: "Thermal slowdown",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::HW_THERMAL_SLOWDOWN,
+        key: "hw_thermal_slowdown",
+        label: "HW thermal slowdown",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::HW_POWER_BRAKE_SLOWDOWN,
+        key: "hw_power_brake_slowdown",
+        label: "HW power brake",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::HW_SLOWDOWN,
+        key: "hw_slowdown",
+        label: "HW slowdown",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::SYNC_BOOST,
+        key: "sync_boost",
+        label: "Sync boost",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::APPLICATIONS_CLOCKS_SETTING,
+        key: "applications_clocks_setting",
+        label: "App clocks setting",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::DISPLAY_CLOCK_SETTING,
+        key: "display_clock_setting",
+        label: "Display clock setting",
+    },
+    ThrottleReason {
+        flag: ThrottleReasons::GPU_IDLE,
+        key: "idle",
+        label: "Idle",
+    },
+];
+
+// the known reasons set in a throttle bitmask
+pub fn active_throttle_reasons(
+    reasons: ThrottleReasons,
+) -> impl Iterator<Item = &'static ThrottleReason> {
+    THROTTLE_REASONS
+        .iter()
+        .filter(move |reason| reasons.contains(reason.flag))
+}
+
+// the readings of a single fan
+#[derive(Debug, Clone)]
+pub struct FanReading {
+    pub fan: u32,
+    // current and target speed in percent
+    pub speed_percent: Metric<u32>,
+    pub target_percent: Metric<u32>,
+    pub speed_rpm: Metric<u32>,
+    pub policy: Metric<FanControlPolicy>,
+}
+
+// one set of readings from a single gpu, kept as raw numbers so it can be
+// graphed, logged or compared without re-parsing anything
+#[derive(Debug, Clone)]
+pub struct TelemetrySample {
+    // when the sample was taken
+    pub timestamp: SystemTime,
+    pub device_index: u32,
+    // power draw and the enforced power limit in milliwatts
+    pub power_mw: Metric<u32>,
+    pub power_limit_mw: Metric<u32>,
+    // core temperature in °C
+    pub temperature_c: Metric<u32>,
+    // memory figures in bytes
+    pub mem_free_bytes: Metric<u64>,
+    pub mem_used_bytes: Metric<u64>,
+    pub mem_total_bytes: Metric<u64>,
+    // every fan on the card, or why they could not be counted
+    pub fans: Metric<Vec<FanReading>>,
+    // utilization in percent
+    pub gpu_utilization_percent: Metric<u32>,
+    pub mem_utilization_percent: Metric<u32>,
+    // current and max clocks in MHz
+    pub clocks_mhz: [Metric<u32>; CLOCK_COUNT],
+    pub max_clocks_mhz: [Metric<u32>; CLOCK_COUNT],
+    // why the current clocks are below the max ones
+    pub throttle_reasons: Metric<ThrottleReasons>,
+}
+
+impl TelemetrySample {
+    // an empty sample used before the first real reading comes in
+    pub fn empty(device_index: u32) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            device_index,
+            power_mw: Ok(0),
+            power_limit_mw: Ok(0),
+            temperature_c: Ok(0),
+            mem_free_bytes: Ok(0),
+            mem_used_bytes: Ok(0),
+            mem_total_bytes: Ok(0),
+            fans: Ok(Vec::new()),
+            gpu_utilization_percent: Ok(0),
+            mem_utilization_percent: Ok(0),
+            clocks_mhz: std::array::from_fn(|_| Ok(0)),
+            max_clocks_mhz: std::array::from_fn(|_| Ok(0)),
+            throttle_reasons: Ok(ThrottleReasons::empty()),
+        }
+    }
+
+    // current speed of one clock
+    pub fn clock_mhz(&self, clock: Clock) -> &Metric<u32> {
+        &self.clocks_mhz[clock_index(clock)]
+    }
+
+    // speed of the first fan in percent, for the places that only have room
+    // for a single value
+    pub fn fan_speed_percent(&self) -> Metric<u32> {
+        match &self.fans {
+            Ok(fans) => fans
+                .first()
+                .map_or(Err(GpuError::NotSupported), |fan| fan.speed_percent.clone()),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    // keys of the active throttle reasons, for the logs
+    pub fn throttle_keys(&self) -> Metric<Vec<&'static str>> {
+        self.throttle_reasons
+            .as_ref()
+            .map(|reasons| {
+                active_throttle_reasons(*reasons)
+                    .map(|reason| reason.key)
+                    .collect()
+            })
+            .map_err(Clone::clone)
+    }
+
+    // the sample as a flat JSON object. Units are part of the key names and
+    // readings that could not be taken are null
+    pub fn to_json(&self) -> Value {
+        fn value<T: Into<Value> + Copy>(metric: &Metric<T>) -> Value {
+            metric.as_ref().map_or(Value::Null, |value| (*value).into())
+        }
+
+        let clocks = |clocks: &[Metric<u32>; CLOCK_COUNT]| {
+            CLOCK_LABELS
+                .iter()
+                .zip(clocks)
+                .map(|(label, clock)| (label.to_lowercase(), value(clock)))
+                .collect::<serde_json::Map<_, _>>()
+        };
+
+        json!({
+            "timestamp_ms": unix_millis(self.timestamp),
+            "device_index": self.device_index,
+            "power_mw": value(&self.power_mw),
+            "power_limit_mw": value(&self.power_limit_mw),
+            "temperature_c": value(&self.temperature_c),
+            "mem_free_bytes": value(&self.mem_free_bytes),
+            "mem_used_bytes": value(&self.mem_used_bytes),
+            "mem_total_bytes": value(&self.mem_total_bytes),
+            "fan_speed_percent": value(&self.fan_speed_percent()),
+            "fans": self.fans.as_ref().ok().map(|fans| {
+                fans.iter()
+                    .map(|fan| {
+                        json!({
+                            "fan": fan.fan,
+                            "speed_percent": value(&fan.speed_percent),
+                            "target_percent": value(&fan.target_percent),
+                            "speed_rpm": value(&fan.speed_rpm),
+                            "policy": fan.policy.as_ref().ok().map(ToString::to_string),
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            }),
+            "gpu_utilization_percent": value(&self.gpu_utilization_percent),
+            "mem_utilization_percent": value(&self.mem_utilization_percent),
+            "clocks_mhz": clocks(&self.clocks_mhz),
+            "max_clocks_mhz": clocks(&self.max_clocks_mhz),
+            "throttle_reasons": self.throttle_keys().ok(),
+        })
+    }
+}
+
+// milliseconds since the unix epoch
+pub fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// convert milliwatts to watts
+pub fn mw_to_watts(power_mw: u32) -> f64 {
+    power_mw as f64 / 1000.0
+}
+
+// convert bytes to mebibytes
+pub fn bytes_to_mib(bytes: u64) -> u64 {
+    bytes / BYTES_PER_MIB
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_known_throttle_reasons() {
+        let reasons = ThrottleReasons::SW_THERMAL_SLOWDOWN
+            | ThrottleReasons::HW_POWER_BRAKE_SLOWDOWN
+            | ThrottleReasons::GPU_IDLE;
+        let labels: Vec<&str> = active_throttle_reasons(reasons)
+            .map(|reason| reason.label)
+            .collect();
+
+        assert_eq!(labels, ["Thermal slowdown", "HW power brake", "Idle"]);
+        assert_eq!(active_throttle_reasons(ThrottleReasons::empty()).count(), 0);
+    }
+
+    #[test]
+    fn json_lists_throttle_keys_and_nulls_failed_readings() {
+        let mut sample = TelemetrySample::empty(1);
+        sample.throttle_reasons = Ok(ThrottleReasons::SW_POWER_CAP | ThrottleReasons::SYNC_BOOST);
+        sample.temperature_c = Err(GpuError::NotSupported);
+
+        let json = sample.to_json();
+        assert_eq!(
+            json["throttle_reasons"],
+            json!(["sw_power_cap", "sync_boost"])
+        );
+        assert_eq!(json["temperature_c"], Value::Null);
+        assert_eq!(json["device_index"], 1);
+
+        sample.throttle_reasons = Err(GpuError::NotSupported);
+        assert_eq!(sample.to_json()["throttle_reasons"], Value::Null);
+    }
+}